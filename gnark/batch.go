@@ -0,0 +1,144 @@
+//go:build batch
+
+package main
+
+import (
+	"encoding/json"
+	"fmt"
+	"os"
+
+	"github.com/consensys/gnark/frontend"
+	"github.com/consensys/gnark/std/algebra/emulated/sw_bn254"
+	"github.com/consensys/gnark/std/algebra/emulated/sw_emulated"
+	"github.com/consensys/gnark/std/math/emulated/emparams"
+	"github.com/consensys/gnark/std/math/uints"
+)
+
+// Proofs of the same vkey, verified by verifyBatch in verify_batch.go.
+type Halo2BatchVerifierCircuit struct {
+	config      Halo2VerifierConfig
+	Instances   [][][]frontend.Variable `gnark:",public"`
+	Transcripts [][]frontend.Variable
+}
+
+func loadBatchProofData() ([]Halo2VerifierProofData, error) {
+	var res []Halo2VerifierProofData
+
+	data, err := os.ReadFile("halo2_verifier_batch_proof.json")
+	if err != nil {
+		return res, err
+	}
+
+	err = json.Unmarshal(data, &res)
+	if err != nil {
+		return res, err
+	}
+
+	return res, nil
+}
+
+// Same as newVerifierCircuits, for all proofs of halo2_verifier_batch_proof.json.
+func newBatchVerifierCircuits(config Halo2VerifierConfig) (frontend.Circuit, frontend.Circuit, error) {
+	allProofData, err := loadBatchProofData()
+	if err != nil {
+		return nil, nil, err
+	}
+
+	defalutInstances := make([][][]frontend.Variable, len(allProofData))
+	defalutTranscripts := make([][]frontend.Variable, len(allProofData))
+	instances := make([][][]frontend.Variable, len(allProofData))
+	transcripts := make([][]frontend.Variable, len(allProofData))
+	for k, proofData := range allProofData {
+		defalutInstances[k] = make([][]frontend.Variable, len(proofData.Instance))
+		instances[k] = make([][]frontend.Variable, len(proofData.Instance))
+		for i := range proofData.Instance {
+			defalutInstances[k][i] = make([]frontend.Variable, len(proofData.Instance[i]))
+			instances[k][i], err = parseVariables(proofData.Instance[i], "instance")
+			if err != nil {
+				return nil, nil, err
+			}
+		}
+		defalutTranscripts[k] = make([]frontend.Variable, len(proofData.Transcript))
+		transcripts[k], err = parseVariables(proofData.Transcript, "transcript")
+		if err != nil {
+			return nil, nil, err
+		}
+	}
+
+	halo2VerifierCircuit := Halo2BatchVerifierCircuit{
+		config:      config,
+		Instances:   defalutInstances,
+		Transcripts: defalutTranscripts,
+	}
+	witnessCircuit := Halo2BatchVerifierCircuit{
+		config:      config,
+		Instances:   instances,
+		Transcripts: transcripts,
+	}
+
+	return &halo2VerifierCircuit, &witnessCircuit, nil
+}
+
+func (circuit *Halo2BatchVerifierCircuit) Define(api frontend.API) error {
+	u64Api, err := uints.New[uints.U64](api)
+	if err != nil {
+		return err
+	}
+
+	u256Api := NewU256API(api, u64Api)
+
+	bn254Api, err := NewBN254API(api, u256Api)
+	if err != nil {
+		return err
+	}
+
+	halo2Api := NewHalo2VerifierAPI(circuit.config, api, u64Api, u256Api, bn254Api)
+
+	n := len(circuit.Transcripts)
+	allInstanceCommitments := make([][]*sw_emulated.AffinePoint[emparams.BN254Fp], n)
+	allCommitments := make([][]*sw_emulated.AffinePoint[emparams.BN254Fp], n)
+	allEvals := make([][]frontend.Variable, n)
+	allChallenges := make([][]frontend.Variable, n)
+
+	for i := 0; i < n; i++ {
+		transcript, err := halo2Api.proofToU256(circuit.Transcripts[i])
+		if err != nil {
+			return err
+		}
+
+		allInstanceCommitments[i] = make([]*sw_emulated.AffinePoint[emparams.BN254Fp], len(circuit.Instances[i]))
+		for j := range circuit.Instances[i] {
+			allInstanceCommitments[i][j], err = halo2Api.calcInstanceCommitment(circuit.Instances[i][j])
+			if err != nil {
+				return err
+			}
+		}
+
+		allChallenges[i], allCommitments[i], allEvals[i], err = halo2Api.getChallengesShPlonkCircuit(allInstanceCommitments[i], transcript)
+		if err != nil {
+			return err
+		}
+	}
+
+	batchP0, batchP1 := halo2Api.verifyBatch(allInstanceCommitments, allCommitments, allEvals, allChallenges)
+	g2Points := halo2Api.GetVerifyCircuitsG2Affine()
+
+	pairingApi, err := sw_bn254.NewPairing(api)
+	if err != nil {
+		return fmt.Errorf("NewPairing: %w", err)
+	}
+
+	// The pairs are not combined with random coefficients, so each proof is checked on its own.
+	for i := range batchP0 {
+		err = pairingApi.PairingCheck(
+			[]*sw_emulated.AffinePoint[emparams.BN254Fp]{batchP0[i], batchP1[i]},
+			[]*sw_bn254.G2Affine{&g2Points[0], &g2Points[1]},
+		)
+
+		if err != nil {
+			return fmt.Errorf("pair %d: %w", i, err)
+		}
+	}
+
+	return nil
+}
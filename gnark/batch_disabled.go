@@ -0,0 +1,14 @@
+//go:build !batch
+
+package main
+
+import (
+	"errors"
+
+	"github.com/consensys/gnark/frontend"
+)
+
+// verify_batch.go is only rendered by gnark_render_batch, build with -tags batch to use it.
+func newBatchVerifierCircuits(config Halo2VerifierConfig) (frontend.Circuit, frontend.Circuit, error) {
+	return nil, nil, errors.New("batch verifier is not built, rebuild with -tags batch")
+}
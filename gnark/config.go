@@ -1,8 +1,9 @@
 package main
 
 type Halo2VerifierProofData struct {
-	Instance   [][]string `json:"instance"`
-	Transcript []string   `json:"transcript"`
+	Instance       [][]string `json:"instance"`
+	Transcript     []string   `json:"transcript"`
+	TranscriptHash string     `json:"transcript_hash"`
 }
 
 type Halo2VerifierConfig struct {
@@ -14,4 +15,5 @@ type Halo2VerifierConfig struct {
 	NbLookups              uint32     `json:"nb_lookups"`
 	NbPermutationGroups    uint32     `json:"nb_permutation_groups"`
 	NbEvals                uint32     `json:"nb_evals"`
+	TranscriptHash         string     `json:"transcript_hash"`
 }
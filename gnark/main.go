@@ -49,13 +49,63 @@ func loadHalo2VerifierConfig() (Halo2VerifierConfig, error) {
 	return res, nil
 }
 
+func parseVariables(values []string, name string) ([]frontend.Variable, error) {
+	res := make([]frontend.Variable, len(values))
+	for i := range values {
+		v, succeed := big.NewInt(0).SetString(values[i], 10)
+		if !succeed {
+			return nil, fmt.Errorf("invalid %s %s", name, values[i])
+		}
+		res[i] = v
+	}
+
+	return res, nil
+}
+
+// Returns the circuit to compile and the circuit with the witness of halo2_verifier_proof.json.
+func newVerifierCircuits(config Halo2VerifierConfig) (frontend.Circuit, frontend.Circuit, error) {
+	proofData, err := loadProofData()
+	if err != nil {
+		return nil, nil, err
+	}
+
+	defalutInstance := make([][]frontend.Variable, len(proofData.Instance))
+	instance := make([][]frontend.Variable, len(proofData.Instance))
+	for i := range proofData.Instance {
+		defalutInstance[i] = make([]frontend.Variable, len(proofData.Instance[i]))
+		instance[i], err = parseVariables(proofData.Instance[i], "instance")
+		if err != nil {
+			return nil, nil, err
+		}
+	}
+	transcript, err := parseVariables(proofData.Transcript, "transcript")
+	if err != nil {
+		return nil, nil, err
+	}
+
+	halo2VerifierCircuit := Halo2VerifierCircuit{
+		config:     config,
+		Instance:   defalutInstance,
+		Transcript: make([]frontend.Variable, len(proofData.Transcript)),
+	}
+	witnessCircuit := Halo2VerifierCircuit{
+		config:     config,
+		Transcript: transcript,
+		Instance:   instance,
+	}
+
+	return &halo2VerifierCircuit, &witnessCircuit, nil
+}
+
 func main() {
 	var backendIDString, curveIDString string
 	var isSetup bool
+	var isBatch bool
 
 	flag.StringVar(&backendIDString, "backendID", "GROTH16", "Specify the backend ID (e.g., PLONK, GROTH16)")
 	flag.StringVar(&curveIDString, "curveID", "BN254", "Specify the curve ID (e.g., BN254, BLS12_381)")
 	flag.BoolVar(&isSetup, "setup", true, "Whether to setup to generate pk,vk")
+	flag.BoolVar(&isBatch, "batch", false, "Whether to verify halo2_verifier_batch_proof.json with verifyBatch")
 	flag.Parse()
 
 	backendID, err := parseBackendID(backendIDString)
@@ -68,24 +118,20 @@ func main() {
 		log.Fatalf("Invalid curveID: %v", err)
 	}
 
-	proofData, err := loadProofData()
-	if err != nil {
-		panic(err)
-	}
-
 	config, err := loadHalo2VerifierConfig()
 	if err != nil {
 		panic(err)
 	}
 
-	defalutInstance := make([][]frontend.Variable, len(proofData.Instance))
-	for i := range proofData.Instance {
-		defalutInstance[i] = make([]frontend.Variable, len(proofData.Instance[i]))
+	// 0. load the proofs, they are filled into witnessCircuit
+	var halo2VerifierCircuit, witnessCircuit frontend.Circuit
+	if isBatch {
+		halo2VerifierCircuit, witnessCircuit, err = newBatchVerifierCircuits(config)
+	} else {
+		halo2VerifierCircuit, witnessCircuit, err = newVerifierCircuits(config)
 	}
-	halo2VerifierCircuit := Halo2VerifierCircuit{
-		config:     config,
-		Instance:   defalutInstance,
-		Transcript: make([]frontend.Variable, len(proofData.Transcript)),
+	if err != nil {
+		panic(err)
 	}
 
 	var (
@@ -94,7 +140,7 @@ func main() {
 
 	// 1. compile
 	log.Println("[Start] Compile")
-	ccs, err := Compile(&halo2VerifierCircuit, curveID, backendID, []frontend.CompileOption{})
+	ccs, err := Compile(halo2VerifierCircuit, curveID, backendID, []frontend.CompileOption{})
 	if err != nil {
 		panic(err)
 	}
@@ -125,35 +171,8 @@ func main() {
 		verifierOpts = append(verifierOpts, backend.WithVerifierHashToFieldFunction(sha256.New()))
 	}
 
-	succeed := true
-
-	// 3a. Fill witness and instance
-	instance := make([][]frontend.Variable, len(proofData.Instance))
-	for i := range proofData.Instance {
-		instance[i] = make([]frontend.Variable, len(proofData.Instance[i]))
-		for j := range proofData.Instance[i] {
-			instance[i][j], succeed = big.NewInt(0).SetString(proofData.Instance[i][j], 10)
-			if !succeed {
-				_ = fmt.Errorf("invalid instance", proofData.Instance[i][j])
-			}
-		}
-	}
-	transcript := make([]frontend.Variable, len(proofData.Transcript))
-	for i := range proofData.Transcript {
-		transcript[i], succeed = big.NewInt(0).SetString(proofData.Transcript[i], 10)
-		if !succeed {
-			_ = fmt.Errorf("invalid transcript", proofData.Transcript[i])
-		}
-	}
-
-	witnessCircuit := Halo2VerifierCircuit{
-		config:     config,
-		Transcript: transcript,
-		Instance:   instance,
-	}
-
-	// 3b. Generate witness
-	witness, err := frontend.NewWitness(&witnessCircuit, curveID.ScalarField())
+	// 3. Generate witness
+	witness, err := frontend.NewWitness(witnessCircuit, curveID.ScalarField())
 	if err != nil {
 		log.Fatalln(err)
 	}
@@ -185,7 +204,7 @@ func main() {
 	if err != nil {
 		panic(err)
 	}
-	s, err := frontend.NewSchema(&witnessCircuit)
+	s, err := frontend.NewSchema(witnessCircuit)
 	if err != nil {
 		panic(err)
 	}
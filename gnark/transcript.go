@@ -6,27 +6,41 @@ import (
 
 	"github.com/consensys/gnark/frontend"
 	"github.com/consensys/gnark/std/algebra/emulated/sw_emulated"
+	"github.com/consensys/gnark/std/hash"
 	"github.com/consensys/gnark/std/hash/sha2"
+	"github.com/consensys/gnark/std/hash/sha3"
 	"github.com/consensys/gnark/std/math/emulated/emparams"
 	"github.com/consensys/gnark/std/math/uints"
 )
 
+func newTranscriptHasher(api frontend.API, transcriptHash string) (hash.BinaryHasher, error) {
+	switch transcriptHash {
+	case "", "sha256":
+		return sha2.New(api)
+	case "keccak":
+		return sha3.NewLegacyKeccak256(api)
+	default:
+		return nil, fmt.Errorf("unsupported transcript hash %s", transcriptHash)
+	}
+}
+
 func squeezeChallenge(
 	api frontend.API,
+	transcriptHash string,
 	absorbing *[]uints.U8,
 	challenges *[]frontend.Variable,
 ) error {
 	*absorbing = append(*absorbing, uints.NewU8(0))
 
-	sha2Api, err := sha2.New(api)
+	hasher, err := newTranscriptHasher(api, transcriptHash)
 	if err != nil {
 		return err
 	}
 
-	sha2Api.Write(*absorbing)
-	res := sha2Api.Sum()
+	hasher.Write(*absorbing)
+	res := hasher.Sum()
 	if len(res) != 32 {
-		panic("sha2 returned value not 32 bytes")
+		panic("transcript hash returned value not 32 bytes")
 	}
 
 	// Pack bytes in BE
@@ -123,7 +137,7 @@ func (halo2Api *Halo2VerifierAPI) getChallengesShPlonkCircuit(
 		}
 
 		for j := uint32(0); j < opSeq[i][1]; j++ {
-			err := squeezeChallenge(halo2Api.api, &absorbing, &challenges)
+			err := squeezeChallenge(halo2Api.api, halo2Api.config.TranscriptHash, &absorbing, &challenges)
 			if err != nil {
 				return challenges, commitments, evals, err
 			}
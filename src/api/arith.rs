@@ -4,6 +4,7 @@ use halo2_proofs::arithmetic::Field;
 use std::ops::Add;
 use std::ops::Div;
 use std::ops::Mul;
+use std::ops::Neg;
 use std::ops::Sub;
 use std::rc::Rc;
 
@@ -14,6 +15,7 @@ pub enum AstScalar<C: CurveAffine> {
     FromChallenge(Rc<AstTranscript<C>>),
     Add(Rc<Self>, Rc<Self>),
     Sub(Rc<Self>, Rc<Self>),
+    Neg(Rc<Self>),
     Mul(Rc<Self>, Rc<Self>, bool), // bool if for challenge group optimization
     Div(Rc<Self>, Rc<Self>),
     Pow(Rc<Self>, u32),
@@ -41,7 +43,7 @@ pub enum AstPoint<C: CurveAffine> {
     FromTranscript(Rc<AstTranscript<C>>),
     FromInstance(usize, usize),
     MultiExp(Vec<(Rc<Self>, Rc<AstScalar<C>>)>, usize), // msm group: usize
-    CheckPoint(String, Rc<Self>), // for debug
+    CheckPoint(String, Rc<Self>),                       // for debug
 }
 
 #[repr(transparent)]
@@ -152,6 +154,7 @@ impl<C: CurveAffine> Sub<AstScalarRc<C>> for AstScalarRc<C> {
             (AstScalar::FromConst(a), AstScalar::FromConst(b)) => {
                 sconst!(*a - *b)
             }
+            (AstScalar::FromConst(a), _) if a == &C::ScalarExt::zero() => -rhs,
             (_, AstScalar::FromConst(b)) => {
                 if b == &C::ScalarExt::zero() {
                     self
@@ -164,6 +167,19 @@ impl<C: CurveAffine> Sub<AstScalarRc<C>> for AstScalarRc<C> {
     }
 }
 
+impl<C: CurveAffine> Neg for AstScalarRc<C> {
+    type Output = AstScalarRc<C>;
+
+    fn neg(self) -> Self::Output {
+        let a: &AstScalar<C> = self.0.as_ref();
+        match a {
+            AstScalar::FromConst(a) => sconst!(-*a),
+            AstScalar::Neg(a) => AstScalarRc(a.clone()),
+            _ => AstScalarRc(Rc::new(AstScalar::Neg(self.0))),
+        }
+    }
+}
+
 impl<C: CurveAffine> Div<AstScalarRc<C>> for AstScalarRc<C> {
     type Output = AstScalarRc<C>;
 
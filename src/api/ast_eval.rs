@@ -1,14 +1,25 @@
 use crate::api::arith::AstPoint;
+use crate::api::arith::AstPointRc;
 use crate::api::arith::AstScalar;
+use crate::api::halo2::verifier::VerifierParams;
 use crate::api::transcript::AstTranscript;
+use crate::circuits::utils::field_hex;
 use halo2_proofs::arithmetic::CurveAffine;
+use halo2_proofs::arithmetic::Field;
+use halo2ecc_s::utils::field_to_bn;
+use serde::Deserialize;
+use serde::Serialize;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::rc::Rc;
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+// Bump it together with any change of EvalOps or EvalPos, so stale serialized contexts are rejected.
+pub const EVAL_CONTEXT_FORMAT_VERSION: u32 = 2;
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum EvalPos {
     Constant(usize),
     Empty,
@@ -32,7 +43,7 @@ impl EvalPos {
     }
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum EvalOps {
     TranscriptReadScalar(usize, EvalPos),
     TranscriptReadPoint(usize, EvalPos),
@@ -42,8 +53,10 @@ pub enum EvalOps {
 
     ScalarAdd(EvalPos, EvalPos),
     ScalarSub(EvalPos, EvalPos),
+    ScalarNeg(EvalPos),
     ScalarMul(EvalPos, EvalPos, bool),
     ScalarDiv(EvalPos, EvalPos),
+    ScalarInv(EvalPos),
     ScalarPow(EvalPos, u32),
 
     MSM(Vec<(EvalPos, EvalPos)>, EvalPos), // add last MSMSlice for dependence
@@ -52,7 +65,50 @@ pub enum EvalOps {
     CheckPoint(String, EvalPos), // for debug purpose
 }
 
+// Kind of EvalOps without payload.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum OpType {
+    TranscriptReadScalar,
+    TranscriptReadPoint,
+    TranscriptCommonScalar,
+    TranscriptCommonPoint,
+    TranscriptSqueeze,
+
+    ScalarAdd,
+    ScalarSub,
+    ScalarNeg,
+    ScalarMul,
+    ScalarDiv,
+    ScalarInv,
+    ScalarPow,
+
+    MSM,
+    MSMSlice,
+
+    CheckPoint,
+}
+
 impl EvalOps {
+    pub fn op_type(&self) -> OpType {
+        match self {
+            EvalOps::TranscriptReadScalar(..) => OpType::TranscriptReadScalar,
+            EvalOps::TranscriptReadPoint(..) => OpType::TranscriptReadPoint,
+            EvalOps::TranscriptCommonScalar(..) => OpType::TranscriptCommonScalar,
+            EvalOps::TranscriptCommonPoint(..) => OpType::TranscriptCommonPoint,
+            EvalOps::TranscriptSqueeze(..) => OpType::TranscriptSqueeze,
+            EvalOps::ScalarAdd(..) => OpType::ScalarAdd,
+            EvalOps::ScalarSub(..) => OpType::ScalarSub,
+            EvalOps::ScalarNeg(..) => OpType::ScalarNeg,
+            EvalOps::ScalarMul(..) => OpType::ScalarMul,
+            EvalOps::ScalarDiv(..) => OpType::ScalarDiv,
+            EvalOps::ScalarInv(..) => OpType::ScalarInv,
+            EvalOps::ScalarPow(..) => OpType::ScalarPow,
+            EvalOps::MSM(..) => OpType::MSM,
+            EvalOps::MSMSlice(..) => OpType::MSMSlice,
+            EvalOps::CheckPoint(..) => OpType::CheckPoint,
+        }
+    }
+
     pub fn deps(&self) -> Vec<&EvalPos> {
         match self {
             EvalOps::TranscriptReadScalar(_, a) => vec![a],
@@ -62,8 +118,10 @@ impl EvalOps {
             EvalOps::TranscriptSqueeze(_, a) => vec![a],
             EvalOps::ScalarAdd(a, b) => vec![a, b],
             EvalOps::ScalarSub(a, b) => vec![a, b],
+            EvalOps::ScalarNeg(a) => vec![a],
             EvalOps::ScalarMul(a, b, _) => vec![a, b],
             EvalOps::ScalarDiv(a, b) => vec![a, b],
+            EvalOps::ScalarInv(a) => vec![a],
             EvalOps::ScalarPow(a, _) => vec![a],
             EvalOps::MSMSlice((a, b), last, _) => {
                 let mut deps = last.as_ref().map(|x| vec![x]).unwrap_or(vec![]);
@@ -83,54 +141,149 @@ impl EvalOps {
         }
     }
 
-    pub fn map(&self, reverse_order: &Vec<usize>) -> Self {
+    pub fn name(&self) -> &'static str {
         match self {
-            EvalOps::TranscriptReadScalar(i, a) => {
-                EvalOps::TranscriptReadScalar(*i, a.map(reverse_order))
+            EvalOps::TranscriptReadScalar(..) => "TranscriptReadScalar",
+            EvalOps::TranscriptReadPoint(..) => "TranscriptReadPoint",
+            EvalOps::TranscriptCommonScalar(..) => "TranscriptCommonScalar",
+            EvalOps::TranscriptCommonPoint(..) => "TranscriptCommonPoint",
+            EvalOps::TranscriptSqueeze(..) => "TranscriptSqueeze",
+            EvalOps::ScalarAdd(..) => "ScalarAdd",
+            EvalOps::ScalarSub(..) => "ScalarSub",
+            EvalOps::ScalarNeg(..) => "ScalarNeg",
+            EvalOps::ScalarMul(..) => "ScalarMul",
+            EvalOps::ScalarDiv(..) => "ScalarDiv",
+            EvalOps::ScalarInv(..) => "ScalarInv",
+            EvalOps::ScalarPow(..) => "ScalarPow",
+            EvalOps::MSM(..) => "MSM",
+            EvalOps::MSMSlice(..) => "MSMSlice",
+            EvalOps::CheckPoint(..) => "CheckPoint",
+        }
+    }
+
+    // deps with a flag telling whether the dep is a point
+    pub fn typed_deps(&self) -> Vec<(&EvalPos, bool)> {
+        match self {
+            EvalOps::TranscriptCommonPoint(_, t, p) => vec![(t, false), (p, true)],
+            EvalOps::MSMSlice((p, s), last, _) => {
+                let mut deps = last.as_ref().map(|x| vec![(x, true)]).unwrap_or(vec![]);
+                deps.push((p, true));
+                deps.push((s, false));
+                deps
             }
-            EvalOps::TranscriptReadPoint(i, a) => {
-                EvalOps::TranscriptReadPoint(*i, a.map(reverse_order))
+            EvalOps::MSM(psl, last) => {
+                let mut deps = vec![(last, true)];
+                for (p, s) in psl {
+                    deps.push((p, true));
+                    deps.push((s, false));
+                }
+                deps
             }
+            _ => self.deps().into_iter().map(|x| (x, false)).collect(),
+        }
+    }
+
+    pub fn map(&self, reverse_order: &Vec<usize>) -> Self {
+        self.map_with(&|x| x.map(reverse_order))
+    }
+
+    pub fn map_with(&self, f: &impl Fn(&EvalPos) -> EvalPos) -> Self {
+        self.map_with_typed(&|x, _| f(x))
+    }
+
+    // Same as map_with, f also receives whether the position is a point
+    pub fn map_with_typed(&self, f: &impl Fn(&EvalPos, bool) -> EvalPos) -> Self {
+        match self {
+            EvalOps::TranscriptReadScalar(i, a) => EvalOps::TranscriptReadScalar(*i, f(a, false)),
+            EvalOps::TranscriptReadPoint(i, a) => EvalOps::TranscriptReadPoint(*i, f(a, false)),
             EvalOps::TranscriptCommonScalar(i, a, b) => {
-                EvalOps::TranscriptCommonScalar(*i, a.map(reverse_order), b.map(reverse_order))
+                EvalOps::TranscriptCommonScalar(*i, f(a, false), f(b, false))
             }
             EvalOps::TranscriptCommonPoint(i, a, b) => {
-                EvalOps::TranscriptCommonPoint(*i, a.map(reverse_order), b.map(reverse_order))
-            }
-            EvalOps::TranscriptSqueeze(i, a) => {
-                EvalOps::TranscriptSqueeze(*i, a.map(reverse_order))
-            }
-            EvalOps::ScalarAdd(a, b) => {
-                EvalOps::ScalarAdd(a.map(reverse_order), b.map(reverse_order))
-            }
-            EvalOps::ScalarSub(a, b) => {
-                EvalOps::ScalarSub(a.map(reverse_order), b.map(reverse_order))
+                EvalOps::TranscriptCommonPoint(*i, f(a, false), f(b, true))
             }
-            EvalOps::ScalarMul(a, b, c) => {
-                EvalOps::ScalarMul(a.map(reverse_order), b.map(reverse_order), *c)
-            }
-            EvalOps::ScalarDiv(a, b) => {
-                EvalOps::ScalarDiv(a.map(reverse_order), b.map(reverse_order))
-            }
-            EvalOps::ScalarPow(a, n) => EvalOps::ScalarPow(a.map(reverse_order), *n),
+            EvalOps::TranscriptSqueeze(i, a) => EvalOps::TranscriptSqueeze(*i, f(a, false)),
+            EvalOps::ScalarAdd(a, b) => EvalOps::ScalarAdd(f(a, false), f(b, false)),
+            EvalOps::ScalarSub(a, b) => EvalOps::ScalarSub(f(a, false), f(b, false)),
+            EvalOps::ScalarNeg(a) => EvalOps::ScalarNeg(f(a, false)),
+            EvalOps::ScalarMul(a, b, c) => EvalOps::ScalarMul(f(a, false), f(b, false), *c),
+            EvalOps::ScalarDiv(a, b) => EvalOps::ScalarDiv(f(a, false), f(b, false)),
+            EvalOps::ScalarInv(a) => EvalOps::ScalarInv(f(a, false)),
+            EvalOps::ScalarPow(a, n) => EvalOps::ScalarPow(f(a, false), *n),
             EvalOps::MSM(psl, last_msm_slice) => EvalOps::MSM(
-                {
-                    psl.iter()
-                        .map(|(p, s)| (p.map(reverse_order), s.map(reverse_order)))
-                        .collect()
-                },
-                last_msm_slice.map(reverse_order),
+                { psl.iter().map(|(p, s)| (f(p, true), f(s, false))).collect() },
+                f(last_msm_slice, true),
             ),
             EvalOps::MSMSlice((a, b), last, msm_group) => EvalOps::MSMSlice(
-                (a.map(reverse_order), b.map(reverse_order)),
-                last.as_ref().map(|x| x.map(reverse_order)),
+                (f(a, true), f(b, false)),
+                last.as_ref().map(|x| f(x, true)),
                 *msm_group,
             ),
-            EvalOps::CheckPoint(n, a) => EvalOps::CheckPoint(n.clone(), a.map(reverse_order)),
+            EvalOps::CheckPoint(n, a) => EvalOps::CheckPoint(n.clone(), f(a, false)),
         }
     }
 }
 
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvalStats {
+    pub msm_slice_count: usize,
+    pub scalar_mul_count: usize,
+    pub scalar_div_count: usize,
+    pub scalar_add_sub_count: usize,
+    pub transcript_read_count: usize,
+    pub transcript_squeeze_count: usize,
+    pub const_scalar_count: usize,
+    pub const_point_count: usize,
+    pub total_op_count: usize,
+}
+
+// Ops are compared by index, constants are compared by value.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EvalContextDiff {
+    pub mismatched_ops: Vec<(usize, EvalOps, EvalOps)>,
+    pub extra_a: Vec<(usize, EvalOps)>,
+    pub extra_b: Vec<(usize, EvalOps)>,
+}
+
+impl EvalContextDiff {
+    pub fn is_empty(&self) -> bool {
+        self.mismatched_ops.is_empty() && self.extra_a.is_empty() && self.extra_b.is_empty()
+    }
+
+    // The first op index where two contexts diverge.
+    pub fn first_divergence(&self) -> Option<usize> {
+        self.mismatched_ops
+            .first()
+            .map(|x| x.0)
+            .or(self.extra_a.first().map(|x| x.0))
+            .or(self.extra_b.first().map(|x| x.0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalContextDeserializeError {
+    VersionMismatch { expected: u32, found: u32 },
+    InvalidFormat(String),
+}
+
+// Transcripts and msm groups are identified by index, EvalContext::merge rejects
+// a context that reuses an index of the other one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvalContextMergeError {
+    TranscriptIndexCollision(usize),
+    MsmGroupCollision(usize),
+}
+
+// Translation caches are not serialized, only the translated ops and constants.
+#[derive(Serialize, Deserialize)]
+struct SerializedEvalContext {
+    format_version: u32,
+    ops: Vec<EvalOps>,
+    const_points: Vec<Vec<u8>>,
+    const_scalars: Vec<String>,
+    finals: Vec<usize>,
+}
+
 #[derive(Clone, Default)]
 pub struct EvalContext<C: CurveAffine> {
     pub ops: Vec<EvalOps>,
@@ -148,9 +301,633 @@ impl<C: CurveAffine> EvalContext<C> {
     pub fn translate(ast: &[Rc<AstPoint<C>>]) -> Self {
         let mut c = Self::default();
         c.full_translate_ast_point(ast);
+        if !cfg!(feature = "debug_aggregation") {
+            c.prune_checkpoints();
+        }
+        if cfg!(debug_assertions) {
+            c.assert_valid();
+        }
         c
     }
 
+    // Translate the pairing points built on params, e.g. by verify_single_proof_with_params.
+    // finals are [w_x, w_g, advice commitments of params].
+    pub fn from_verifier_params(
+        params: &VerifierParams<C>,
+        w_x: AstPointRc<C>,
+        w_g: AstPointRc<C>,
+    ) -> Self {
+        let mut targets = vec![w_x.0, w_g.0];
+        targets.extend(params.advice_commitments.iter().map(|p| p.0.clone()));
+        Self::translate(&targets[..])
+    }
+
+    // Translate more targets into an already translated context and append them to finals,
+    // returns their final op indices. Ops already in the context are reused and new ops are
+    // appended after their deps, so the existing ops keep their indices and order.
+    pub fn extend_finals(&mut self, new_targets: &[Rc<AstPoint<C>>]) -> Vec<usize> {
+        self.ops_cache = self
+            .ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| (op.clone(), i))
+            .collect();
+
+        for ast in new_targets {
+            let pos = self.translate_ast_point(ast);
+            self.finals.push(pos.to_ops_index_unsafe());
+        }
+
+        self.ops_cache.clear();
+        self.deps.clear();
+        self.reverse_deps.clear();
+        self.transcript_cache.clear();
+
+        if !cfg!(feature = "debug_aggregation") {
+            self.prune_checkpoints();
+        }
+        if cfg!(debug_assertions) {
+            self.assert_valid();
+        }
+
+        self.finals[self.finals.len() - new_targets.len()..].to_vec()
+    }
+
+    pub fn to_json(&self) -> String {
+        let serialized = SerializedEvalContext {
+            format_version: EVAL_CONTEXT_FORMAT_VERSION,
+            ops: self.ops.clone(),
+            const_points: self
+                .const_points
+                .iter()
+                .map(|p| p.to_bytes().as_ref().to_vec())
+                .collect(),
+            const_scalars: self.const_scalars.iter().map(field_hex::to_hex).collect(),
+            finals: self.finals.clone(),
+        };
+        serde_json::to_string(&serialized).unwrap()
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, EvalContextDeserializeError> {
+        let invalid = |e: String| EvalContextDeserializeError::InvalidFormat(e);
+
+        // Check the version before parsing ops, which may fail on a stale format.
+        let value: serde_json::Value =
+            serde_json::from_str(s).map_err(|e| invalid(e.to_string()))?;
+        let found = value
+            .get("format_version")
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| invalid("missing format_version".to_owned()))?
+            as u32;
+        if found != EVAL_CONTEXT_FORMAT_VERSION {
+            return Err(EvalContextDeserializeError::VersionMismatch {
+                expected: EVAL_CONTEXT_FORMAT_VERSION,
+                found,
+            });
+        }
+
+        let serialized: SerializedEvalContext =
+            serde_json::from_value(value).map_err(|e| invalid(e.to_string()))?;
+
+        let const_points = serialized
+            .const_points
+            .iter()
+            .map(|bytes| {
+                let mut repr = C::Repr::default();
+                if repr.as_ref().len() != bytes.len() {
+                    return None;
+                }
+                repr.as_mut().copy_from_slice(bytes);
+                Option::from(C::from_bytes(&repr))
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("invalid const point".to_owned()))?;
+        let const_scalars = serialized
+            .const_scalars
+            .iter()
+            .map(|x| field_hex::from_hex(x))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| invalid("invalid const scalar".to_owned()))?;
+
+        let c = Self {
+            ops: serialized.ops,
+            const_points,
+            const_scalars,
+            finals: serialized.finals,
+            ..Default::default()
+        };
+        // The blob is untrusted, a dangling or forward reference would be read out of bounds later.
+        c.validate().map_err(invalid)?;
+        Ok(c)
+    }
+
+    // Check ops are topologically sorted, deduplicated and only reference existing positions.
+    pub fn validate(&self) -> Result<(), String> {
+        for (i, op) in self.ops.iter().enumerate() {
+            for (dep, is_point) in op.typed_deps() {
+                match dep {
+                    EvalPos::Ops(j) if *j >= i => {
+                        return Err(format!(
+                            "op {} ({}) depends on later op {}",
+                            i,
+                            op.name(),
+                            j
+                        ))
+                    }
+                    EvalPos::Constant(j) if is_point && *j >= self.const_points.len() => {
+                        return Err(format!(
+                            "op {} ({}) references const point {} out of {}",
+                            i,
+                            op.name(),
+                            j,
+                            self.const_points.len()
+                        ))
+                    }
+                    EvalPos::Constant(j) if !is_point && *j >= self.const_scalars.len() => {
+                        return Err(format!(
+                            "op {} ({}) references const scalar {} out of {}",
+                            i,
+                            op.name(),
+                            j,
+                            self.const_scalars.len()
+                        ))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        for f in self.finals.iter() {
+            if *f >= self.ops.len() {
+                return Err(format!("final {} out of {} ops", f, self.ops.len()));
+            }
+        }
+
+        let mut seen = HashMap::new();
+        for (i, op) in self.ops.iter().enumerate() {
+            if let Some(j) = seen.insert(op, i) {
+                return Err(format!("op {} ({}) duplicates op {}", i, op.name(), j));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn assert_valid(&self) {
+        if let Err(e) = self.validate() {
+            panic!("{}", e);
+        }
+    }
+
+    pub fn stats(&self) -> EvalStats {
+        let mut stats = EvalStats {
+            const_scalar_count: self.const_scalars.len(),
+            const_point_count: self.const_points.len(),
+            total_op_count: self.ops.len(),
+            ..Default::default()
+        };
+
+        for op in self.ops.iter() {
+            match op {
+                EvalOps::TranscriptReadScalar(..) | EvalOps::TranscriptReadPoint(..) => {
+                    stats.transcript_read_count += 1
+                }
+                EvalOps::TranscriptSqueeze(..) => stats.transcript_squeeze_count += 1,
+                EvalOps::ScalarAdd(..) | EvalOps::ScalarSub(..) | EvalOps::ScalarNeg(..) => {
+                    stats.scalar_add_sub_count += 1
+                }
+                EvalOps::ScalarMul(..) => stats.scalar_mul_count += 1,
+                EvalOps::ScalarDiv(..) | EvalOps::ScalarInv(..) => stats.scalar_div_count += 1,
+                EvalOps::MSMSlice(..) => stats.msm_slice_count += 1,
+                _ => {}
+            }
+        }
+
+        stats
+    }
+
+    // Length of the longest dependency chain of ops, a source op has depth 1.
+    // Relies on ops being topologically sorted, see assert_valid.
+    pub fn topological_depth(&self) -> usize {
+        let mut depths = vec![0; self.ops.len()];
+        for (i, op) in self.ops.iter().enumerate() {
+            depths[i] = 1 + op
+                .deps()
+                .into_iter()
+                .filter_map(|dep| match dep {
+                    EvalPos::Ops(j) => Some(depths[*j]),
+                    _ => None,
+                })
+                .max()
+                .unwrap_or(0);
+        }
+        depths.into_iter().max().unwrap_or(0)
+    }
+
+    // Indices of ops grouped by their type, in ascending order.
+    pub fn ops_by_type(&self) -> HashMap<OpType, Vec<usize>> {
+        let mut res: HashMap<OpType, Vec<usize>> = HashMap::new();
+        for (i, op) in self.ops.iter().enumerate() {
+            res.entry(op.op_type()).or_default().push(i);
+        }
+        res
+    }
+
+    // Combine two translated contexts, ops of b that are not in a are appended after ops of a.
+    // Constants and ops are deduplicated and finals of both are kept in order.
+    // b must use other transcript indices and msm groups than a, e.g. build it at other proof
+    // indices with another msm offset, see verify_aggregation_proofs_with_offset.
+    // A checkpoint of b whose tag is taken by another value in a gets a " (merged)" suffix.
+    pub fn merge(a: Self, b: Self) -> Result<Self, EvalContextMergeError> {
+        if let Some(i) = a
+            .transcript_indices()
+            .intersection(&b.transcript_indices())
+            .next()
+        {
+            return Err(EvalContextMergeError::TranscriptIndexCollision(*i));
+        }
+        if let Some(i) = a.msm_groups().intersection(&b.msm_groups()).next() {
+            return Err(EvalContextMergeError::MsmGroupCollision(*i));
+        }
+
+        let tags = a
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                EvalOps::CheckPoint(tag, _) => Some(tag.clone()),
+                _ => None,
+            })
+            .collect::<HashSet<_>>();
+
+        let mut c = a;
+        let mut ops_cache: HashMap<EvalOps, usize> = c
+            .ops
+            .iter()
+            .enumerate()
+            .map(|(i, op)| (op.clone(), i))
+            .collect();
+
+        let scalar_map = b
+            .const_scalars
+            .iter()
+            .map(|x| c.push_const_scalar(*x))
+            .collect::<Vec<_>>();
+        let point_map = b
+            .const_points
+            .iter()
+            .map(|x| c.push_const_point(*x))
+            .collect::<Vec<_>>();
+
+        // op index of b -> op index of c
+        let mut translate: Vec<usize> = vec![];
+        for op in b.ops.iter() {
+            let op = op.map_with_typed(&|x, is_point| match x {
+                EvalPos::Ops(i) => EvalPos::Ops(translate[*i]),
+                EvalPos::Constant(i) if is_point => point_map[*i].clone(),
+                EvalPos::Constant(i) => scalar_map[*i].clone(),
+                _ => x.clone(),
+            });
+            let op = match op {
+                EvalOps::CheckPoint(tag, v)
+                    if tags.contains(&tag)
+                        && !ops_cache
+                            .contains_key(&EvalOps::CheckPoint(tag.clone(), v.clone())) =>
+                {
+                    EvalOps::CheckPoint(format!("{} (merged)", tag), v)
+                }
+                op => op,
+            };
+
+            match ops_cache.get(&op) {
+                Some(i) => translate.push(*i),
+                None => {
+                    ops_cache.insert(op.clone(), c.ops.len());
+                    translate.push(c.ops.len());
+                    c.ops.push(op);
+                }
+            }
+        }
+
+        c.finals.extend(b.finals.iter().map(|f| translate[*f]));
+        Ok(c)
+    }
+
+    fn transcript_indices(&self) -> BTreeSet<usize> {
+        self.ops
+            .iter()
+            .filter_map(|op| match op {
+                EvalOps::TranscriptReadScalar(i, _)
+                | EvalOps::TranscriptReadPoint(i, _)
+                | EvalOps::TranscriptCommonScalar(i, _, _)
+                | EvalOps::TranscriptCommonPoint(i, _, _)
+                | EvalOps::TranscriptSqueeze(i, _) => Some(*i),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn msm_groups(&self) -> BTreeSet<usize> {
+        self.ops
+            .iter()
+            .filter_map(|op| match op {
+                EvalOps::MSMSlice(_, _, group) => Some(*group),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // Compare two contexts op by op, e.g. the contexts of two consecutive recursive aggregation
+    // rounds, to find where they diverge. Constant positions match if they refer to equal values.
+    pub fn diff(a: &Self, b: &Self) -> EvalContextDiff {
+        let op_eq = |op_a: &EvalOps, op_b: &EvalOps| {
+            op_a.map_with(&|_| EvalPos::Empty) == op_b.map_with(&|_| EvalPos::Empty)
+                && op_a.typed_deps().into_iter().zip(op_b.typed_deps()).all(
+                    |((pa, is_point), (pb, _))| match (pa, pb) {
+                        (EvalPos::Constant(i), EvalPos::Constant(j)) if is_point => {
+                            a.const_points[*i] == b.const_points[*j]
+                        }
+                        (EvalPos::Constant(i), EvalPos::Constant(j)) => {
+                            a.const_scalars[*i] == b.const_scalars[*j]
+                        }
+                        _ => pa == pb,
+                    },
+                )
+        };
+
+        let mut diff = EvalContextDiff::default();
+        for (i, (op_a, op_b)) in a.ops.iter().zip(b.ops.iter()).enumerate() {
+            if !op_eq(op_a, op_b) {
+                diff.mismatched_ops.push((i, op_a.clone(), op_b.clone()));
+            }
+        }
+
+        let common = usize::min(a.ops.len(), b.ops.len());
+        diff.extra_a = a.ops[common..]
+            .iter()
+            .enumerate()
+            .map(|(i, op)| (i + common, op.clone()))
+            .collect();
+        diff.extra_b = b.ops[common..]
+            .iter()
+            .enumerate()
+            .map(|(i, op)| (i + common, op.clone()))
+            .collect();
+
+        diff
+    }
+
+    // Patch a constant scalar in place so a cached topology can be reused with new values.
+    // Op indices are untouched, returns false if old_scalar is not a constant of this context.
+    pub fn replace_constant(&mut self, old_scalar: C::ScalarExt, new_scalar: C::ScalarExt) -> bool {
+        match self.const_scalars.iter().position(|x| *x == old_scalar) {
+            Some(i) => {
+                self.const_scalars[i] = new_scalar;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn replace_constant_point(&mut self, old: C, new: C) -> bool {
+        match self.const_points.iter().position(|x| *x == old) {
+            Some(i) => {
+                self.const_points[i] = new;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Dump the ops DAG in graphviz DOT format for debugging.
+    pub fn visualize(&self) -> String {
+        self.visualize_with_labels(|_| None)
+    }
+
+    // Same as visualize, each op is annotated with its value, e.g. NativeEvalContext::values.
+    // Scalars show their first 8 hex digits and points the first 8 hex digits of x.
+    pub fn visualize_with_values(&self, values: &[(Option<C>, Option<C::ScalarExt>)]) -> String {
+        let hex8 = |s: String| format!("0x{}", &s[..s.len().min(8)]);
+        self.visualize_with_labels(|i| match values.get(i) {
+            Some((_, Some(s))) => Some(hex8(field_to_bn(s).to_str_radix(16))),
+            Some((Some(p), _)) => {
+                let coordinates: Option<_> = p.coordinates().into();
+                // the identity has no coordinates
+                Some(match coordinates {
+                    Some(c) => format!("x={}", hex8(field_to_bn(c.x()).to_str_radix(16))),
+                    None => "identity".to_owned(),
+                })
+            }
+            _ => None,
+        })
+    }
+
+    fn visualize_with_labels(&self, value_label: impl Fn(usize) -> Option<String>) -> String {
+        let mut lines = vec!["digraph EvalContext {".to_owned()];
+
+        for i in 0..self.const_scalars.len() {
+            lines.push(format!(
+                "    cs{} [label=\"ConstScalar {}\", style=filled, fillcolor=lightblue];",
+                i, i
+            ));
+        }
+        for i in 0..self.const_points.len() {
+            lines.push(format!(
+                "    cp{} [label=\"ConstPoint {}\", style=filled, fillcolor=lightgreen];",
+                i, i
+            ));
+        }
+
+        for (i, op) in self.ops.iter().enumerate() {
+            let shape = if self.finals.contains(&i) {
+                "doublecircle"
+            } else {
+                "ellipse"
+            };
+            let value = value_label(i)
+                .map(|v| format!("\\n{}", v))
+                .unwrap_or_default();
+            lines.push(format!(
+                "    op{} [label=\"{} {}{}\", shape={}];",
+                i,
+                op.name(),
+                i,
+                value,
+                shape
+            ));
+        }
+
+        let mut instances = HashSet::new();
+        for (i, op) in self.ops.iter().enumerate() {
+            for (dep, is_point) in op.typed_deps() {
+                let from = match dep {
+                    EvalPos::Ops(j) => format!("op{}", j),
+                    EvalPos::Constant(j) if is_point => format!("cp{}", j),
+                    EvalPos::Constant(j) => format!("cs{}", j),
+                    EvalPos::Instance(j, k) => {
+                        if instances.insert((*j, *k)) {
+                            lines.push(format!(
+                                "    instance_{}_{} [label=\"Instance {} {}\", style=filled, fillcolor=lightyellow];",
+                                j, k, j, k
+                            ));
+                        }
+                        format!("instance_{}_{}", j, k)
+                    }
+                    EvalPos::Empty => continue,
+                };
+                lines.push(format!("    {} -> op{};", from, i));
+            }
+        }
+
+        lines.push("}".to_owned());
+        lines.join("\n")
+    }
+
+    // Evaluate scalar ops whose inputs are all constants and drop trivial ops like x + 0, x * 1.
+    // Ops that become equal after folding are merged again.
+    // Ops indices are compacted, finals are remapped and keep their values.
+    pub fn constant_fold(&mut self) {
+        let zero = C::ScalarExt::zero();
+        let one = C::ScalarExt::one();
+
+        let mut resolved: Vec<EvalPos> = vec![];
+        let mut ops = vec![];
+        let mut ops_cache: HashMap<EvalOps, usize> = HashMap::new();
+
+        for op in self.ops.clone() {
+            let op = op.map_with(&|x| match x {
+                EvalPos::Ops(i) => resolved[*i].clone(),
+                _ => x.clone(),
+            });
+
+            let const_of = |x: &EvalPos| match x {
+                EvalPos::Constant(i) => Some(self.const_scalars[*i]),
+                _ => None,
+            };
+
+            let folded = match &op {
+                EvalOps::ScalarAdd(a, b) => match (const_of(a), const_of(b)) {
+                    (Some(a), Some(b)) => Some(self.push_const_scalar(a + b)),
+                    (Some(a), _) if a == zero => Some(b.clone()),
+                    (_, Some(b)) if b == zero => Some(a.clone()),
+                    _ => None,
+                },
+                EvalOps::ScalarSub(a, b) => match (const_of(a), const_of(b)) {
+                    (Some(a), Some(b)) => Some(self.push_const_scalar(a - b)),
+                    (_, Some(b)) if b == zero => Some(a.clone()),
+                    _ => None,
+                },
+                EvalOps::ScalarNeg(a) => const_of(a).map(|a| self.push_const_scalar(-a)),
+                EvalOps::ScalarMul(a, b, _) => match (const_of(a), const_of(b)) {
+                    (Some(a), Some(b)) => Some(self.push_const_scalar(a * b)),
+                    (Some(a), _) if a == one => Some(b.clone()),
+                    (_, Some(b)) if b == one => Some(a.clone()),
+                    _ => None,
+                },
+                EvalOps::ScalarDiv(a, b) => match (const_of(a), const_of(b)) {
+                    (Some(a), Some(b)) if b != zero => {
+                        Some(self.push_const_scalar(a * b.invert().unwrap()))
+                    }
+                    (_, Some(b)) if b == one => Some(a.clone()),
+                    _ => None,
+                },
+                EvalOps::ScalarInv(a) => match const_of(a) {
+                    Some(a) if a != zero => Some(self.push_const_scalar(a.invert().unwrap())),
+                    _ => None,
+                },
+                EvalOps::ScalarPow(a, n) => match const_of(a) {
+                    Some(a) => Some(self.push_const_scalar(a.pow_vartime([*n as u64]))),
+                    None if *n == 1 => Some(a.clone()),
+                    None => None,
+                },
+                // checkpoint is only meaningful on ops
+                EvalOps::CheckPoint(_, a) => match a {
+                    EvalPos::Ops(_) => None,
+                    _ => Some(a.clone()),
+                },
+                _ => None,
+            };
+
+            match folded {
+                Some(pos) => resolved.push(pos),
+                None => match ops_cache.get(&op) {
+                    Some(i) => resolved.push(EvalPos::Ops(*i)),
+                    None => {
+                        ops_cache.insert(op.clone(), ops.len());
+                        resolved.push(EvalPos::Ops(ops.len()));
+                        ops.push(op);
+                    }
+                },
+            }
+        }
+
+        self.ops = ops;
+        for f in self.finals.iter_mut() {
+            *f = resolved[*f].to_ops_index_unsafe();
+        }
+    }
+
+    // Remove CheckPoint ops, references to a checkpoint are redirected to its inner value.
+    // Ops indices are compacted and finals are remapped.
+    pub fn prune_checkpoints(&mut self) {
+        let mut resolved: Vec<EvalPos> = vec![];
+        let mut ops = vec![];
+
+        for op in self.ops.iter() {
+            let op = op.map_with(&|x| match x {
+                EvalPos::Ops(i) => resolved[*i].clone(),
+                _ => x.clone(),
+            });
+
+            match op {
+                EvalOps::CheckPoint(_, a) => resolved.push(a),
+                op => {
+                    resolved.push(EvalPos::Ops(ops.len()));
+                    ops.push(op);
+                }
+            }
+        }
+
+        self.ops = ops;
+        for f in self.finals.iter_mut() {
+            *f = resolved[*f].to_ops_index_unsafe();
+        }
+    }
+
+    // Remove ops that no final depends on, e.g. debug checkpoints.
+    // Ops indices are compacted and finals are remapped.
+    pub fn eliminate_dead_code(&mut self) {
+        let mut alive = vec![false; self.ops.len()];
+        let mut queue = VecDeque::from(self.finals.clone());
+
+        while let Some(i) = queue.pop_front() {
+            if alive[i] {
+                continue;
+            }
+            alive[i] = true;
+            for dep in self.ops[i].deps() {
+                if let EvalPos::Ops(j) = dep {
+                    if !alive[*j] {
+                        queue.push_back(*j);
+                    }
+                }
+            }
+        }
+
+        let mut reverse_order = vec![0; self.ops.len()];
+        let mut ops = vec![];
+        for (i, op) in self.ops.iter().enumerate() {
+            if alive[i] {
+                reverse_order[i] = ops.len();
+                ops.push(op.map(&reverse_order));
+            }
+        }
+
+        self.ops = ops;
+        for f in self.finals.iter_mut() {
+            *f = reverse_order[*f];
+        }
+    }
+
     fn add_dep(&mut self, prev: &EvalPos, post: &EvalPos) {
         if let EvalPos::Ops(prev) = prev {
             if let EvalPos::Ops(post) = post {
@@ -183,21 +960,36 @@ impl<C: CurveAffine> EvalContext<C> {
         }
     }
 
+    fn push_const_scalar(&mut self, x: C::ScalarExt) -> EvalPos {
+        let mut pos = self.const_scalars.len();
+        for (i, s) in self.const_scalars.iter().enumerate() {
+            if *s == x {
+                pos = i;
+            }
+        }
+        if pos == self.const_scalars.len() {
+            self.const_scalars.push(x);
+        }
+        EvalPos::Constant(pos.try_into().unwrap())
+    }
+
+    fn push_const_point(&mut self, x: C) -> EvalPos {
+        let mut pos = self.const_points.len();
+        for (i, p) in self.const_points.iter().enumerate() {
+            if *p == x {
+                pos = i;
+            }
+        }
+        if pos == self.const_points.len() {
+            self.const_points.push(x);
+        }
+        EvalPos::Constant(pos.try_into().unwrap())
+    }
+
     fn translate_ast_scalar(&mut self, ast: &Rc<AstScalar<C>>) -> EvalPos {
         let ast: &AstScalar<C> = ast.as_ref();
         match ast {
-            AstScalar::FromConst(x) => {
-                let mut pos = self.const_scalars.len();
-                for (i, s) in self.const_scalars.iter().enumerate() {
-                    if s == x {
-                        pos = i;
-                    }
-                }
-                if pos == self.const_scalars.len() {
-                    self.const_scalars.push(*x);
-                }
-                EvalPos::Constant(pos.try_into().unwrap())
-            }
+            AstScalar::FromConst(x) => self.push_const_scalar(*x),
             AstScalar::FromTranscript(t) | AstScalar::FromChallenge(t) => {
                 self.translate_ast_transcript(t)
             }
@@ -211,6 +1003,10 @@ impl<C: CurveAffine> EvalContext<C> {
                 let b = self.translate_ast_scalar(b);
                 self.push_op(EvalOps::ScalarSub(a, b))
             }
+            AstScalar::Neg(a) => {
+                let a = self.translate_ast_scalar(a);
+                self.push_op(EvalOps::ScalarNeg(a))
+            }
             AstScalar::Mul(a, b, is_cg) => {
                 let a = self.translate_ast_scalar(a);
                 let b = self.translate_ast_scalar(b);
@@ -219,7 +1015,12 @@ impl<C: CurveAffine> EvalContext<C> {
             AstScalar::Div(a, b) => {
                 let a = self.translate_ast_scalar(a);
                 let b = self.translate_ast_scalar(b);
-                self.push_op(EvalOps::ScalarDiv(a, b))
+                match a {
+                    EvalPos::Constant(i) if self.const_scalars[i] == C::ScalarExt::one() => {
+                        self.push_op(EvalOps::ScalarInv(b))
+                    }
+                    _ => self.push_op(EvalOps::ScalarDiv(a, b)),
+                }
             }
             AstScalar::Pow(a, n) => {
                 let a = self.translate_ast_scalar(a);
@@ -274,18 +1075,7 @@ impl<C: CurveAffine> EvalContext<C> {
     fn translate_ast_point(&mut self, ast: &Rc<AstPoint<C>>) -> EvalPos {
         let ast: &AstPoint<C> = ast.as_ref();
         match ast {
-            AstPoint::FromConst(c) => {
-                let mut pos = self.const_points.len();
-                for (i, p) in self.const_points.iter().enumerate() {
-                    if p == c {
-                        pos = i;
-                    }
-                }
-                if pos == self.const_points.len() {
-                    self.const_points.push(*c);
-                }
-                EvalPos::Constant(pos.try_into().unwrap())
-            }
+            AstPoint::FromConst(c) => self.push_const_point(*c),
             AstPoint::FromTranscript(t) => self.translate_ast_transcript(t),
             AstPoint::FromInstance(i, j) => EvalPos::Instance(*i, *j),
             AstPoint::MultiExp(psl, group) => {
@@ -382,3 +1172,557 @@ impl<C: CurveAffine> EvalContext<C> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::api::arith::AstPointRc;
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::ast_eval::EvalContextDeserializeError;
+    use crate::api::ast_eval::EvalContextMergeError;
+    use crate::api::ast_eval::EvalOps;
+    use crate::api::ast_eval::EvalPos;
+    use crate::api::ast_eval::OpType;
+    use crate::api::ast_eval::EVAL_CONTEXT_FORMAT_VERSION;
+    use crate::api::halo2::verify_aggregation_proofs;
+    use crate::api::halo2::verify_single_proof_no_eval;
+    use crate::api::halo2::verify_single_proof_with_params;
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use crate::circuits::utils::instance_to_instance_commitment;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::circuits::utils::ProofProtocol;
+    use crate::circuits::utils::TranscriptHash;
+    use crate::native_verifier::NativeEvalContext;
+    use crate::transcript::poseidon::PoseidonRead;
+    use halo2_proofs::arithmetic::CurveAffine;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use halo2_proofs::pairing::group::Curve;
+    use halo2_proofs::plonk::VerifyingKey;
+    use halo2_proofs::poly::commitment::Params;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use halo2ecc_s::utils::field_to_bn;
+
+    struct Fixture {
+        params: Params<G1Affine>,
+        vkey: VerifyingKey<G1Affine>,
+        w_x: AstPointRc<G1Affine>,
+        w_g: AstPointRc<G1Affine>,
+        advices: Vec<Vec<AstPointRc<G1Affine>>>,
+    }
+
+    // The pairing points of verifying a single SimpleCircuit proof.
+    fn simple_circuit_fixture(use_shplonk: bool) -> Fixture {
+        let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+        let params = load_or_build_unsafe_params::<Bn256>(8, None);
+        let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+        let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+        let (w_x, w_g, advices) =
+            verify_aggregation_proofs(&params_verifier, &[&vkey], &vec![], use_shplonk, &vec![]);
+        Fixture {
+            params,
+            vkey,
+            w_x,
+            w_g,
+            advices,
+        }
+    }
+
+    // The pairing points of a proof of the fixture circuit at proof index,
+    // w_x and w_g use msm groups msm_offset and msm_offset + 1.
+    fn single_proof_context(f: &Fixture, index: usize, msm_offset: usize) -> EvalContext<G1Affine> {
+        let params_verifier: ParamsVerifier<Bn256> = f.params.verifier(1).unwrap();
+        let (proof, _, _) = verify_single_proof_no_eval(&params_verifier, &f.vkey, index, false);
+        let w_x = proof.w_x.eval(params_verifier.g1, msm_offset);
+        let w_g = proof.w_g.eval(-params_verifier.g1, msm_offset + 1);
+        EvalContext::translate(&[w_x.0, w_g.0])
+    }
+
+    #[test]
+    fn test_eval_stats() {
+        let f = simple_circuit_fixture(true);
+        let c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+        c.assert_valid();
+        let stats = c.stats();
+
+        assert_eq!(stats.total_op_count, c.ops.len());
+        assert_eq!(stats.const_scalar_count, c.const_scalars.len());
+        assert_eq!(stats.const_point_count, c.const_points.len());
+        assert!(stats.msm_slice_count > 0);
+        assert!(stats.transcript_squeeze_count > 0);
+        assert!(
+            stats.msm_slice_count
+                + stats.scalar_mul_count
+                + stats.scalar_div_count
+                + stats.scalar_add_sub_count
+                + stats.transcript_read_count
+                + stats.transcript_squeeze_count
+                <= stats.total_op_count
+        );
+
+        let n_points = c
+            .ops
+            .iter()
+            .filter(|op| matches!(op, EvalOps::TranscriptReadPoint(..)))
+            .count();
+        assert!(n_points >= f.vkey.cs.num_advice_columns);
+        assert!(stats.transcript_read_count > n_points);
+
+        let by_type = c.ops_by_type();
+        assert_eq!(
+            by_type.values().map(|v| v.len()).sum::<usize>(),
+            c.ops.len()
+        );
+        assert_eq!(by_type[&OpType::TranscriptReadPoint].len(), n_points);
+        assert_eq!(by_type[&OpType::MSMSlice].len(), stats.msm_slice_count);
+        for (t, indices) in by_type.iter() {
+            assert!(indices.windows(2).all(|w| w[0] < w[1]));
+            assert!(indices.iter().all(|i| c.ops[*i].op_type() == *t));
+        }
+
+        // shplonk inverts the point differences, a division of constant one is translated to ScalarInv
+        assert!(!by_type[&OpType::ScalarInv].is_empty());
+        assert!(c.ops.iter().all(|op| match op {
+            EvalOps::ScalarDiv(EvalPos::Constant(i), _) => c.const_scalars[*i] != Fr::one(),
+            _ => true,
+        }));
+    }
+
+    #[test]
+    fn test_eval_visualize() {
+        let f = simple_circuit_fixture(true);
+        let c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+        let dot = c.visualize();
+
+        assert!(dot.starts_with("digraph EvalContext {"));
+        assert!(dot.ends_with("}"));
+        assert!(dot.contains("op0 [label=\"TranscriptReadPoint 0\""));
+        assert!(dot.contains("[label=\"MSMSlice"));
+        assert!(dot.contains("cs0 [label=\"ConstScalar 0\""));
+        assert!(dot.contains("instance_0_0 -> op"));
+        for f in c.finals.iter() {
+            assert!(dot.contains(&format!(
+                "op{} [label=\"MSM {}\", shape=doublecircle]",
+                f, f
+            )));
+        }
+    }
+
+    #[test]
+    fn test_eval_visualize_with_values() {
+        let f = simple_circuit_fixture(false);
+        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        let proof = load_or_create_proof::<Bn256, _>(
+            &f.params,
+            f.vkey.clone(),
+            circuit,
+            &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            TranscriptHash::Poseidon,
+            false,
+            ProofProtocol::GWC,
+        );
+
+        let params_verifier = f.params.verifier(1).unwrap();
+        let c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+        let instance_commitments =
+            instance_to_instance_commitment(&params_verifier, &[&f.vkey], vec![&instances]);
+
+        let empty = vec![];
+        let mut ctx = NativeEvalContext::<Bn256, _, _>::new(
+            c.clone(),
+            instance_commitments,
+            vec![
+                PoseidonRead::init(&proof[..]),
+                PoseidonRead::init(&empty[..]),
+            ],
+        );
+        ctx.context_eval();
+
+        let dot = c.visualize_with_values(&ctx.values);
+        assert!(dot.starts_with("digraph EvalContext {"));
+        assert_eq!(dot.lines().count(), c.visualize().lines().count());
+
+        let x = field_to_bn(ctx.values[0].0.unwrap().coordinates().unwrap().x()).to_str_radix(16);
+        assert!(dot.contains(&format!(
+            "op0 [label=\"TranscriptReadPoint 0\\nx=0x{}\"",
+            &x[..8]
+        )));
+        for f in c.finals.iter() {
+            assert!(dot.contains(&format!("op{} [label=\"MSM {}\\nx=0x", f, f)));
+        }
+    }
+
+    #[test]
+    fn test_eval_topological_depth() {
+        let f = simple_circuit_fixture(true);
+        let c = EvalContext::translate(&[f.w_x.0.clone(), f.w_g.0.clone()]);
+        let depth = c.topological_depth();
+
+        assert!(depth > 1);
+        assert!(depth < c.ops.len());
+
+        // a second proof runs in parallel to the first one
+        let c = single_proof_context(&f, 0, 0);
+        let depth = c.topological_depth();
+        let merged = EvalContext::merge(c, single_proof_context(&f, 1, 2)).unwrap();
+        assert_eq!(merged.topological_depth(), depth);
+    }
+
+    #[test]
+    fn test_eval_constant_fold() {
+        let f = simple_circuit_fixture(true);
+        let mut c = EvalContext::translate(&[f.w_x.0.clone(), f.w_g.0.clone()]);
+        let n_ops = c.ops.len();
+        c.constant_fold();
+        c.assert_valid();
+        assert!(c.ops.len() <= n_ops);
+        assert_eq!(c.finals.len(), 2);
+
+        // x * 1 + 2 and x + 2 fold to the same op, which is kept once
+        let mut c = EvalContext::<G1Affine>::default();
+        c.const_scalars = vec![Fr::one(), Fr::from(2u64)];
+        c.ops = vec![
+            EvalOps::TranscriptSqueeze(0, EvalPos::Empty),
+            EvalOps::ScalarMul(EvalPos::Ops(0), EvalPos::Constant(0), false),
+            EvalOps::ScalarAdd(EvalPos::Ops(1), EvalPos::Constant(1)),
+            EvalOps::ScalarAdd(EvalPos::Ops(0), EvalPos::Constant(1)),
+            EvalOps::ScalarMul(EvalPos::Ops(2), EvalPos::Ops(3), false),
+        ];
+        c.finals = vec![4];
+        c.assert_valid();
+
+        c.constant_fold();
+        c.assert_valid();
+        assert_eq!(
+            c.ops,
+            vec![
+                EvalOps::TranscriptSqueeze(0, EvalPos::Empty),
+                EvalOps::ScalarAdd(EvalPos::Ops(0), EvalPos::Constant(1)),
+                EvalOps::ScalarMul(EvalPos::Ops(1), EvalPos::Ops(1), false),
+            ]
+        );
+        assert_eq!(c.finals, vec![2]);
+    }
+
+    #[test]
+    fn test_eval_prune_checkpoints() {
+        let f = simple_circuit_fixture(true);
+        let mut c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+        #[cfg(not(feature = "debug_aggregation"))]
+        assert!(!c.ops_by_type().contains_key(&OpType::CheckPoint));
+
+        c.prune_checkpoints();
+        let ops = c.ops.clone();
+        let finals = c.finals.clone();
+
+        // a reference to a checkpoint is redirected to the checkpoint's inner value
+        let j = c
+            .ops
+            .iter()
+            .position(|op| op.op_type() == OpType::TranscriptSqueeze)
+            .unwrap();
+        let n = c.ops.len();
+        c.ops
+            .push(EvalOps::CheckPoint("squeeze".to_owned(), EvalPos::Ops(j)));
+        c.ops.push(EvalOps::ScalarNeg(EvalPos::Ops(n)));
+
+        c.prune_checkpoints();
+        c.assert_valid();
+        assert_eq!(c.ops.len(), n + 1);
+        assert_eq!(&c.ops[..n], &ops[..]);
+        assert_eq!(c.ops[n], EvalOps::ScalarNeg(EvalPos::Ops(j)));
+        assert_eq!(c.finals, finals);
+    }
+
+    #[test]
+    fn test_eval_extend_finals() {
+        let f = simple_circuit_fixture(true);
+        let full = EvalContext::translate(&[f.w_x.0.clone(), f.w_g.0.clone()]);
+
+        let mut c = EvalContext::translate(&[f.w_x.0.clone()]);
+        let ops = c.ops.clone();
+        let new_finals = c.extend_finals(&[f.w_g.0.clone()]);
+        assert_eq!(new_finals, vec![c.finals[1]]);
+        assert_eq!(c.finals.len(), 2);
+        assert_eq!(&c.ops[..ops.len()], &ops[..]);
+        assert_eq!(c.ops.len(), full.ops.len());
+
+        // a target that is already translated adds no ops
+        let n = c.ops.len();
+        let again = c.extend_finals(&[f.w_x.0]);
+        assert_eq!(again, vec![c.finals[0]]);
+        assert_eq!(c.ops.len(), n);
+    }
+
+    #[test]
+    fn test_eval_eliminate_dead_code() {
+        let f = simple_circuit_fixture(true);
+        let mut c = EvalContext::translate(&[f.w_x.0.clone(), f.w_g.0]);
+        let n_ops = c.ops.len();
+
+        // nothing to remove if all finals are kept
+        c.eliminate_dead_code();
+        assert_eq!(c.ops.len(), n_ops);
+
+        // drop w_g, only ops reachable from w_x should remain
+        c.finals.truncate(1);
+        c.eliminate_dead_code();
+        c.assert_valid();
+        let expected = EvalContext::translate(&[f.w_x.0]);
+        assert!(c.ops.len() < n_ops);
+        assert_eq!(c.ops.len(), expected.ops.len());
+        assert_eq!(c.finals.len(), 1);
+
+        let ops = c.ops.clone();
+        let finals = c.finals.clone();
+        c.eliminate_dead_code();
+        assert_eq!(c.ops, ops);
+        assert_eq!(c.finals, finals);
+    }
+
+    #[test]
+    fn test_eval_merge() {
+        let f = simple_circuit_fixture(true);
+        let a = single_proof_context(&f, 0, 0);
+        let b = single_proof_context(&f, 1, 2);
+
+        let c = EvalContext::merge(a.clone(), b.clone()).unwrap();
+        c.assert_valid();
+        assert_eq!(&c.ops[..a.ops.len()], &a.ops[..]);
+        assert!(c.ops.len() <= a.ops.len() + b.ops.len());
+        assert_eq!(c.const_scalars, a.const_scalars);
+        assert_eq!(c.const_points, a.const_points);
+        assert_eq!(c.finals.len(), 4);
+        assert_eq!(&c.finals[..2], &a.finals[..]);
+        for (fc, fb) in c.finals[2..].iter().zip(b.finals.iter()) {
+            assert_eq!(c.ops[*fc].op_type(), b.ops[*fb].op_type());
+        }
+
+        // the same proof index or msm group is rejected
+        assert_eq!(
+            EvalContext::merge(a.clone(), a.clone()).err(),
+            Some(EvalContextMergeError::TranscriptIndexCollision(0))
+        );
+        assert_eq!(
+            EvalContext::merge(a, single_proof_context(&f, 1, 0)).err(),
+            Some(EvalContextMergeError::MsmGroupCollision(0))
+        );
+
+        // ops of b equal to ops of a are reused, a taken checkpoint tag is renamed
+        let x = Fr::from(3u64);
+        let y = Fr::from(5u64);
+        let mut a = EvalContext::<G1Affine>::default();
+        a.const_scalars = vec![x];
+        a.ops = vec![
+            EvalOps::ScalarPow(EvalPos::Constant(0), 3),
+            EvalOps::TranscriptSqueeze(0, EvalPos::Empty),
+            EvalOps::CheckPoint("s".to_owned(), EvalPos::Ops(1)),
+            EvalOps::ScalarMul(EvalPos::Ops(1), EvalPos::Ops(0), false),
+        ];
+        a.finals = vec![3];
+        let mut b = EvalContext::<G1Affine>::default();
+        b.const_scalars = vec![y, x];
+        b.ops = vec![
+            EvalOps::ScalarPow(EvalPos::Constant(1), 3),
+            EvalOps::TranscriptSqueeze(1, EvalPos::Empty),
+            EvalOps::CheckPoint("s".to_owned(), EvalPos::Ops(1)),
+            EvalOps::ScalarMul(EvalPos::Ops(1), EvalPos::Ops(0), false),
+        ];
+        b.finals = vec![3];
+
+        let c = EvalContext::merge(a, b).unwrap();
+        c.assert_valid();
+        assert_eq!(c.const_scalars, vec![x, y]);
+        assert_eq!(
+            c.ops,
+            vec![
+                EvalOps::ScalarPow(EvalPos::Constant(0), 3),
+                EvalOps::TranscriptSqueeze(0, EvalPos::Empty),
+                EvalOps::CheckPoint("s".to_owned(), EvalPos::Ops(1)),
+                EvalOps::ScalarMul(EvalPos::Ops(1), EvalPos::Ops(0), false),
+                EvalOps::TranscriptSqueeze(1, EvalPos::Empty),
+                EvalOps::CheckPoint("s (merged)".to_owned(), EvalPos::Ops(4)),
+                EvalOps::ScalarMul(EvalPos::Ops(4), EvalPos::Ops(0), false),
+            ]
+        );
+        assert_eq!(c.finals, vec![3, 6]);
+    }
+
+    #[test]
+    fn test_eval_replace_constant() {
+        let f = simple_circuit_fixture(true);
+        let mut c = EvalContext::translate(&[f.w_x.0]);
+        let ops = c.ops.clone();
+        let finals = c.finals.clone();
+
+        let old_point = c.const_points[0];
+        let new_point = (old_point + old_point).to_affine();
+        assert!(c.replace_constant_point(old_point, new_point));
+        assert_eq!(c.const_points[0], new_point);
+        assert!(!c.replace_constant_point(old_point, new_point));
+
+        let old_scalar = c.const_scalars[0];
+        let new_scalar = old_scalar + Fr::one();
+        assert!(c.replace_constant(old_scalar, new_scalar));
+        assert_eq!(c.const_scalars[0], new_scalar);
+
+        assert_eq!(c.ops, ops);
+        assert_eq!(c.finals, finals);
+    }
+
+    #[test]
+    fn test_eval_diff() {
+        let f = simple_circuit_fixture(true);
+        let a = EvalContext::translate(&[f.w_x.0]);
+        assert!(EvalContext::diff(&a, &a).is_empty());
+
+        // the same constants at other indices make no difference
+        let mut b = a.clone();
+        b.const_scalars.reverse();
+        let n = b.const_scalars.len();
+        b.ops = b
+            .ops
+            .iter()
+            .map(|op| {
+                op.map_with_typed(&|x, is_point| match x {
+                    EvalPos::Constant(i) if !is_point => EvalPos::Constant(n - 1 - i),
+                    _ => x.clone(),
+                })
+            })
+            .collect();
+        assert!(EvalContext::diff(&a, &b).is_empty());
+
+        let mut b = a.clone();
+        let old_point = b.const_points[0];
+        b.replace_constant_point(old_point, (old_point + old_point).to_affine());
+        let diff = EvalContext::diff(&a, &b);
+        assert!(!diff.mismatched_ops.is_empty());
+        for (i, op_a, op_b) in diff.mismatched_ops.iter() {
+            assert_eq!(&a.ops[*i], op_a);
+            assert_eq!(op_a, op_b);
+            assert!(op_a.deps().contains(&&EvalPos::Constant(0)));
+        }
+        assert!(diff.extra_a.is_empty() && diff.extra_b.is_empty());
+
+        let mut b = a.clone();
+        b.ops.truncate(a.ops.len() - 2);
+        let diff = EvalContext::diff(&a, &b);
+        assert!(diff.mismatched_ops.is_empty());
+        assert_eq!(diff.extra_a.len(), 2);
+        assert_eq!(diff.first_divergence(), Some(a.ops.len() - 2));
+        assert!(EvalContext::diff(&b, &a).extra_a.is_empty());
+    }
+
+    #[test]
+    fn test_eval_context_json_round_trip() {
+        let f = simple_circuit_fixture(true);
+        let c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+
+        let json = c.to_json();
+        let d = EvalContext::<G1Affine>::from_json(&json).unwrap();
+        assert_eq!(d.ops, c.ops);
+        assert_eq!(d.const_points, c.const_points);
+        assert_eq!(d.const_scalars, c.const_scalars);
+        assert_eq!(d.finals, c.finals);
+
+        let stale = json.replace(
+            &format!("\"format_version\":{}", EVAL_CONTEXT_FORMAT_VERSION),
+            &format!("\"format_version\":{}", EVAL_CONTEXT_FORMAT_VERSION + 1),
+        );
+        assert_eq!(
+            EvalContext::<G1Affine>::from_json(&stale).err(),
+            Some(EvalContextDeserializeError::VersionMismatch {
+                expected: EVAL_CONTEXT_FORMAT_VERSION,
+                found: EVAL_CONTEXT_FORMAT_VERSION + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_eval_context_json_rejects_invalid_refs() {
+        let f = simple_circuit_fixture(true);
+        let c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+        let last = c.ops.len() - 1;
+
+        // forward reference: the first op with an op dependency points at the last op
+        let mut forward = c.clone();
+        let i = forward
+            .ops
+            .iter()
+            .position(|op| op.deps().iter().any(|d| matches!(d, EvalPos::Ops(_))))
+            .unwrap();
+        forward.ops[i] = forward.ops[i].map_with(&|d| match d {
+            EvalPos::Ops(_) => EvalPos::Ops(last),
+            d => d.clone(),
+        });
+        assert!(matches!(
+            EvalContext::<G1Affine>::from_json(&forward.to_json()),
+            Err(EvalContextDeserializeError::InvalidFormat(_))
+        ));
+
+        // dangling reference: a final past the end of ops
+        let mut dangling = c.clone();
+        dangling.finals.push(c.ops.len());
+        assert!(matches!(
+            EvalContext::<G1Affine>::from_json(&dangling.to_json()),
+            Err(EvalContextDeserializeError::InvalidFormat(_))
+        ));
+
+        // dangling reference: a const scalar that was dropped
+        assert!(!c.const_scalars.is_empty());
+        let mut dangling = c.clone();
+        dangling.const_scalars.clear();
+        assert!(matches!(
+            EvalContext::<G1Affine>::from_json(&dangling.to_json()),
+            Err(EvalContextDeserializeError::InvalidFormat(_))
+        ));
+
+        assert!(EvalContext::<G1Affine>::from_json(&c.to_json()).is_ok());
+    }
+
+    #[test]
+    fn test_eval_context_json_rejects_v1_with_scalar_inv() {
+        let f = simple_circuit_fixture(true);
+        let c = EvalContext::translate(&[f.w_x.0, f.w_g.0]);
+        assert!(!c.ops_by_type()[&OpType::ScalarInv].is_empty());
+
+        // v1 has no ScalarInv, a v1 blob with it must not be accepted
+        let json = c.to_json();
+        assert!(json.contains("\"ScalarInv\""));
+        let v1 = json.replace(
+            &format!("\"format_version\":{}", EVAL_CONTEXT_FORMAT_VERSION),
+            "\"format_version\":1",
+        );
+        assert_eq!(
+            EvalContext::<G1Affine>::from_json(&v1).err(),
+            Some(EvalContextDeserializeError::VersionMismatch {
+                expected: 2,
+                found: 1,
+            })
+        );
+    }
+
+    #[test]
+    fn test_eval_context_from_verifier_params() {
+        let f = simple_circuit_fixture(false);
+        let mut targets = vec![f.w_x.0, f.w_g.0];
+        targets.extend(f.advices[0].iter().map(|p| p.0.clone()));
+        let expected = EvalContext::translate(&targets[..]);
+
+        let params_verifier: ParamsVerifier<Bn256> = f.params.verifier(1).unwrap();
+        let (w_x, w_g, verifier_params) =
+            verify_single_proof_with_params(&params_verifier, &f.vkey, true);
+        let c = EvalContext::from_verifier_params(&verifier_params, w_x, w_g);
+
+        assert_eq!(c.finals.len(), 2 + verifier_params.advice_commitments.len());
+        assert_eq!(c.ops, expected.ops);
+        assert_eq!(c.const_points, expected.const_points);
+        assert_eq!(c.const_scalars, expected.const_scalars);
+        assert_eq!(c.finals, expected.finals);
+    }
+}
@@ -1,6 +1,7 @@
 use super::protocols::lookup;
 use super::protocols::permutation;
 use super::protocols::shuffle;
+use super::verifier::MultiOpenProof;
 use super::verifier::VerifierParams;
 use crate::api::arith::*;
 use crate::api::transcript::AstTranscript;
@@ -15,23 +16,158 @@ use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::Field;
 use halo2_proofs::arithmetic::FieldExt;
 use halo2_proofs::arithmetic::MultiMillerLoop;
+use halo2_proofs::plonk::Expression;
 use halo2_proofs::plonk::VerifyingKey;
 use halo2_proofs::poly::commitment::ParamsVerifier;
 use std::collections::HashSet;
 use std::iter;
+use std::marker::PhantomData;
 use std::rc::Rc;
 
-pub struct VerifierParamsBuilder<'a, E: MultiMillerLoop> {
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CircuitSizeEstimate {
+    pub evaluation_queries: usize,
+    pub msm_groups: usize,
+    pub estimated_msm_ops: usize,
+    pub estimated_scalar_ops: usize,
+    pub estimated_transcript_reads: usize,
+}
+
+fn expression_ops<F: FieldExt>(expr: &Expression<F>) -> usize {
+    match expr {
+        Expression::Negated(a) | Expression::Scaled(a, _) => 1 + expression_ops(a),
+        Expression::Sum(a, b) | Expression::Product(a, b) => {
+            1 + expression_ops(a) + expression_ops(b)
+        }
+        _ => 0,
+    }
+}
+
+// Rotations the queries of vk are opened at, each one is an msm group of the multi-open proof.
+fn opening_rotations<C: CurveAffine>(vk: &VerifyingKey<C>) -> HashSet<i32> {
+    let cs = &vk.cs;
+    let n_permutation_product_commitments = cs.permutation.columns.chunks(cs.degree() - 2).len();
+
+    let mut rotations = HashSet::<i32>::new();
+    for i in iter::empty()
+        .chain(cs.instance_queries.iter().map(|x| x.1 .0 as i32))
+        .chain(cs.advice_queries.iter().map(|x| x.1 .0 as i32))
+        .chain(cs.fixed_queries.iter().map(|x| x.1 .0 as i32))
+        .chain(vec![0, 1, -1].into_iter())
+    {
+        rotations.insert(i);
+    }
+    if n_permutation_product_commitments > 1 {
+        rotations.insert(-((cs.blinding_factors() + 1) as i32));
+    }
+    rotations
+}
+
+// Points read before x, scalars read after x and points of the multi-open proof,
+// in the order they appear in a single proof of vk.
+pub(crate) fn proof_transcript_layout<C: CurveAffine>(
+    vk: &VerifyingKey<C>,
+    use_gwc: bool,
+) -> (usize, usize, usize) {
+    let cs = &vk.cs;
+    let poly_degree = vk.domain.get_quotient_poly_degree();
+    let n_permutation_product_commitments = cs.permutation.columns.chunks(cs.degree() - 2).len();
+    let n_lookups = cs.lookups.len();
+    let n_shuffle_groups = cs.shuffles.group(cs.degree()).len();
+
+    // each permutation product is opened at x, x_next and x_last (except the last one)
+    let n_permutation_product_evals = if n_permutation_product_commitments > 0 {
+        n_permutation_product_commitments * 3 - 1
+    } else {
+        0
+    };
+
+    let n_points = cs.num_advice_columns
+        + n_lookups * 3
+        + n_permutation_product_commitments
+        + n_shuffle_groups
+        + 1
+        + poly_degree;
+    let n_scalars = cs.instance_queries.len()
+        + cs.advice_queries.len()
+        + cs.fixed_queries.len()
+        + 1
+        + vk.permutation.commitments.len()
+        + n_permutation_product_evals
+        + n_lookups * 5
+        + n_shuffle_groups * 2;
+    let n_multiopen_points = if use_gwc {
+        opening_rotations(vk).len()
+    } else {
+        2
+    };
+
+    (n_points, n_scalars, n_multiopen_points)
+}
+
+// Type tags selecting the multi-open protocol of a VerifierParamsBuilder.
+pub struct GWC;
+pub struct SHPLONK;
+
+pub trait MultiOpenProtocol {
+    const USE_GWC: bool;
+
+    fn batch_multi_open_proofs<C: CurveAffine>(params: &VerifierParams<C>) -> MultiOpenProof<C>;
+}
+
+impl MultiOpenProtocol for GWC {
+    const USE_GWC: bool = true;
+
+    fn batch_multi_open_proofs<C: CurveAffine>(params: &VerifierParams<C>) -> MultiOpenProof<C> {
+        params.batch_multi_open_proofs_gwc()
+    }
+}
+
+impl MultiOpenProtocol for SHPLONK {
+    const USE_GWC: bool = false;
+
+    fn batch_multi_open_proofs<C: CurveAffine>(params: &VerifierParams<C>) -> MultiOpenProof<C> {
+        params.batch_multi_open_proofs_shplonk()
+    }
+}
+
+pub struct VerifierParamsBuilder<'a, E: MultiMillerLoop, P: MultiOpenProtocol> {
     pub(crate) key: String,
     pub(crate) proof_index: usize,
     pub(crate) params: &'a ParamsVerifier<E>,
     pub(crate) vk: &'a VerifyingKey<E::G1Affine>,
-    pub(crate) use_gwc: bool,
+    pub(crate) _protocol: PhantomData<P>,
 }
 
-impl<'a, C: CurveAffine, E: MultiMillerLoop<G1Affine = C, Scalar = C::ScalarExt>>
-    VerifierParamsBuilder<'a, E>
+impl<
+        'a,
+        C: CurveAffine,
+        E: MultiMillerLoop<G1Affine = C, Scalar = C::ScalarExt>,
+        P: MultiOpenProtocol,
+    > VerifierParamsBuilder<'a, E, P>
 {
+    pub fn new(
+        key: String,
+        proof_index: usize,
+        params: &'a ParamsVerifier<E>,
+        vk: &'a VerifyingKey<E::G1Affine>,
+    ) -> Self {
+        Self {
+            key,
+            proof_index,
+            params,
+            vk,
+            _protocol: PhantomData,
+        }
+    }
+
+    // Build the verifier params and batch the multi-open proof with protocol P.
+    pub fn build_multi_open(&self) -> (VerifierParams<C>, MultiOpenProof<C>, Rc<AstTranscript<C>>) {
+        let (verifier_params, transcript) = self.build();
+        let proof = P::batch_multi_open_proofs(&verifier_params);
+        (verifier_params, proof, transcript)
+    }
+
     fn init_transcript(&self, proof_index: usize) -> (Vec<AstPointRc<C>>, Rc<AstTranscript<C>>) {
         let mut hasher = blake2b_simd::Params::new()
             .hash_length(64)
@@ -60,6 +196,59 @@ impl<'a, C: CurveAffine, E: MultiMillerLoop<G1Affine = C, Scalar = C::ScalarExt>
         (instance_commitments, transcript)
     }
 
+    // Estimate the size of the verifier from vk only, without building the ast.
+    // Scalar ops are a rough count of expression evaluation and query combination.
+    pub fn dry_run(&self) -> CircuitSizeEstimate {
+        let cs = &self.vk.cs;
+        let shuffle_groups = cs.shuffles.group(cs.degree());
+
+        let (n_points, n_scalars, n_multiopen_commitments) =
+            proof_transcript_layout(self.vk, P::USE_GWC);
+        let estimated_transcript_reads = n_points + n_scalars + n_multiopen_commitments;
+        // every scalar read after x is an evaluation, plus the vanishing evaluation derived from h
+        let evaluation_queries = n_scalars + 1;
+
+        let gates = cs
+            .gates
+            .iter()
+            .flat_map(|x| x.polys.iter())
+            .collect::<Vec<_>>();
+        let lookup_expressions = cs
+            .lookups
+            .iter()
+            .flat_map(|x| x.input_expressions.iter().chain(x.table_expressions.iter()))
+            .collect::<Vec<_>>();
+        let shuffle_expressions = shuffle_groups
+            .iter()
+            .flat_map(|v| {
+                v.0.iter().flat_map(|x| {
+                    x.input_expressions
+                        .iter()
+                        .chain(x.shuffle_expressions.iter())
+                })
+            })
+            .collect::<Vec<_>>();
+
+        // expressions are folded by challenges, each fold costs a mul and an add
+        let estimated_scalar_ops = iter::empty()
+            .chain(gates.iter())
+            .chain(lookup_expressions.iter())
+            .chain(shuffle_expressions.iter())
+            .map(|expr| expression_ops(expr) + 2)
+            .sum::<usize>()
+            + cs.permutation.columns.len() * 4
+            + evaluation_queries * 2
+            + (cs.blinding_factors() + 2) * 4;
+
+        CircuitSizeEstimate {
+            evaluation_queries,
+            msm_groups: opening_rotations(self.vk).len(),
+            estimated_msm_ops: evaluation_queries + n_multiopen_commitments + 1,
+            estimated_scalar_ops,
+            estimated_transcript_reads,
+        }
+    }
+
     pub fn build(&self) -> (VerifierParams<C>, Rc<AstTranscript<C>>) {
         let one = C::ScalarExt::one();
         let cs = &self.vk.cs;
@@ -224,7 +413,7 @@ impl<'a, C: CurveAffine, E: MultiMillerLoop<G1Affine = C, Scalar = C::ScalarExt>
             })
             .collect::<Vec<_>>();
 
-        let (multiopen_commitments, multiopen_challenges) = if self.use_gwc {
+        let (multiopen_commitments, multiopen_challenges) = if P::USE_GWC {
             // gwc
             let v = transcript.squeeze_challenge();
             let u = transcript.squeeze_challenge();
@@ -258,9 +447,8 @@ impl<'a, C: CurveAffine, E: MultiMillerLoop<G1Affine = C, Scalar = C::ScalarExt>
             (0..=l as usize)
                 .map(|i| {
                     let wi = &ws[i];
-                        ((wi / sconst!(C::ScalarExt::from(n as u64)))
-                            * (xn.clone() - sconst!(one)))
-                            / (x.clone() - wi.clone())
+                    ((wi / sconst!(C::ScalarExt::from(n as u64))) * (xn.clone() - sconst!(one)))
+                        / (x.clone() - wi.clone())
                 })
                 .rev()
                 .collect::<Vec<_>>()
@@ -1,13 +1,20 @@
 use self::builder::VerifierParamsBuilder;
+use self::builder::GWC;
+use self::builder::SHPLONK;
 use self::verifier::MultiOpenProof;
+use self::verifier::VerifierParams;
 use super::arith::AstPointRc;
+#[cfg(feature = "debug_aggregation")]
+use super::arith::AstScalarRc;
 use super::transcript::AstTranscript;
 use super::transcript::AstTranscriptReader;
 use crate::api::arith::AstPoint;
 use crate::api::halo2::query::replace_commitment;
 use crate::api::halo2::query::EvaluationQuerySchemaRc;
+use crate::api::halo2::query::ProofIndexRewriter;
 use crate::pcheckpoint;
 use crate::scalar;
+use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::MultiMillerLoop;
 use halo2_proofs::plonk::VerifyingKey;
 use halo2_proofs::poly::commitment::ParamsVerifier;
@@ -45,26 +52,152 @@ pub fn verify_single_proof_no_eval<E: MultiMillerLoop>(
     Vec<AstPointRc<E::G1Affine>>,
     Rc<AstTranscript<E::G1Affine>>,
 ) {
-    let params_builder = VerifierParamsBuilder {
-        vk,
-        params,
-        key: format_circuit_key(index),
-        proof_index: index,
-        use_gwc,
-    };
+    let (proof, verifier_params, transcript) = build_single_proof(params, vk, index, use_gwc);
+    (proof, verifier_params.advice_commitments, transcript)
+}
+
+// Built single proofs keyed by the hash of the params, the hash of the pinned vkey and the
+// multi-open protocol, the AST depends on all of them. Each entry keeps the proof index it
+// was built at, proofs of a batch that share the vkey re-index the entry to their own index.
+pub struct VerifierParamsCache<C: CurveAffine> {
+    entries: HashMap<
+        (String, String, bool),
+        (
+            usize,
+            MultiOpenProof<C>,
+            Vec<AstPointRc<C>>,
+            Rc<AstTranscript<C>>,
+        ),
+    >,
+}
+
+impl<C: CurveAffine> VerifierParamsCache<C> {
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl<C: CurveAffine> Default for VerifierParamsCache<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The AST reads n from the params, and g2 and s_g2 tell apart setups of the same size.
+fn params_hash<E: MultiMillerLoop>(params: &ParamsVerifier<E>) -> String {
+    let mut hasher = blake2b_simd::Params::new()
+        .hash_length(32)
+        .personal(b"Halo2-Verify-Par")
+        .to_state();
+
+    let s = format!("{:?}", (params.n, params.g1, params.g2, params.s_g2));
+    hasher.update(&(s.len() as u64).to_le_bytes());
+    hasher.update(s.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
+
+fn vkey_pinned_hash<C: CurveAffine>(vk: &VerifyingKey<C>) -> String {
+    let mut hasher = blake2b_simd::Params::new()
+        .hash_length(32)
+        .personal(b"Halo2-Verify-Key")
+        .to_state();
+
+    let s = format!("{:?}", vk.pinned());
+    hasher.update(&(s.len() as u64).to_le_bytes());
+    hasher.update(s.as_bytes());
+    hasher.finalize().to_hex().to_string()
+}
 
-    let (verifier_params, transcript) = params_builder.build();
+// Same as verify_single_proof_no_eval, but the built proof is reused from cache
+// for a vkey that was built with the same params and protocol before, at any index.
+pub fn verify_single_proof_no_eval_cached<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vk: &VerifyingKey<E::G1Affine>,
+    index: usize,
+    use_gwc: bool,
+    cache: &mut VerifierParamsCache<E::G1Affine>,
+) -> (
+    MultiOpenProof<E::G1Affine>,
+    Vec<AstPointRc<E::G1Affine>>,
+    Rc<AstTranscript<E::G1Affine>>,
+) {
+    let (built_index, proof, advice_commitments, transcript) = cache
+        .entries
+        .entry((params_hash(params), vkey_pinned_hash(vk), use_gwc))
+        .or_insert_with(|| {
+            let (proof, verifier_params, transcript) =
+                build_single_proof(params, vk, index, use_gwc);
+            (index, proof, verifier_params.advice_commitments, transcript)
+        });
+
+    if *built_index == index {
+        return (
+            proof.clone(),
+            advice_commitments.clone(),
+            transcript.clone(),
+        );
+    }
+
+    let mut rewriter = ProofIndexRewriter::new(*built_index, index);
     (
-        if use_gwc {
-            verifier_params.batch_multi_open_proofs_gwc()
-        } else {
-            verifier_params.batch_multi_open_proofs_shplonk()
+        MultiOpenProof {
+            w_x: EvaluationQuerySchemaRc(rewriter.schema(&proof.w_x.0)),
+            w_g: EvaluationQuerySchemaRc(rewriter.schema(&proof.w_g.0)),
         },
-        verifier_params.advice_commitments,
-        transcript,
+        advice_commitments
+            .iter()
+            .map(|p| AstPointRc(rewriter.point(&p.0)))
+            .collect(),
+        rewriter.transcript(transcript),
     )
 }
 
+// Same as verify_single_proof_no_eval, but evaluates the pairing points and keeps the
+// verifier params, see EvalContext::from_verifier_params.
+pub fn verify_single_proof_with_params<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vk: &VerifyingKey<E::G1Affine>,
+    use_gwc: bool,
+) -> (
+    AstPointRc<E::G1Affine>,
+    AstPointRc<E::G1Affine>,
+    VerifierParams<E::G1Affine>,
+) {
+    let (proof, verifier_params, _) = build_single_proof(params, vk, 0, use_gwc);
+    let w_x = pcheckpoint!("w_x".to_owned(), proof.w_x.eval(params.g1, 0));
+    let w_g = pcheckpoint!("w_g".to_owned(), proof.w_g.eval(-params.g1, 1));
+    (w_x, w_g, verifier_params)
+}
+
+fn build_single_proof<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vk: &VerifyingKey<E::G1Affine>,
+    index: usize,
+    use_gwc: bool,
+) -> (
+    MultiOpenProof<E::G1Affine>,
+    VerifierParams<E::G1Affine>,
+    Rc<AstTranscript<E::G1Affine>>,
+) {
+    let key = format_circuit_key(index);
+    let (verifier_params, proof, transcript) = if use_gwc {
+        VerifierParamsBuilder::<_, GWC>::new(key, index, params, vk).build_multi_open()
+    } else {
+        VerifierParamsBuilder::<_, SHPLONK>::new(key, index, params, vk).build_multi_open()
+    };
+    (proof, verifier_params, transcript)
+}
+
 pub fn verify_aggregation_proofs<E: MultiMillerLoop>(
     params: &ParamsVerifier<E>,
     vks: &[&VerifyingKey<E::G1Affine>],
@@ -75,11 +208,114 @@ pub fn verify_aggregation_proofs<E: MultiMillerLoop>(
     AstPointRc<E::G1Affine>,           // w_x
     AstPointRc<E::G1Affine>,           // w_g
     Vec<Vec<AstPointRc<E::G1Affine>>>, // advice commitments
+) {
+    verify_aggregation_proofs_with_offset(
+        params,
+        vks,
+        commitment_check,
+        use_shplonk_as_default,
+        proofs_with_shplonk,
+        0,
+    )
+}
+
+// Same as verify_aggregation_proofs, but w_x and w_g use msm groups msm_offset and msm_offset + 1
+// instead of 0 and 1, so that results of independent calls can be merged without collision.
+pub fn verify_aggregation_proofs_with_offset<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vks: &[&VerifyingKey<E::G1Affine>],
+    commitment_check: &Vec<[usize; 4]>,
+    use_shplonk_as_default: bool,
+    proofs_with_shplonk: &Vec<usize>,
+    msm_offset: usize,
+) -> (
+    AstPointRc<E::G1Affine>,           // w_x
+    AstPointRc<E::G1Affine>,           // w_g
+    Vec<Vec<AstPointRc<E::G1Affine>>>, // advice commitments
+) {
+    // proofs sharing a vkey are built once and re-indexed
+    let mut cache = VerifierParamsCache::new();
+    let (w_x, w_g, advice_commitments, _) = aggregate_proofs(
+        params,
+        vks,
+        commitment_check,
+        use_shplonk_as_default,
+        proofs_with_shplonk,
+        msm_offset,
+        Some(&mut cache),
+    );
+    (w_x, w_g, advice_commitments)
+}
+
+// Same as verify_aggregation_proofs, additionally returns the challenges of each proof by name.
+#[cfg(feature = "debug_aggregation")]
+pub fn verify_aggregation_proofs_debug<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vks: &[&VerifyingKey<E::G1Affine>],
+    commitment_check: &Vec<[usize; 4]>,
+    use_shplonk_as_default: bool,
+    proofs_with_shplonk: &Vec<usize>,
+) -> (
+    AstPointRc<E::G1Affine>,
+    AstPointRc<E::G1Affine>,
+    Vec<Vec<AstPointRc<E::G1Affine>>>,
+    Vec<HashMap<String, AstScalarRc<E::G1Affine>>>,
+) {
+    let (w_x, w_g, advice_commitments, verifier_params) = aggregate_proofs(
+        params,
+        vks,
+        commitment_check,
+        use_shplonk_as_default,
+        proofs_with_shplonk,
+        0,
+        None,
+    );
+
+    let challenges = verifier_params
+        .iter()
+        .map(|p| {
+            let mut challenges = HashMap::new();
+            challenges.insert("theta".to_owned(), p.theta.clone());
+            challenges.insert("beta".to_owned(), p.beta.clone());
+            challenges.insert("gamma".to_owned(), p.gamma.clone());
+            challenges.insert("y".to_owned(), p.y.clone());
+            challenges.insert("x".to_owned(), p.x.clone());
+            // gwc squeezes (v, u) and shplonk squeezes (y, v, u) in multi-open
+            let names = if p.multiopen_challenges.len() == 2 {
+                vec!["v", "u"]
+            } else {
+                vec!["y", "v", "u"]
+            };
+            for (name, c) in names.into_iter().zip(p.multiopen_challenges.iter()) {
+                challenges.insert(format!("multiopen_{}", name), c.clone());
+            }
+            challenges
+        })
+        .collect();
+
+    (w_x, w_g, advice_commitments, challenges)
+}
+
+fn aggregate_proofs<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vks: &[&VerifyingKey<E::G1Affine>],
+    commitment_check: &Vec<[usize; 4]>,
+    use_shplonk_as_default: bool,
+    proofs_with_shplonk: &Vec<usize>,
+    msm_offset: usize,
+    // without cache, the verifier params of each proof are built and returned
+    mut cache: Option<&mut VerifierParamsCache<E::G1Affine>>,
+) -> (
+    AstPointRc<E::G1Affine>,
+    AstPointRc<E::G1Affine>,
+    Vec<Vec<AstPointRc<E::G1Affine>>>,
+    Vec<VerifierParams<E::G1Affine>>,
 ) {
     let mut transcript = Rc::new(AstTranscript::Init(vks.len()));
 
     let mut pairs = vec![];
     let mut advice_commitments = vec![];
+    let mut verifier_params = vec![];
 
     // replace commitment to reduce msm len
     let mut commitment_map = HashMap::new();
@@ -93,9 +329,17 @@ pub fn verify_aggregation_proofs<E: MultiMillerLoop>(
 
     for (i, vk) in vks.into_iter().enumerate() {
         let use_shplonk = use_shplonk_as_default || proofs_with_shplonk.contains(&i);
-        let (p, a, mut t) = verify_single_proof_no_eval(params, vk, i, !use_shplonk);
+        let (p, advices, mut t) = match cache.as_deref_mut() {
+            Some(cache) => verify_single_proof_no_eval_cached(params, vk, i, !use_shplonk, cache),
+            None => {
+                let (p, v, t) = build_single_proof(params, vk, i, !use_shplonk);
+                let advices = v.advice_commitments.clone();
+                verifier_params.push(v);
+                (p, advices, t)
+            }
+        };
         transcript.common_scalar(t.squeeze_challenge());
-        advice_commitments.push(a);
+        advice_commitments.push(advices);
         pairs.push(p);
     }
 
@@ -128,8 +372,8 @@ pub fn verify_aggregation_proofs<E: MultiMillerLoop>(
         pair.w_g = EvaluationQuerySchemaRc(w_g_replace_res.0);
     }
 
-    let w_x = pcheckpoint!("w_x".to_owned(), pair.w_x.eval(params.g1, 0));
-    let w_g = pcheckpoint!("w_g".to_owned(), pair.w_g.eval(-params.g1, 1));
+    let w_x = pcheckpoint!("w_x".to_owned(), pair.w_x.eval(params.g1, msm_offset));
+    let w_g = pcheckpoint!("w_g".to_owned(), pair.w_g.eval(-params.g1, msm_offset + 1));
 
-    (w_x, w_g, advice_commitments)
+    (w_x, w_g, advice_commitments, verifier_params)
 }
@@ -94,6 +94,43 @@ impl<C: CurveAffine> Evaluated<C> {
         }
     }
 
+    // Human-readable view of the permutation columns grouped by product chunk,
+    // the chunking follows columns.chunks(vk.cs.degree() - 2) as in the verifier codegen.
+    pub fn describe(&self, vk: &VerifyingKey<C>) -> String {
+        let columns = &vk.cs.permutation.columns;
+        let n_chunks = columns.chunks(self.chunk_len).len();
+        let last_rotation = -((self.blinding_factors + 1) as i32);
+
+        let mut lines = vec![format!(
+            "{}: {} columns, chunk size {}, {} z polynomials",
+            self.key,
+            columns.len(),
+            self.chunk_len,
+            n_chunks
+        )];
+
+        for (i, chunk) in columns.chunks(self.chunk_len).enumerate() {
+            let chunk_columns = chunk
+                .iter()
+                .map(|c| format!("{:?} {}", c.column_type(), c.index()))
+                .collect::<Vec<_>>();
+            // z of each chunk is queried at x and x_next, all but the last are also queried at x_last
+            let z_rotations = if i + 1 < n_chunks {
+                format!("[0, 1, {}]", last_rotation)
+            } else {
+                "[0, 1]".to_owned()
+            };
+            lines.push(format!(
+                "chunk {}: columns [{}] at rotation 0, z rotations {}",
+                i,
+                chunk_columns.join(", "),
+                z_rotations
+            ));
+        }
+
+        lines.join("\n")
+    }
+
     pub fn expressions(&self, params: &VerifierParams<C>) -> Vec<AstScalarRc<C>> {
         let one = &sconst!(C::ScalarExt::one());
 
@@ -36,11 +36,11 @@ impl<C: CurveAffine> Evaluated<C> {
             .rev()
             .enumerate()
             .map(|(i, c)| {
-                commit!(Rc::new(CommitQuery {
-                    key: format!("{}_h_commitment{}", params.key.clone(), i),
-                    commitment: Some(c.clone()),
-                    eval: None,
-                }))
+                commit!(Rc::new(CommitQuery::new(
+                    format!("{}_h_commitment{}", params.key.clone(), i),
+                    Some(c.clone()),
+                    None,
+                )))
             })
             .reduce(|acc, commitment| scalar!(params.xn.clone()) * acc + commitment)
             .unwrap();
@@ -58,7 +58,7 @@ impl<C: CurveAffine> Evaluated<C> {
                 0,
                 params.x.clone(),
                 self.h_commitment.clone(),
-                scalar!(self.expected_h_eval.clone())
+                scalar!(self.expected_h_eval.clone()),
             ),
             EvaluationQuery::new(
                 0,
@@ -2,6 +2,8 @@ use crate::api::arith::AstPoint;
 use crate::api::arith::AstPointRc;
 use crate::api::arith::AstScalar;
 use crate::api::arith::AstScalarRc;
+use crate::api::halo2::format_circuit_key;
+use crate::api::transcript::AstTranscript;
 use crate::commit;
 use crate::eval;
 use crate::pconst;
@@ -10,8 +12,10 @@ use crate::sconst;
 use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::Field;
 use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::ops::Add;
 use std::ops::Mul;
+use std::ptr::NonNull;
 use std::rc::Rc;
 
 #[derive(Clone, Debug)]
@@ -19,6 +23,35 @@ pub struct CommitQuery<C: CurveAffine> {
     pub key: String,
     pub commitment: Option<AstPointRc<C>>,
     pub eval: Option<AstScalarRc<C>>,
+    // queries on the same commitment node share one msm term even with different keys
+    pub commitment_ptr_eq: Option<NonNull<AstPoint<C>>>,
+}
+
+pub fn commitment_ptr<C: CurveAffine>(p: &AstPointRc<C>) -> Option<NonNull<AstPoint<C>>> {
+    NonNull::new(Rc::as_ptr(&p.0) as *mut _)
+}
+
+impl<C: CurveAffine> CommitQuery<C> {
+    pub fn new(
+        key: String,
+        commitment: Option<AstPointRc<C>>,
+        eval: Option<AstScalarRc<C>>,
+    ) -> Self {
+        CommitQuery {
+            key,
+            commitment_ptr_eq: commitment.as_ref().and_then(commitment_ptr),
+            commitment,
+            eval,
+        }
+    }
+
+    // Same key or same commitment node, i.e. the queries share one msm term.
+    // PartialEq only compares keys.
+    pub fn same_msm_term(&self, other: &Self) -> bool {
+        self.key == other.key
+            || (self.commitment_ptr_eq.is_some()
+                && self.commitment_ptr_eq == other.commitment_ptr_eq)
+    }
 }
 
 impl<C: CurveAffine> PartialEq for CommitQuery<C> {
@@ -52,6 +85,51 @@ impl<C: CurveAffine> EvaluationQuerySchema<C> {
     }
 }
 
+impl<C: CurveAffine> EvaluationQuerySchema<C> {
+    // Recursively drop 1 * x, x * 1, 0 + x and x + 0, and fold 0 * x into zero.
+    pub fn simplify(self) -> Self {
+        let is_const = |x: &Self, v: C::ScalarExt| match x {
+            EvaluationQuerySchema::Scalar(s) => s.0.check_const_and_get() == Some(v),
+            _ => false,
+        };
+        let zero = C::ScalarExt::zero();
+        let one = C::ScalarExt::one();
+
+        match self {
+            EvaluationQuerySchema::Add(l, r, _) => {
+                let l = l.as_ref().clone().simplify();
+                let r = r.as_ref().clone().simplify();
+                if is_const(&l, zero) {
+                    r
+                } else if is_const(&r, zero) {
+                    l
+                } else {
+                    let contains_commitment = l.contains_commitment() || r.contains_commitment();
+                    EvaluationQuerySchema::Add(Rc::new(l), Rc::new(r), contains_commitment)
+                }
+            }
+            EvaluationQuerySchema::Mul(l, r, _) => {
+                let l = l.as_ref().clone().simplify();
+                let r = r.as_ref().clone().simplify();
+                if is_const(&l, zero) || is_const(&r, zero) {
+                    EvaluationQuerySchema::Scalar(sconst!(zero))
+                } else if is_const(&l, one) {
+                    r
+                } else if is_const(&r, one) {
+                    l
+                } else {
+                    let contains_commitment = l.contains_commitment() || r.contains_commitment();
+                    EvaluationQuerySchema::Mul(Rc::new(l), Rc::new(r), contains_commitment)
+                }
+            }
+            EvaluationQuerySchema::CheckPoint(tag, x) => {
+                EvaluationQuerySchema::CheckPoint(tag, Rc::new(x.as_ref().clone().simplify()))
+            }
+            x => x,
+        }
+    }
+}
+
 impl<C: CurveAffine> PartialEq for EvaluationQuerySchema<C> {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -75,9 +153,13 @@ pub fn replace_commitment<C: CurveAffine>(
     let mut replaced = false;
     match target.as_ref() {
         EvaluationQuerySchema::Commitment(a) => {
-            if from_key == &a.key {
+            // a commitment already pointing to p is renamed so that it merges with p by key
+            if from_key == &a.key
+                || (a.commitment_ptr_eq.is_some() && a.commitment_ptr_eq == commitment_ptr(p))
+            {
                 let mut a = Rc::as_ref(a).clone();
                 a.commitment = Some(p.clone());
+                a.commitment_ptr_eq = commitment_ptr(p);
                 a.key = to_key.to_owned();
                 target = Rc::new(EvaluationQuerySchema::Commitment(Rc::new(a)));
                 replaced = true;
@@ -111,6 +193,177 @@ pub fn replace_commitment<C: CurveAffine>(
     (target, replaced)
 }
 
+// Rewrites the AST of a single proof built at proof index `from` into the AST of the same
+// proof at index `to`: transcript and instance nodes, commitment keys and the advice
+// commitment checkpoints. Nodes are rewritten once, so shared nodes stay shared.
+pub(crate) struct ProofIndexRewriter<C: CurveAffine> {
+    from: usize,
+    to: usize,
+    transcripts: HashMap<*const AstTranscript<C>, Rc<AstTranscript<C>>>,
+    scalars: HashMap<*const AstScalar<C>, Rc<AstScalar<C>>>,
+    points: HashMap<*const AstPoint<C>, Rc<AstPoint<C>>>,
+    queries: HashMap<*const CommitQuery<C>, Rc<CommitQuery<C>>>,
+    schemas: HashMap<*const EvaluationQuerySchema<C>, Rc<EvaluationQuerySchema<C>>>,
+}
+
+impl<C: CurveAffine> ProofIndexRewriter<C> {
+    pub(crate) fn new(from: usize, to: usize) -> Self {
+        Self {
+            from,
+            to,
+            transcripts: HashMap::new(),
+            scalars: HashMap::new(),
+            points: HashMap::new(),
+            queries: HashMap::new(),
+            schemas: HashMap::new(),
+        }
+    }
+
+    fn index(&self, index: usize) -> usize {
+        assert_eq!(index, self.from);
+        self.to
+    }
+
+    fn key(&self, key: &str) -> String {
+        let from = format_circuit_key(self.from);
+        match key.strip_prefix(&from) {
+            Some(suffix) if suffix.is_empty() || suffix.starts_with('_') => {
+                format!("{}{}", format_circuit_key(self.to), suffix)
+            }
+            _ => key.to_owned(),
+        }
+    }
+
+    fn tag(&self, tag: &str) -> String {
+        let from = format!("advice commitment {} ", self.from);
+        match tag.strip_prefix(&from) {
+            Some(suffix) => format!("advice commitment {} {}", self.to, suffix),
+            None => tag.to_owned(),
+        }
+    }
+
+    pub(crate) fn transcript(&mut self, t: &Rc<AstTranscript<C>>) -> Rc<AstTranscript<C>> {
+        if let Some(t) = self.transcripts.get(&Rc::as_ptr(t)) {
+            return t.clone();
+        }
+
+        let res = Rc::new(match t.as_ref() {
+            AstTranscript::ReadScalar(i, prev) => {
+                AstTranscript::ReadScalar(self.index(*i), self.transcript(prev))
+            }
+            AstTranscript::ReadPoint(i, prev) => {
+                AstTranscript::ReadPoint(self.index(*i), self.transcript(prev))
+            }
+            AstTranscript::CommonScalar(i, prev, s) => {
+                AstTranscript::CommonScalar(self.index(*i), self.transcript(prev), self.scalar(s))
+            }
+            AstTranscript::CommonPoint(i, prev, p) => {
+                AstTranscript::CommonPoint(self.index(*i), self.transcript(prev), self.point(p))
+            }
+            AstTranscript::SqueezeChallenge(i, prev) => {
+                AstTranscript::SqueezeChallenge(self.index(*i), self.transcript(prev))
+            }
+            AstTranscript::Init(i) => AstTranscript::Init(self.index(*i)),
+        });
+        self.transcripts.insert(Rc::as_ptr(t), res.clone());
+        res
+    }
+
+    fn scalar(&mut self, s: &Rc<AstScalar<C>>) -> Rc<AstScalar<C>> {
+        if let Some(s) = self.scalars.get(&Rc::as_ptr(s)) {
+            return s.clone();
+        }
+
+        let res = match s.as_ref() {
+            AstScalar::FromConst(_) => s.clone(),
+            AstScalar::FromTranscript(t) => Rc::new(AstScalar::FromTranscript(self.transcript(t))),
+            AstScalar::FromChallenge(t) => Rc::new(AstScalar::FromChallenge(self.transcript(t))),
+            AstScalar::Add(a, b) => Rc::new(AstScalar::Add(self.scalar(a), self.scalar(b))),
+            AstScalar::Sub(a, b) => Rc::new(AstScalar::Sub(self.scalar(a), self.scalar(b))),
+            AstScalar::Neg(a) => Rc::new(AstScalar::Neg(self.scalar(a))),
+            AstScalar::Mul(a, b, g) => Rc::new(AstScalar::Mul(self.scalar(a), self.scalar(b), *g)),
+            AstScalar::Div(a, b) => Rc::new(AstScalar::Div(self.scalar(a), self.scalar(b))),
+            AstScalar::Pow(a, n) => Rc::new(AstScalar::Pow(self.scalar(a), *n)),
+            AstScalar::CheckPoint(tag, a) => {
+                Rc::new(AstScalar::CheckPoint(self.tag(tag), self.scalar(a)))
+            }
+        };
+        self.scalars.insert(Rc::as_ptr(s), res.clone());
+        res
+    }
+
+    pub(crate) fn point(&mut self, p: &Rc<AstPoint<C>>) -> Rc<AstPoint<C>> {
+        if let Some(p) = self.points.get(&Rc::as_ptr(p)) {
+            return p.clone();
+        }
+
+        let res = match p.as_ref() {
+            AstPoint::FromConst(_) => p.clone(),
+            AstPoint::FromTranscript(t) => Rc::new(AstPoint::FromTranscript(self.transcript(t))),
+            AstPoint::FromInstance(i, col) => Rc::new(AstPoint::FromInstance(self.index(*i), *col)),
+            AstPoint::MultiExp(terms, group) => Rc::new(AstPoint::MultiExp(
+                terms
+                    .iter()
+                    .map(|(p, s)| (self.point(p), self.scalar(s)))
+                    .collect(),
+                *group,
+            )),
+            AstPoint::CheckPoint(tag, a) => {
+                Rc::new(AstPoint::CheckPoint(self.tag(tag), self.point(a)))
+            }
+        };
+        self.points.insert(Rc::as_ptr(p), res.clone());
+        res
+    }
+
+    fn query(&mut self, q: &Rc<CommitQuery<C>>) -> Rc<CommitQuery<C>> {
+        if let Some(q) = self.queries.get(&Rc::as_ptr(q)) {
+            return q.clone();
+        }
+
+        let commitment = q.commitment.as_ref().map(|p| AstPointRc(self.point(&p.0)));
+        let res = Rc::new(CommitQuery {
+            key: self.key(&q.key),
+            // the rewritten commitment nodes are shared like the original ones
+            commitment_ptr_eq: commitment.as_ref().and_then(commitment_ptr),
+            commitment,
+            eval: q.eval.as_ref().map(|s| AstScalarRc(self.scalar(&s.0))),
+        });
+        self.queries.insert(Rc::as_ptr(q), res.clone());
+        res
+    }
+
+    pub(crate) fn schema(
+        &mut self,
+        s: &Rc<EvaluationQuerySchema<C>>,
+    ) -> Rc<EvaluationQuerySchema<C>> {
+        if let Some(s) = self.schemas.get(&Rc::as_ptr(s)) {
+            return s.clone();
+        }
+
+        let res = Rc::new(match s.as_ref() {
+            EvaluationQuerySchema::Commitment(q) => {
+                EvaluationQuerySchema::Commitment(self.query(q))
+            }
+            EvaluationQuerySchema::Eval(q) => EvaluationQuerySchema::Eval(self.query(q)),
+            EvaluationQuerySchema::Scalar(x) => {
+                EvaluationQuerySchema::Scalar(AstScalarRc(self.scalar(&x.0)))
+            }
+            EvaluationQuerySchema::Add(a, b, c) => {
+                EvaluationQuerySchema::Add(self.schema(a), self.schema(b), *c)
+            }
+            EvaluationQuerySchema::Mul(a, b, c) => {
+                EvaluationQuerySchema::Mul(self.schema(a), self.schema(b), *c)
+            }
+            EvaluationQuerySchema::CheckPoint(tag, a) => {
+                EvaluationQuerySchema::CheckPoint(tag.clone(), self.schema(a))
+            }
+        });
+        self.schemas.insert(Rc::as_ptr(s), res.clone());
+        res
+    }
+}
+
 #[repr(transparent)]
 #[derive(Clone, Debug, PartialEq)]
 pub struct EvaluationQuerySchemaRc<C: CurveAffine>(pub Rc<EvaluationQuerySchema<C>>);
@@ -131,11 +384,7 @@ impl<C: CurveAffine> EvaluationQuery<C> {
         commitment: AstPointRc<C>,
         eval: AstScalarRc<C>,
     ) -> Self {
-        let c = Rc::new(CommitQuery {
-            key,
-            commitment: Some(commitment),
-            eval: Some(eval),
-        });
+        let c = Rc::new(CommitQuery::new(key, Some(commitment), Some(eval)));
 
         EvaluationQuery {
             point,
@@ -207,6 +456,30 @@ impl<C: CurveAffine> EvaluationQuerySchema<C> {
             EvaluationQuerySchema::CheckPoint(_, s) => s.contains_commitment(),
         }
     }
+
+    // Number of distinct commitments, i.e. the msm size of eval without the g1 term.
+    // Commitments are merged by key or by node as in eval.
+    pub fn count_commitments(&self) -> usize {
+        let mut commitments = vec![];
+        self.collect_commitments(&mut commitments);
+        commitments.len()
+    }
+
+    fn collect_commitments<'a>(&'a self, commitments: &mut Vec<&'a CommitQuery<C>>) {
+        match self {
+            EvaluationQuerySchema::Commitment(c) => {
+                if !commitments.iter().any(|x| x.same_msm_term(c)) {
+                    commitments.push(c.as_ref());
+                }
+            }
+            EvaluationQuerySchema::Add(l, r, true) | EvaluationQuerySchema::Mul(l, r, true) => {
+                l.collect_commitments(commitments);
+                r.collect_commitments(commitments);
+            }
+            EvaluationQuerySchema::CheckPoint(_, s) => s.collect_commitments(commitments),
+            _ => {}
+        }
+    }
 }
 
 impl<C: CurveAffine> Add<EvaluationQuerySchemaRc<C>> for EvaluationQuerySchemaRc<C> {
@@ -358,6 +631,8 @@ impl<C: CurveAffine> EvaluationQuerySchemaRc<C> {
                     if let Some(sl) = pl.get_mut(&k) {
                         assert!(Rc::ptr_eq(&sl.0 .0, &p.0));
                         sl.1 = &sl.1 + sr;
+                    } else if let Some(sl) = pl.values_mut().find(|sl| Rc::ptr_eq(&sl.0 .0, &p.0)) {
+                        sl.1 = &sl.1 + sr;
                     } else {
                         pl.insert(k, (p, sr));
                     }
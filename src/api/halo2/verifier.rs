@@ -11,13 +11,21 @@ use super::query::EvaluationQuerySchemaRc;
 use crate::api::arith::AstPointRc;
 use crate::api::arith::AstScalar;
 use crate::api::arith::AstScalarRc;
+use crate::api::ast_eval::EvalContext;
 use crate::commit;
 use crate::echeckpoint;
+use crate::native_verifier::NativeEvalContext;
 use crate::scalar;
 use crate::sconst;
 use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::Field;
+use halo2_proofs::arithmetic::MillerLoopResult;
+use halo2_proofs::arithmetic::MultiMillerLoop;
+use halo2_proofs::pairing::group::Group;
 use halo2_proofs::plonk::Expression;
+use halo2_proofs::poly::commitment::ParamsVerifier;
+use halo2_proofs::transcript::EncodedChallenge;
+use halo2_proofs::transcript::TranscriptRead;
 use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::iter;
@@ -72,18 +80,87 @@ pub struct VerifierParams<C: CurveAffine> {
     pub ls: Vec<AstScalarRc<C>>,
     pub l_blind: AstScalarRc<C>,
 }
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifierError {
+    ColumnOutOfBounds { col: usize, n_advice: usize },
+    InstanceColumnOutOfBounds { col: usize, n_instance: usize },
+    FixedColumnOutOfBounds { col: usize, n_fixed: usize },
+}
+
 pub struct EvaluationProof<C: CurveAffine> {
     pub point: AstScalarRc<C>,
     pub s: EvaluationQuerySchemaRc<C>,
     pub w: AstPointRc<C>,
 }
 
+#[derive(Clone)]
 pub struct MultiOpenProof<C: CurveAffine> {
     pub w_x: EvaluationQuerySchemaRc<C>,
     pub w_g: EvaluationQuerySchemaRc<C>,
 }
 
+impl<C: CurveAffine> MultiOpenProof<C> {
+    // Evaluate the opening natively with the proof transcripts and check the final pairing.
+    pub fn verify_native<
+        E: MultiMillerLoop<G1Affine = C>,
+        EC: EncodedChallenge<C>,
+        T: TranscriptRead<C, EC>,
+    >(
+        self,
+        params: &ParamsVerifier<E>,
+        g1: C,
+        instance_commitments: Vec<Vec<C>>,
+        t: Vec<T>,
+    ) -> bool {
+        let w_x = self.w_x.eval(g1, 0);
+        let w_g = self.w_g.eval(-g1, 1);
+
+        let c = EvalContext::translate(&[w_x.0, w_g.0]);
+        let mut ctx = NativeEvalContext::<E, EC, T>::new(c, instance_commitments, t);
+        ctx.context_eval();
+
+        let s_g2_prepared = E::G2Prepared::from(params.s_g2);
+        let n_g2_prepared = E::G2Prepared::from(-params.g2);
+        bool::from(
+            E::multi_miller_loop(&[
+                (&ctx.finals[0], &s_g2_prepared),
+                (&ctx.finals[1], &n_g2_prepared),
+            ])
+            .final_exponentiation()
+            .is_identity(),
+        )
+    }
+}
+
 impl<C: CurveAffine> VerifierParams<C> {
+    pub fn advice_commitment_at(&self, col: usize) -> Result<&AstPointRc<C>, VerifierError> {
+        self.advice_commitments
+            .get(col)
+            .ok_or(VerifierError::ColumnOutOfBounds {
+                col,
+                n_advice: self.advice_commitments.len(),
+            })
+    }
+
+    pub fn instance_commitment_at(&self, col: usize) -> Result<&AstPointRc<C>, VerifierError> {
+        self.instance_commitments
+            .get(col)
+            .ok_or(VerifierError::InstanceColumnOutOfBounds {
+                col,
+                n_instance: self.instance_commitments.len(),
+            })
+    }
+
+    pub fn fixed_commitment_at(&self, col: usize) -> Result<&AstPointRc<C>, VerifierError> {
+        self.fixed_commitments
+            .get(col)
+            .ok_or(VerifierError::FixedColumnOutOfBounds {
+                col,
+                n_fixed: self.fixed_commitments.len(),
+            })
+    }
+
     pub(crate) fn evaluate_expression(&self, expr: &Expression<C::ScalarExt>) -> AstScalarRc<C> {
         match expr {
             Expression::Constant(c) => sconst!(*c),
@@ -91,7 +168,7 @@ impl<C: CurveAffine> VerifierParams<C> {
             Expression::Fixed { query_index, .. } => self.fixed_evals[*query_index].clone(),
             Expression::Advice { query_index, .. } => self.advice_evals[*query_index].clone(),
             Expression::Instance { query_index, .. } => self.instance_evals[*query_index].clone(),
-            Expression::Negated(a) => sconst!(C::ScalarExt::zero()) - self.evaluate_expression(a),
+            Expression::Negated(a) => -self.evaluate_expression(a),
             Expression::Sum(a, b) => self.evaluate_expression(a) + self.evaluate_expression(b),
             Expression::Product(a, b) => self.evaluate_expression(a) * self.evaluate_expression(b),
             Expression::Scaled(a, b) => sconst!(*b) * self.evaluate_expression(a),
@@ -124,7 +201,7 @@ impl<C: CurveAffine> VerifierParams<C> {
         sconst!(omega_at) * &self.x
     }
 
-    fn get_all_queries(&self) -> Vec<EvaluationQuery<C>> {
+    pub(crate) fn get_all_queries(&self) -> Vec<EvaluationQuery<C>> {
         let expression_evals = self
             .get_all_expression_evals()
             .into_iter()
@@ -134,9 +211,7 @@ impl<C: CurveAffine> VerifierParams<C> {
 
         let mut queries = vec![];
         {
-            let instance_commitments = &self.instance_commitments;
             let instance_evals = &self.instance_evals;
-            let advice_commitments = &self.advice_commitments;
             let advice_evals = &self.advice_evals;
             let permutation = &self.permutation_evaluated;
             let lookups = &self.lookup_evaluated;
@@ -147,7 +222,7 @@ impl<C: CurveAffine> VerifierParams<C> {
                     at,
                     self.x_rotate(at),
                     format_instance_commitment_key(&self.key, column),
-                    instance_commitments[column].clone(),
+                    self.instance_commitment_at(column).unwrap().clone(),
                     instance_evals[query_index].clone(),
                 ))
             }
@@ -157,7 +232,7 @@ impl<C: CurveAffine> VerifierParams<C> {
                     at,
                     self.x_rotate(at),
                     format_advice_commitment_key(&self.key, column),
-                    advice_commitments[column].clone(),
+                    self.advice_commitment_at(column).unwrap().clone(),
                     advice_evals[query_index].clone(),
                 ))
             }
@@ -172,7 +247,7 @@ impl<C: CurveAffine> VerifierParams<C> {
                 at,
                 self.x_rotate(at),
                 format_fixed_commitment_key(&self.key, column),
-                self.fixed_commitments[column].clone(),
+                self.fixed_commitment_at(column).unwrap().clone(),
                 self.fixed_evals[query_index].clone(),
             ))
         }
@@ -201,9 +276,11 @@ impl<C: CurveAffine> VerifierParams<C> {
         queries
     }
 
-    fn get_point_schemas_gwc(&self) -> Vec<EvaluationProof<C>> {
+    pub(crate) fn get_point_schemas_gwc(&self) -> Vec<EvaluationProof<C>> {
         let queries = self.get_all_queries();
 
+        // Queries are grouped by rotation, so each point has exactly one evaluation proof
+        // and one w commitment in the proof, there is nothing left to merge across groups.
         let mut queries_groups: BTreeMap<i32, (_, Vec<_>)> = BTreeMap::new();
 
         for query in queries {
@@ -253,11 +330,11 @@ impl<C: CurveAffine> VerifierParams<C> {
 
         for (i, p) in proofs.into_iter().enumerate() {
             let s = &p.s;
-            let w = Rc::new(CommitQuery {
-                key: format!("{}_w{}", self.key, i),
-                commitment: Some(p.w.clone()),
-                eval: None,
-            });
+            let w = Rc::new(CommitQuery::new(
+                format!("{}_w{}", self.key, i),
+                Some(p.w.clone()),
+                None,
+            ));
 
             w_x = w_x.map_or(Some(commit!(w.clone())), |w_x| {
                 Some(scalar!(u.clone()) * w_x + commit!(w.clone()))
@@ -506,21 +583,21 @@ impl<C: CurveAffine> VerifierParams<C> {
             };
         }
 
-        let h1 = commit!(Rc::new(CommitQuery {
-            key: format!("{}_h1", self.key),
-            commitment: Some(h1),
-            eval: None,
-        }));
+        let h1 = commit!(Rc::new(CommitQuery::new(
+            format!("{}_h1", self.key),
+            Some(h1),
+            None,
+        )));
 
-        let h2 = commit!(Rc::new(CommitQuery {
-            key: format!("{}_h2", self.key),
-            commitment: Some(h2),
-            eval: None,
-        }));
+        let h2 = commit!(Rc::new(CommitQuery::new(
+            format!("{}_h2", self.key),
+            Some(h2),
+            None,
+        )));
 
         let mut outer_msm = outer_msm.unwrap();
         outer_msm = outer_msm + scalar!(r_outer_acc);
-        outer_msm = outer_msm + scalar!(sconst!(C::ScalarExt::zero()) - z_0.unwrap()) * h1;
+        outer_msm = outer_msm + scalar!(-z_0.unwrap()) * h1;
         outer_msm = outer_msm + scalar!(u) * h2.clone();
 
         MultiOpenProof {
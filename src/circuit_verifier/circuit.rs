@@ -1,6 +1,15 @@
+use super::dry_run_aggregate_verify_circuit;
+use super::estimate_aggregate_verify_circuit_rows;
+use super::G2AffineBaseHelper;
+use super::GtHelper;
+use crate::circuits::utils::AggregatorConfig;
+use crate::circuits::utils::TranscriptHash;
 use ark_std::end_timer;
 use ark_std::start_timer;
 use halo2_proofs::arithmetic::CurveAffine;
+use halo2_proofs::arithmetic::FieldExt;
+use halo2_proofs::arithmetic::MultiMillerLoop;
+use halo2_proofs::arithmetic::MultiMillerLoopOnProvePairing;
 use halo2_proofs::circuit::floor_planner::FlatFloorPlanner;
 use halo2_proofs::circuit::Layouter;
 use halo2_proofs::plonk::Circuit;
@@ -8,19 +17,25 @@ use halo2_proofs::plonk::Column;
 use halo2_proofs::plonk::ConstraintSystem;
 use halo2_proofs::plonk::Error;
 use halo2_proofs::plonk::Instance;
+use halo2_proofs::plonk::VerifyingKey;
+use halo2_proofs::poly::commitment::ParamsVerifier;
 use halo2ecc_s::assign::AssignedValue;
 use halo2ecc_s::circuit::base_chip::BaseChip;
 use halo2ecc_s::circuit::base_chip::BaseChipConfig;
+use halo2ecc_s::circuit::pairing_chip::PairingChipOnProvePairingOps;
 use halo2ecc_s::circuit::range_chip::RangeChip;
 use halo2ecc_s::circuit::range_chip::RangeChipConfig;
 use halo2ecc_s::circuit::select_chip::SelectChip;
 use halo2ecc_s::circuit::select_chip::SelectChipConfig;
+use halo2ecc_s::context::NativeScalarEccContext;
 use halo2ecc_s::context::Records;
 use std::rc::Rc;
 
 pub struct AggregatorCircuitOption<C: CurveAffine> {
     pub circuit_with_select_chip: Option<AggregatorCircuit<C>>,
     pub circuit_without_select_chip: Option<AggregatorNoSelectCircuit<C>>,
+    // max offset among base, range and select regions, 0 if unknown
+    pub rows_used: usize,
 }
 
 impl<C: CurveAffine> From<AggregatorCircuit<C>> for AggregatorCircuitOption<C> {
@@ -28,6 +43,7 @@ impl<C: CurveAffine> From<AggregatorCircuit<C>> for AggregatorCircuitOption<C> {
         Self {
             circuit_with_select_chip: Some(circuit_with_select_chip),
             circuit_without_select_chip: None,
+            rows_used: 0,
         }
     }
 }
@@ -37,6 +53,49 @@ impl<C: CurveAffine> From<AggregatorNoSelectCircuit<C>> for AggregatorCircuitOpt
         Self {
             circuit_with_select_chip: None,
             circuit_without_select_chip: Some(circuit_without_select_chip),
+            rows_used: 0,
+        }
+    }
+}
+
+// Rows consumed by each part of the aggregator, a row is counted by the max offset
+// among base, range and select regions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RowUtilization {
+    pub pairing_rows: usize,
+    pub msm_rows: usize,
+    pub transcript_rows: usize,
+    pub instance_encoding_rows: usize,
+    pub total_rows: usize,
+    pub max_rows: usize,
+}
+
+impl RowUtilization {
+    pub fn fraction(&self) -> f64 {
+        self.total_rows as f64 / self.max_rows as f64
+    }
+}
+
+impl<C: CurveAffine> AggregatorCircuitOption<C> {
+    pub fn row_utilization(&self, k: u32) -> RowUtilization {
+        match (
+            &self.circuit_with_select_chip,
+            &self.circuit_without_select_chip,
+        ) {
+            (Some(c), _) => c.row_utilization(k),
+            (_, Some(c)) => c.row_utilization(k),
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn constraint_count(&self) -> usize {
+        match (
+            &self.circuit_with_select_chip,
+            &self.circuit_without_select_chip,
+        ) {
+            (Some(c), _) => c.constraint_count(),
+            (_, Some(c)) => c.constraint_count(),
+            _ => unreachable!(),
         }
     }
 }
@@ -53,11 +112,84 @@ pub struct AggregatorChipConfig {
 pub struct AggregatorCircuit<C: CurveAffine> {
     pub records: Rc<Records<C::Scalar>>,
     instances: Vec<AssignedValue<C::Scalar>>,
+    pub(crate) rows: RowUtilization,
 }
 
 impl<C: CurveAffine> AggregatorCircuit<C> {
     pub fn new(records: Rc<Records<C::Scalar>>, instances: Vec<AssignedValue<C::Scalar>>) -> Self {
-        Self { records, instances }
+        Self {
+            records,
+            instances,
+            rows: RowUtilization::default(),
+        }
+    }
+
+    // Rows recorded while building the circuit against the 2^k row limit.
+    pub fn row_utilization(&self, k: u32) -> RowUtilization {
+        RowUtilization {
+            max_rows: 1 << k,
+            ..self.rows.clone()
+        }
+    }
+
+    // Advice cells of the recorded rows, an upper bound of the assigned cells without
+    // running keygen or synthesize.
+    pub fn constraint_count(&self) -> usize {
+        let mut meta = ConstraintSystem::default();
+        Self::configure(&mut meta);
+        self.rows.total_rows * meta.num_advice_columns
+    }
+
+    // Smallest k such that 2^k exceeds the rows of the aggregator with 10% margin.
+    // params are the verifier params of the target circuits, the rows are counted on a
+    // dry run against them, so no setup of the aggregator params is required.
+    pub fn estimate_k<
+        E: MultiMillerLoop<G1Affine = C, Scalar = C::Scalar>
+            + G2AffineBaseHelper
+            + GtHelper
+            + MultiMillerLoopOnProvePairing,
+    >(
+        params: &ParamsVerifier<E>,
+        vkeys: &[&VerifyingKey<E::G1Affine>],
+        config: &AggregatorConfig<E::Scalar>,
+    ) -> u32
+    where
+        NativeScalarEccContext<C>: PairingChipOnProvePairingOps<C, C::Scalar>,
+    {
+        let rows = estimate_aggregate_verify_circuit_rows(params, vkeys, config).total_rows;
+        let required = rows + rows / 10;
+
+        let mut k = 1;
+        while (1usize << k) <= required {
+            k += 1;
+        }
+        k
+    }
+
+    // Rows taken by the pairing check of an aggregator over vkeys with the default config,
+    // measured the same way as estimate_k.
+    pub fn recommended_pairing_rows<
+        E: MultiMillerLoop<G1Affine = C, Scalar = C::Scalar>
+            + G2AffineBaseHelper
+            + GtHelper
+            + MultiMillerLoopOnProvePairing,
+    >(
+        params: &ParamsVerifier<E>,
+        vkeys: &[&VerifyingKey<E::G1Affine>],
+    ) -> usize
+    where
+        NativeScalarEccContext<C>: PairingChipOnProvePairingOps<C, C::Scalar>,
+    {
+        let config = AggregatorConfig::default_aggregator_config(
+            TranscriptHash::Poseidon,
+            vkeys
+                .iter()
+                .map(|vk| vec![1; vk.cs.num_instance_columns])
+                .collect(),
+            false,
+        );
+
+        estimate_aggregate_verify_circuit_rows(params, vkeys, &config).pairing_rows
     }
 }
 
@@ -86,56 +218,133 @@ impl<C: CurveAffine> Circuit<C::Scalar> for AggregatorCircuit<C> {
     fn synthesize(
         &self,
         config: Self::Config,
-        mut layouter: impl Layouter<C::Scalar>,
+        layouter: impl Layouter<C::Scalar>,
     ) -> Result<(), Error> {
-        let timer = start_timer!(|| "synthesize");
+        synthesize_records(&self.records, &self.instances, config, layouter)
+    }
+}
 
-        let base_chip = BaseChip::new(config.base_chip_config);
-        let range_chip = RangeChip::<C::Scalar>::new(config.range_chip_config);
-        let select_chip = SelectChip::new(config.select_chip_config);
+// Assign the recorded cells and constrain the instance cells, shared by the aggregator
+// circuit and its skeleton.
+fn synthesize_records<F: FieldExt>(
+    records: &Records<F>,
+    instances: &[AssignedValue<F>],
+    config: AggregatorChipConfig,
+    mut layouter: impl Layouter<F>,
+) -> Result<(), Error> {
+    let timer = start_timer!(|| "synthesize");
+
+    let base_chip = BaseChip::new(config.base_chip_config);
+    let range_chip = RangeChip::<F>::new(config.range_chip_config);
+    let select_chip = SelectChip::new(config.select_chip_config);
+
+    let instance_cells = layouter.assign_region(
+        || "base",
+        |mut region| {
+            let timer = start_timer!(|| "assign");
+            let cells = records.assign_all(&mut region, &base_chip, &range_chip, &select_chip)?;
+
+            let r = Some(
+                instances
+                    .iter()
+                    .map(|instance| {
+                        cells[instance.cell.region as usize][instance.cell.col][instance.cell.row]
+                            .as_ref()
+                            .unwrap()
+                            .cell()
+                            .clone()
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            end_timer!(timer);
+
+            Ok(r)
+        },
+    )?;
+
+    match instance_cells {
+        Some(instance_cells) => {
+            range_chip.init_table(&mut layouter)?;
+
+            for (i, cell) in instance_cells.into_iter().enumerate() {
+                layouter.constrain_instance(cell, config.instance_col, i)?;
+            }
+        }
+        // skip on row check synthesize
+        None => {}
+    };
 
-        let instance_cells = layouter.assign_region(
-            || "base",
-            |mut region| {
-                let timer = start_timer!(|| "assign");
-                let cells =
-                    self.records
-                        .assign_all(&mut region, &base_chip, &range_chip, &select_chip)?;
+    end_timer!(timer);
 
-                let r = Some(
-                    self.instances
-                        .iter()
-                        .map(|instance| {
-                            cells[instance.cell.region as usize][instance.cell.col]
-                                [instance.cell.row]
-                                .as_ref()
-                                .unwrap()
-                                .cell()
-                                .clone()
-                        })
-                        .collect::<Vec<_>>(),
-                );
-                end_timer!(timer);
+    Ok(())
+}
 
-                Ok(r)
-            },
-        )?;
+impl<C: CurveAffine> AggregatorCircuit<C> {
+    // A keygen_vk/keygen_pk stand-in that holds only the params, vkeys and config of the
+    // aggregator instead of the records of a built circuit. Its synthesize builds the
+    // aggregator against dummy proofs, the witness values never reach keygen.
+    pub fn without_witnesses_light<
+        E: MultiMillerLoop<G1Affine = C, Scalar = C::Scalar>
+            + G2AffineBaseHelper
+            + GtHelper
+            + MultiMillerLoopOnProvePairing,
+    >(
+        params: ParamsVerifier<E>,
+        vkeys: Vec<VerifyingKey<E::G1Affine>>,
+        config: AggregatorConfig<E::Scalar>,
+    ) -> AggregatorCircuitSkeleton<E> {
+        // The skeleton has the layout of the circuit with select chip.
+        let config = AggregatorConfig {
+            use_select_chip: true,
+            ..config
+        };
+        AggregatorCircuitSkeleton {
+            params: Rc::new(params),
+            vkeys: Rc::new(vkeys),
+            config: Rc::new(config),
+        }
+    }
+}
 
-        match instance_cells {
-            Some(instance_cells) => {
-                range_chip.init_table(&mut layouter)?;
+pub struct AggregatorCircuitSkeleton<E: MultiMillerLoop> {
+    params: Rc<ParamsVerifier<E>>,
+    vkeys: Rc<Vec<VerifyingKey<E::G1Affine>>>,
+    config: Rc<AggregatorConfig<E::Scalar>>,
+}
 
-                for (i, cell) in instance_cells.into_iter().enumerate() {
-                    layouter.constrain_instance(cell, config.instance_col, i)?;
-                }
-            }
-            // skip on row check synthesize
-            None => {}
-        };
+impl<E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing>
+    Circuit<E::Scalar> for AggregatorCircuitSkeleton<E>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    type Config = AggregatorChipConfig;
+    type FloorPlanner = FlatFloorPlanner;
 
-        end_timer!(timer);
+    fn without_witnesses(&self) -> Self {
+        Self {
+            params: self.params.clone(),
+            vkeys: self.vkeys.clone(),
+            config: self.config.clone(),
+        }
+    }
 
-        Ok(())
+    fn configure(meta: &mut ConstraintSystem<E::Scalar>) -> Self::Config {
+        AggregatorCircuit::<E::G1Affine>::configure(meta)
+    }
+
+    fn synthesize(
+        &self,
+        config: Self::Config,
+        layouter: impl Layouter<E::Scalar>,
+    ) -> Result<(), Error> {
+        let circuit = dry_run_aggregate_verify_circuit(
+            &self.params,
+            &self.vkeys.iter().collect::<Vec<_>>()[..],
+            &self.config,
+        )
+        .circuit_with_select_chip
+        .unwrap();
+        synthesize_records(&circuit.records, &circuit.instances, config, layouter)
     }
 }
 
@@ -151,11 +360,32 @@ pub struct AggregatorNoSelectChipConfig {
 pub struct AggregatorNoSelectCircuit<C: CurveAffine> {
     pub records: Rc<Records<C::Scalar>>,
     instances: Vec<AssignedValue<C::Scalar>>,
+    pub(crate) rows: RowUtilization,
 }
 
 impl<C: CurveAffine> AggregatorNoSelectCircuit<C> {
     pub fn new(records: Rc<Records<C::Scalar>>, instances: Vec<AssignedValue<C::Scalar>>) -> Self {
-        Self { records, instances }
+        Self {
+            records,
+            instances,
+            rows: RowUtilization::default(),
+        }
+    }
+
+    // Rows recorded while building the circuit against the 2^k row limit.
+    pub fn row_utilization(&self, k: u32) -> RowUtilization {
+        RowUtilization {
+            max_rows: 1 << k,
+            ..self.rows.clone()
+        }
+    }
+
+    // Advice cells of the recorded rows, an upper bound of the assigned cells without
+    // running keygen or synthesize.
+    pub fn constraint_count(&self) -> usize {
+        let mut meta = ConstraintSystem::default();
+        Self::configure(&mut meta);
+        self.rows.total_rows * meta.num_advice_columns
     }
 }
 
@@ -6,12 +6,17 @@ use crate::api::halo2::verify_aggregation_proofs;
 use crate::circuit_verifier::circuit::AggregatorCircuit;
 use crate::circuit_verifier::circuit::AggregatorCircuitOption;
 use crate::circuit_verifier::circuit::AggregatorNoSelectCircuit;
+use crate::circuit_verifier::circuit::RowUtilization;
 use crate::circuits::utils::instance_to_instance_commitment;
 use crate::circuits::utils::miller_loop_compute_c_wi;
+use crate::circuits::utils::shplonk_proof_indices;
 use crate::circuits::utils::AggregatorConfig;
 use crate::circuits::utils::TranscriptHash;
+use crate::transcript::poseidon::PoseidonEncodedChallenge;
 use crate::transcript::poseidon::PoseidonPure;
 use crate::transcript::poseidon::PoseidonRead;
+use crate::transcript::poseidon::PoseidonWrite;
+use ark_std::rand::rngs::OsRng;
 use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::Field;
 use halo2_proofs::arithmetic::MillerLoopResult;
@@ -21,9 +26,11 @@ use halo2_proofs::pairing::bn256::Bn256;
 use halo2_proofs::pairing::bn256::Fq;
 use halo2_proofs::pairing::bn256::Fq2;
 use halo2_proofs::pairing::group::prime::PrimeCurveAffine;
+use halo2_proofs::pairing::group::Curve;
 use halo2_proofs::pairing::group::Group;
 use halo2_proofs::plonk::VerifyingKey;
 use halo2_proofs::poly::commitment::ParamsVerifier;
+use halo2_proofs::transcript::TranscriptWrite;
 use halo2ecc_s::assign::AssignedFq2;
 use halo2ecc_s::assign::AssignedPoint;
 use halo2ecc_s::assign::AssignedValue;
@@ -35,15 +42,43 @@ use halo2ecc_s::circuit::fq12::Fq12ChipOps;
 use halo2ecc_s::circuit::keccak_chip::KeccakChipOps;
 use halo2ecc_s::circuit::pairing_chip::PairingChipOnProvePairingOps;
 use halo2ecc_s::circuit::pairing_chip::PairingChipOps;
+use halo2ecc_s::circuit::range_chip::MAX_BITS;
+use halo2ecc_s::circuit::range_chip::RANGE_VALUE_DECOMPOSE;
 use halo2ecc_s::context::Context;
 use halo2ecc_s::context::IntegerContext;
 use halo2ecc_s::context::NativeScalarEccContext;
+use halo2ecc_s::utils::bn_to_field;
+use halo2ecc_s::utils::field_to_bn;
+use num_bigint::BigUint;
 use std::cell::RefCell;
 use std::io;
 use std::rc::Rc;
 pub mod circuit;
 pub mod transcript;
 
+fn current_rows<C: CurveAffine>(circuit: &NativeScalarEccContext<C>) -> usize {
+    let ctx = circuit.0.ctx.borrow();
+    ctx.base_offset.max(ctx.range_offset).max(ctx.select_offset)
+}
+
+// Left-to-right square-and-multiply, a^n costs floor(log2(n)) squares and
+// popcount(n) - 1 multiplications.
+fn scalar_pow<C: CurveAffine>(
+    circuit: &mut NativeScalarEccContext<C>,
+    a: &AssignedValue<C::Scalar>,
+    n: u32,
+) -> AssignedValue<C::Scalar> {
+    assert!(n > 0);
+    let mut acc = a.clone();
+    for i in (0..31 - n.leading_zeros()).rev() {
+        acc = circuit.base_integer_chip().base_chip().mul(&acc, &acc);
+        if (n >> i) & 1 == 1 {
+            acc = circuit.base_integer_chip().base_chip().mul(&acc, a);
+        }
+    }
+    acc
+}
+
 fn context_eval<E: MultiMillerLoop, R: io::Read>(
     c: EvalContext<E::G1Affine>,
     instance_commitments: &[&[E::G1Affine]],
@@ -51,6 +86,7 @@ fn context_eval<E: MultiMillerLoop, R: io::Read>(
     circuit: &mut NativeScalarEccContext<E::G1Affine>,
     // Expose hash of constant value to instance to uniform the aggregator circuit
     constants_hasher: &mut PoseidonChipRead<R, E::G1Affine>,
+    rows: &mut RowUtilization,
 ) -> Result<
     (
         Vec<AssignedPoint<E::G1Affine, E::Scalar>>,
@@ -130,7 +166,11 @@ fn context_eval<E: MultiMillerLoop, R: io::Read>(
         };
     }
 
+    // assigned on the first ScalarInv
+    let mut assigned_one = None;
+
     for (_, op) in c.ops.iter().enumerate() {
+        let start = current_rows(circuit);
         it.push(match op {
             EvalOps::TranscriptReadScalar(i, _) => {
                 let s = t[*i].read_scalar(circuit);
@@ -167,6 +207,15 @@ fn context_eval<E: MultiMillerLoop, R: io::Read>(
                         .sub(eval_scalar_pos!(a), eval_scalar_pos!(b)),
                 ),
             ),
+            EvalOps::ScalarNeg(a) => (
+                None,
+                Some(
+                    circuit
+                        .base_integer_chip()
+                        .base_chip()
+                        .sum_with_constant(vec![(eval_scalar_pos!(a), -E::Scalar::one())], None),
+                ),
+            ),
             EvalOps::ScalarMul(a, b, _) => (
                 None,
                 Some(
@@ -185,21 +234,27 @@ fn context_eval<E: MultiMillerLoop, R: io::Read>(
                         .div_unsafe(eval_scalar_pos!(a), eval_scalar_pos!(b)),
                 ),
             ),
+            EvalOps::ScalarInv(a) => {
+                let one = assigned_one
+                    .get_or_insert_with(|| {
+                        circuit
+                            .base_integer_chip()
+                            .base_chip()
+                            .assign_constant(E::Scalar::one())
+                    })
+                    .clone();
+                (
+                    None,
+                    Some(
+                        circuit
+                            .base_integer_chip()
+                            .base_chip()
+                            .div_unsafe(&one, eval_scalar_pos!(a)),
+                    ),
+                )
+            }
             EvalOps::ScalarPow(a, n) => {
-                let mut p = *n;
-                let mut c = vec![];
-                let mut acc = eval_scalar_pos!(a).clone();
-                while p > 0 {
-                    if p & 1 == 1 {
-                        c.push(acc);
-                    }
-                    acc = circuit.base_integer_chip().base_chip().mul(&acc, &acc);
-                    p >>= 1;
-                }
-                let s = c
-                    .into_iter()
-                    .reduce(|acc, x| circuit.base_integer_chip().base_chip().mul(&acc, &x))
-                    .unwrap();
+                let s = scalar_pow(circuit, eval_scalar_pos!(a), *n);
                 (None, Some(s))
             }
             EvalOps::MSM(psl, _) => {
@@ -227,6 +282,17 @@ fn context_eval<E: MultiMillerLoop, R: io::Read>(
                 (None, None)
             }
         });
+
+        let used = current_rows(circuit) - start;
+        match op {
+            EvalOps::TranscriptReadScalar(..)
+            | EvalOps::TranscriptReadPoint(..)
+            | EvalOps::TranscriptCommonScalar(..)
+            | EvalOps::TranscriptCommonPoint(..)
+            | EvalOps::TranscriptSqueeze(..) => rows.transcript_rows += used,
+            EvalOps::MSM(..) => rows.msm_rows += used,
+            _ => {}
+        }
     }
     Ok((
         c.finals
@@ -287,6 +353,74 @@ where
     res.unwrap()
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EncodeError {
+    IdentityPoint,
+}
+
+// Same as encode_point, but rejects the identity instead of encoding it as (0, 0).
+pub fn encode_point_checked<C: CurveAffine>(p: &C) -> Result<[C::Scalar; 3], EncodeError> {
+    if bool::from(p.is_identity()) {
+        return Err(EncodeError::IdentityPoint);
+    }
+    Ok(encode_point(p))
+}
+
+// Same as ecc_encode in circuit: x and y are split into 3 limbs,
+// limb0 = x[0..2b], limb1 = x[2b..] + y[0..b] << b, limb2 = y[b..], b = RANGE_VALUE_DECOMPOSE * MAX_BITS.
+// The identity is encoded as (0, 0), which the circuit does not distinguish from a point
+// with these coordinates, so callers must not pass the identity, see encode_point_checked.
+pub fn encode_point<C: CurveAffine>(p: &C) -> [C::Scalar; 3] {
+    let x_y: Option<_> = p.coordinates().map(|c| (*c.x(), *c.y())).into();
+    let (x, y) = x_y.unwrap_or((C::Base::zero(), C::Base::zero()));
+    let x_bn = field_to_bn(&x);
+    let y_bn = field_to_bn(&y);
+
+    let bits = RANGE_VALUE_DECOMPOSE * MAX_BITS;
+    let chunk_bits = bits * 2;
+
+    let chunk0 = &x_bn & ((BigUint::from(1u64) << chunk_bits) - 1u64);
+    let chunk1 = (x_bn >> chunk_bits) + ((&y_bn & ((BigUint::from(1u64) << bits) - 1u64)) << bits);
+    let chunk2 = y_bn >> bits;
+
+    [
+        bn_to_field(&chunk0),
+        bn_to_field(&chunk1),
+        bn_to_field(&chunk2),
+    ]
+}
+
+pub fn decode_point<C: CurveAffine>(limbs: [C::Scalar; 3]) -> Option<C> {
+    let bits = RANGE_VALUE_DECOMPOSE * MAX_BITS;
+    let chunk_bits = bits * 2;
+
+    let chunk0 = field_to_bn(&limbs[0]);
+    let chunk1 = field_to_bn(&limbs[1]);
+    let chunk2 = field_to_bn(&limbs[2]);
+
+    if chunk0 >= (BigUint::from(1u64) << chunk_bits) {
+        return None;
+    }
+
+    let x_bn = chunk0 + ((&chunk1 & ((BigUint::from(1u64) << bits) - 1u64)) << chunk_bits);
+    let y_bn = (chunk1 >> bits) + (chunk2 << bits);
+
+    let x: C::Base = bn_to_field(&x_bn);
+    let y: C::Base = bn_to_field(&y_bn);
+
+    // Reject non-canonical encodings.
+    if field_to_bn(&x) != x_bn || field_to_bn(&y) != y_bn {
+        return None;
+    }
+
+    // Identity is encoded as (0, 0).
+    if x.is_zero_vartime() && y.is_zero_vartime() {
+        return None;
+    }
+
+    C::from_xy(x, y).into()
+}
+
 pub trait G2AffineBaseHelper: MultiMillerLoop {
     fn decode(
         b: <Self::G2Affine as CurveAffine>::Base,
@@ -365,6 +499,99 @@ impl GtHelper for Bn256 {
  * prev_constant_hash: all previous constant_hash (hash of all circuits' constant values) of aggregators layer
  * layer_idx: current aggregator's layer index
  */
+// Rows of the aggregator built against dummy proofs and instances.
+// max_rows of the result is not meaningful, as no k is chosen yet.
+pub(crate) fn estimate_aggregate_verify_circuit_rows<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+>(
+    params: &ParamsVerifier<E>,
+    vkey: &[&VerifyingKey<E::G1Affine>],
+    config: &AggregatorConfig<E::Scalar>,
+) -> RowUtilization
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    dry_run_aggregate_verify_circuit(params, vkey, config).row_utilization(0)
+}
+
+// Build the aggregator against dummy proofs and instances to measure it.
+// Native checks on values are skipped, only the layout of the circuit is meaningful.
+pub(crate) fn dry_run_aggregate_verify_circuit<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+>(
+    params: &ParamsVerifier<E>,
+    vkey: &[&VerifyingKey<E::G1Affine>],
+    config: &AggregatorConfig<E::Scalar>,
+) -> AggregatorCircuitOption<E::G1Affine>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    let (w_x, w_g, _) = verify_aggregation_proofs(
+        params,
+        vkey,
+        &config.commitment_check,
+        false,
+        &shplonk_proof_indices(&config.proof_protocols(vkey.len())),
+    );
+    let c = EvalContext::translate(&[w_x.0, w_g.0]);
+
+    let mut rest_tries = 100;
+    let mut res = None;
+
+    while rest_tries > 0 && res.is_none() {
+        // Reads of each transcript are kept in order by the topological sort.
+        let mut writers = vkey
+            .iter()
+            .map(|_| PoseidonWrite::<_, _, PoseidonEncodedChallenge<_>>::init(vec![]))
+            .collect::<Vec<_>>();
+        for op in c.ops.iter() {
+            match op {
+                EvalOps::TranscriptReadScalar(i, _) => {
+                    writers[*i].write_scalar(E::Scalar::random(OsRng)).unwrap()
+                }
+                EvalOps::TranscriptReadPoint(i, _) => writers[*i]
+                    .write_point((params.g1 * E::Scalar::random(OsRng)).to_affine())
+                    .unwrap(),
+                _ => {}
+            }
+        }
+        let proofs = writers.into_iter().map(|w| w.finalize()).collect();
+
+        let instances = vkey
+            .iter()
+            .enumerate()
+            .map(|(i, vk)| {
+                (0..vk.cs.num_instance_columns)
+                    .map(|j| {
+                        let rows = config
+                            .target_proof_max_instance
+                            .get(i)
+                            .and_then(|x| x.get(j))
+                            .cloned()
+                            .unwrap_or(1);
+                        (0..rows)
+                            .map(|_| E::Scalar::random(OsRng))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        res = build_aggregate_verify_circuit_with_mode(
+            params,
+            vkey,
+            instances.iter().collect(),
+            &proofs,
+            config,
+            true,
+        )
+        .ok();
+        rest_tries -= 1;
+    }
+
+    res.unwrap().0
+}
+
 pub fn _build_aggregate_verify_circuit<
     E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
 >(
@@ -382,6 +609,31 @@ pub fn _build_aggregate_verify_circuit<
     ),
     UnsafeError,
 >
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    build_aggregate_verify_circuit_with_mode(params, vkey, instances, proofs, config, false)
+}
+
+fn build_aggregate_verify_circuit_with_mode<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+>(
+    params: &ParamsVerifier<E>,
+    vkey: &[&VerifyingKey<E::G1Affine>],
+    instances: Vec<&Vec<Vec<E::Scalar>>>,
+    proofs: &Vec<Vec<u8>>,
+    config: &AggregatorConfig<E::Scalar>,
+    // skip native checks on values when building against dummy proofs
+    dry_run: bool,
+) -> Result<
+    (
+        AggregatorCircuitOption<E::G1Affine>,
+        Vec<E::Scalar>,
+        Vec<E::Scalar>,
+        E::Scalar,
+    ),
+    UnsafeError,
+>
 where
     NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
 {
@@ -398,8 +650,8 @@ where
         params,
         vkey,
         &config.commitment_check,
-        config.target_proof_with_shplonk_as_default,
-        &config.target_proof_with_shplonk,
+        false,
+        &shplonk_proof_indices(&config.proof_protocols(vkey.len())),
     );
 
     let instance_commitments = instance_to_instance_commitment(params, vkey, instances.clone());
@@ -428,6 +680,7 @@ where
     // The translate() apply typological sorting for entries in targets vector.
     let c = EvalContext::translate(&targets[..]);
     let poseidon = PoseidonPure::default();
+    let mut rows = RowUtilization::default();
 
     let (pl, mut il, assigned_constant_hash) = match config.hash {
         TranscriptHash::Poseidon => {
@@ -461,6 +714,7 @@ where
                 &mut t.iter_mut().collect::<Vec<_>>(),
                 &mut ctx,
                 &mut constant_hasher,
+                &mut rows,
             )?
         }
         _ => unreachable!(),
@@ -475,15 +729,15 @@ where
     //         from instance commitment (of last round aggregator).
     // new_instance_commitment =
     //         instance_commitment - MSM(encoded points, params.g1[row..row + encoded_len])
-    for (proof_idx_of_target, columns, proof_idx_of_prev_agg, expose_row) in
+    let start = current_rows(&ctx);
+    for (proof_idx_of_target, columns, proof_idx_of_prev_agg, column_of_prev_agg, expose_row) in
         config.absorb_instance.iter()
     {
         // Encode commitment to scalar vec.
         let encoded_c = ctx.ecc_encode(&il[*proof_idx_of_target][*columns]);
 
-        // Aggregator circuit only has 1 instance column.
-        assert!(il[*proof_idx_of_prev_agg].len() == 1);
-        let instance_commit = il[*proof_idx_of_prev_agg][0].clone();
+        assert!(*column_of_prev_agg < il[*proof_idx_of_prev_agg].len());
+        let instance_commit = il[*proof_idx_of_prev_agg][*column_of_prev_agg].clone();
 
         // Encoded scalars must be 3-element vec.
         assert!(encoded_c.len() == 3);
@@ -498,8 +752,9 @@ where
         let diff_commit = ctx.ecc_neg(&msm_c);
         let instance_commit_curv = ctx.to_point_with_curvature(instance_commit);
         let update_commit = ctx.ecc_add(&instance_commit_curv, &diff_commit);
-        il[*proof_idx_of_prev_agg][0] = update_commit;
+        il[*proof_idx_of_prev_agg][*column_of_prev_agg] = update_commit;
     }
+    rows.instance_encoding_rows += current_rows(&ctx) - start;
 
     // Generate the aggregator hash H,
     // it can determine the aggregator round number and target circuits.
@@ -518,7 +773,7 @@ where
             // The value is restricted in current version, because aggregator only has one instance column.
             assert!(*instance_col == 0);
             // To avoid incorrect config in current version.
-            assert!(*hash == instances[*proof_index][*instance_col][0]);
+            assert!(dry_run || *hash == instances[*proof_index][*instance_col][0]);
 
             let assigned_hash = ctx.base_integer_chip().base_chip().assign(*hash);
             hasher.common_scalar(&mut ctx, &assigned_hash);
@@ -544,22 +799,25 @@ where
     };
 
     // Expose advice commitments as encoded scalars into aggregator's instance
+    let start = current_rows(&ctx);
     for (i, c) in pl[absorb_start_idx..expose_start_idx].iter().enumerate() {
         let encoded_c = ctx.ecc_encode(c);
         let [proof_index, instance_offset, g_index] = config.absorb[i].0;
 
-        assert_eq!(
-            instances[proof_index][instance_offset][g_index],
-            encoded_c[0].val
-        );
-        assert_eq!(
-            instances[proof_index][instance_offset][g_index + 1],
-            encoded_c[1].val
-        );
-        assert_eq!(
-            instances[proof_index][instance_offset][g_index + 2],
-            encoded_c[2].val
-        );
+        if !dry_run {
+            assert_eq!(
+                instances[proof_index][instance_offset][g_index],
+                encoded_c[0].val
+            );
+            assert_eq!(
+                instances[proof_index][instance_offset][g_index + 1],
+                encoded_c[1].val
+            );
+            assert_eq!(
+                instances[proof_index][instance_offset][g_index + 2],
+                encoded_c[2].val
+            );
+        }
 
         let instance_commit = il[proof_index][instance_offset].clone();
         // Encoded scalars must be 3-element vec.
@@ -573,6 +831,7 @@ where
         let update_commit = ctx.ecc_add(&instance_commit_curv, &diff_commit);
         il[proof_index][instance_offset] = update_commit;
     }
+    rows.instance_encoding_rows += current_rows(&ctx) - start;
 
     // Check pairing result for debug purpose.
     let pairing_c_wi = {
@@ -596,13 +855,22 @@ where
         let f = E::multi_miller_loop(&[(&w_x, &s_g2_prepared), (&w_g, &n_g2_prepared)]);
         //verify pairing with final exponent
         let success = bool::from(f.final_exponentiation().is_identity());
-        assert!(success);
+        assert!(dry_run || success);
+
+        // c and wi only exist for a valid pairing, assign identity in dry run for the layout
+        let compute_c_wi = |f| {
+            if dry_run {
+                (E::Gt::identity(), E::Gt::identity())
+            } else {
+                miller_loop_compute_c_wi::<E>(f)
+            }
+        };
 
         if E::support_on_prove_pairing() {
             #[cfg(not(feature = "on_prove_pairing_affine"))]
             {
                 //verify pairing with c and wi scheme
-                let (c, wi) = miller_loop_compute_c_wi::<E>(f);
+                let (c, wi) = compute_c_wi(f);
                 let success = bool::from(
                     E::multi_miller_loop_c_wi(
                         &c,
@@ -611,7 +879,7 @@ where
                     )
                     .is_identity(),
                 );
-                assert!(success);
+                assert!(dry_run || success);
                 Some((c, wi, None))
             }
             #[cfg(feature = "on_prove_pairing_affine")]
@@ -624,10 +892,10 @@ where
                 ]);
                 //verify pairing with final exponent
                 let success = bool::from(f.final_exponentiation().is_identity());
-                assert!(success);
+                assert!(dry_run || success);
 
                 //verify pairing with c and wi scheme (equivalent to final exponent scheme)
-                let (c, wi) = miller_loop_compute_c_wi::<E>(f);
+                let (c, wi) = compute_c_wi(f);
                 let success = bool::from(
                     E::multi_miller_loop_on_prove_pairing(
                         &c,
@@ -636,7 +904,7 @@ where
                     )
                     .is_identity(),
                 );
-                assert!(success);
+                assert!(dry_run || success);
                 Some((c, wi, Some((s_g2_prepared, n_g2_prepared))))
             }
         } else {
@@ -645,6 +913,7 @@ where
     };
 
     // Do pairing in circuit.
+    let start = current_rows(&ctx);
     {
         use halo2ecc_s::assign::AssignedCondition;
         use halo2ecc_s::assign::AssignedG2Affine;
@@ -722,6 +991,9 @@ where
         }
     }
 
+    rows.pairing_rows = current_rows(&ctx) - start;
+
+    let start = current_rows(&ctx);
     let (assigned_instances, instances, shadow_instances) = if !config.is_final_aggregator {
         // Aggregator's instance is [aggregator_hash, target circuits' instance commitments, exposed advice commitments].
         let mut assigned_instances = vec![assigned_final_hash];
@@ -834,6 +1106,7 @@ where
 
         (assigned_instances, instances, shadow_instances)
     };
+    rows.instance_encoding_rows += current_rows(&ctx) - start;
 
     let ctx: Context<_> = ctx.into();
     println!(
@@ -841,11 +1114,19 @@ where
         ctx.base_offset, ctx.range_offset, ctx.select_offset
     );
 
-    let circuit = if config.use_select_chip {
-        AggregatorCircuit::new(Rc::new(ctx.records), assigned_instances).into()
+    let rows_used = ctx.base_offset.max(ctx.range_offset).max(ctx.select_offset);
+    rows.total_rows = rows_used;
+
+    let mut circuit: AggregatorCircuitOption<_> = if config.use_select_chip {
+        let mut circuit = AggregatorCircuit::new(Rc::new(ctx.records), assigned_instances);
+        circuit.rows = rows;
+        circuit.into()
     } else {
-        AggregatorNoSelectCircuit::new(Rc::new(ctx.records), assigned_instances).into()
+        let mut circuit = AggregatorNoSelectCircuit::new(Rc::new(ctx.records), assigned_instances);
+        circuit.rows = rows;
+        circuit.into()
     };
+    circuit.rows_used = rows_used;
 
     Ok((
         circuit,
@@ -854,3 +1135,307 @@ where
         assigned_constant_hash.val,
     ))
 }
+
+#[test]
+fn test_encode_decode_point() {
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use halo2_proofs::pairing::group::Curve;
+
+    for _ in 0..16 {
+        let p = halo2_proofs::pairing::bn256::G1::random(ark_std::rand::rngs::OsRng).to_affine();
+        assert_eq!(decode_point::<G1Affine>(encode_point(&p)), Some(p));
+    }
+
+    assert_eq!(
+        decode_point::<G1Affine>(encode_point(&G1Affine::identity())),
+        None
+    );
+}
+
+#[test]
+fn test_encode_point_checked() {
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use halo2_proofs::pairing::group::Curve;
+
+    let p = halo2_proofs::pairing::bn256::G1::random(OsRng).to_affine();
+    assert_eq!(encode_point_checked(&p), Ok(encode_point(&p)));
+    assert_eq!(
+        encode_point_checked(&G1Affine::identity()),
+        Err(EncodeError::IdentityPoint)
+    );
+}
+
+#[test]
+fn test_scalar_pow_square_and_multiply() {
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+
+    let ctx = Rc::new(RefCell::new(Context::new()));
+    let ctx = IntegerContext::<Fq, Fr>::new(ctx);
+    let mut circuit = NativeScalarEccContext::<G1Affine>::new_without_select_chip(ctx);
+
+    let v = Fr::random(OsRng);
+    let a = circuit.base_integer_chip().base_chip().assign(v);
+
+    for n in [1u32, 2, 3, 5, 16, 17, 255, 1 << 20] {
+        let s = scalar_pow(&mut circuit, &a, n);
+        assert_eq!(s.val, v.pow_vartime([n as u64]));
+    }
+
+    // previous right-to-left expansion, which also squares after the top bit
+    let start = current_rows(&circuit);
+    let mut p = 16u32;
+    let mut c = vec![];
+    let mut acc = a.clone();
+    while p > 0 {
+        if p & 1 == 1 {
+            c.push(acc);
+        }
+        acc = circuit.base_integer_chip().base_chip().mul(&acc, &acc);
+        p >>= 1;
+    }
+    let expected = c
+        .into_iter()
+        .reduce(|acc, x| circuit.base_integer_chip().base_chip().mul(&acc, &x))
+        .unwrap();
+    let previous_rows = current_rows(&circuit) - start;
+
+    let start = current_rows(&circuit);
+    let s = scalar_pow(&mut circuit, &a, 16);
+    let rows = current_rows(&circuit) - start;
+
+    assert_eq!(s.val, expected.val);
+    // 4 squares instead of 5
+    assert!(rows < previous_rows);
+    assert_eq!(rows * 5, previous_rows * 4);
+}
+
+#[test]
+fn test_poseidon_chip_peek_scalar() {
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+
+    let ctx = Rc::new(RefCell::new(Context::new()));
+    let ctx = IntegerContext::<Fq, Fr>::new(ctx);
+    let mut circuit = NativeScalarEccContext::<G1Affine>::new_without_select_chip(ctx);
+
+    let v = Fr::random(OsRng);
+    let mut writer = PoseidonWrite::<_, _, PoseidonEncodedChallenge<_>>::init(vec![]);
+    writer.write_scalar(v).unwrap();
+    let proof = writer.finalize();
+
+    let mut t = PoseidonChipRead::init(PoseidonRead::init(&proof[..]), &mut circuit);
+    let s = t.read_scalar(&mut circuit);
+    let challenge = t.squeeze(&mut circuit);
+
+    let mut t = PoseidonChipRead::init(PoseidonRead::init(&proof[..]), &mut circuit);
+    let peeked = t.peek_scalar(&mut circuit);
+    let mut t_skip = PoseidonChipRead::init(PoseidonRead::init(&proof[..]), &mut circuit);
+    t_skip.peek_scalar(&mut circuit);
+    t.common_scalar(&mut circuit, &peeked);
+
+    assert_eq!(peeked.val, s.val);
+    assert_eq!(t.squeeze(&mut circuit).val, challenge.val);
+    assert_ne!(t_skip.squeeze(&mut circuit).val, challenge.val);
+}
+
+#[test]
+fn test_poseidon_chip_state_values() {
+    use crate::circuit_verifier::transcript::PoseidonChipContext;
+    use crate::transcript::poseidon::RATE;
+    use crate::transcript::poseidon::R_F;
+    use crate::transcript::poseidon::R_P;
+    use crate::transcript::poseidon::T;
+    use halo2_proofs::arithmetic::FieldExt;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use poseidon::Poseidon;
+
+    let ctx = Rc::new(RefCell::new(Context::new()));
+    let ctx = IntegerContext::<Fq, Fr>::new(ctx);
+    let mut circuit = NativeScalarEccContext::<G1Affine>::new_without_select_chip(ctx);
+
+    let spec = PoseidonPure::<G1Affine>::default().get_spec();
+    let mut chip = PoseidonChipContext::new(&mut circuit.base_integer_chip().base_chip(), spec);
+
+    let mut initial = [Fr::zero(); T];
+    initial[0] = Fr::from_u128(1u128 << 64);
+    assert_eq!(chip.get_state_values(), initial);
+
+    let inputs = (0..RATE + 3).map(|_| Fr::random(OsRng)).collect::<Vec<_>>();
+    let assigned = inputs
+        .iter()
+        .map(|x| circuit.base_integer_chip().base_chip().assign(*x))
+        .collect::<Vec<_>>();
+    chip.update(&mut circuit.base_integer_chip().base_chip(), assigned);
+    let squeezed = chip.squeeze(&mut circuit.base_integer_chip().base_chip());
+
+    let mut native = Poseidon::<Fr, T, RATE>::new(R_F, R_P);
+    native.update(&inputs);
+    assert_eq!(squeezed.val, native.squeeze());
+    assert_eq!(chip.get_state_values()[1], squeezed.val);
+}
+
+#[test]
+fn test_poseidon_chip_absorb_batch() {
+    use crate::circuit_verifier::transcript::PoseidonChipContext;
+    use crate::transcript::poseidon::RATE;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+
+    let ctx = Rc::new(RefCell::new(Context::new()));
+    let ctx = IntegerContext::<Fq, Fr>::new(ctx);
+    let mut circuit = NativeScalarEccContext::<G1Affine>::new_without_select_chip(ctx);
+
+    let spec = PoseidonPure::<G1Affine>::default().get_spec();
+    let mut batch =
+        PoseidonChipContext::new(&mut circuit.base_integer_chip().base_chip(), spec.clone());
+    let mut single = PoseidonChipContext::new(&mut circuit.base_integer_chip().base_chip(), spec);
+
+    // start from a partial chunk, then cross several chunk boundaries
+    for n in [3, 2 * RATE + 1, RATE - 4, 0] {
+        let assigned = (0..n)
+            .map(|_| {
+                circuit
+                    .base_integer_chip()
+                    .base_chip()
+                    .assign(Fr::random(OsRng))
+            })
+            .collect::<Vec<_>>();
+        batch.absorb_batch(&mut circuit.base_integer_chip().base_chip(), &assigned);
+        for x in assigned {
+            single.update(&mut circuit.base_integer_chip().base_chip(), vec![x]);
+        }
+        assert_eq!(batch.get_state_values(), single.get_state_values());
+    }
+
+    let batch_squeezed = batch.squeeze(&mut circuit.base_integer_chip().base_chip());
+    let single_squeezed = single.squeeze(&mut circuit.base_integer_chip().base_chip());
+    assert_eq!(batch_squeezed.val, single_squeezed.val);
+}
+
+#[cfg(test)]
+fn simple_circuit_dry_run(
+    config: &AggregatorConfig<halo2_proofs::pairing::bn256::Fr>,
+) -> (
+    ParamsVerifier<Bn256>,
+    VerifyingKey<halo2_proofs::pairing::bn256::G1Affine>,
+    AggregatorCircuitOption<halo2_proofs::pairing::bn256::G1Affine>,
+) {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let agg = dry_run_aggregate_verify_circuit(&params_verifier, &[&vkey], config);
+    (params_verifier, vkey, agg)
+}
+
+#[test]
+fn test_row_utilization() {
+    let config =
+        AggregatorConfig::default_aggregator_config(TranscriptHash::Poseidon, vec![vec![1]], false);
+    let (_, _, agg) = simple_circuit_dry_run(&config);
+
+    let k = 22;
+    let util = agg.row_utilization(k);
+    assert_eq!(util.max_rows, 1 << k);
+    assert!(util.total_rows <= util.max_rows);
+    assert!(util.pairing_rows > 0 && util.msm_rows > 0 && util.transcript_rows > 0);
+    assert!(
+        util.pairing_rows + util.msm_rows + util.transcript_rows + util.instance_encoding_rows
+            <= util.total_rows
+    );
+    assert!(util.fraction() > 0.0 && util.fraction() <= 1.0);
+}
+
+#[test]
+fn test_constraint_count() {
+    let config =
+        AggregatorConfig::default_aggregator_config(TranscriptHash::Poseidon, vec![vec![1]], false);
+    let (_, _, agg) = simple_circuit_dry_run(&config);
+
+    // every recorded row spans all advice columns of the aggregator
+    let util = agg.row_utilization(22);
+    assert!(agg.constraint_count() > util.total_rows);
+    assert_eq!(agg.constraint_count() % util.total_rows, 0);
+}
+
+#[test]
+fn test_without_witnesses_light_keygen() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use halo2_proofs::plonk::keygen_vk;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let config =
+        AggregatorConfig::default_aggregator_config(TranscriptHash::Poseidon, vec![vec![1]], false);
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let target_params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let vkey = load_or_build_vkey::<Bn256, _>(&target_params, &circuit, None);
+    let params_verifier: ParamsVerifier<Bn256> = target_params.verifier(1).unwrap();
+
+    let agg = dry_run_aggregate_verify_circuit(&params_verifier, &[&vkey], &config)
+        .circuit_with_select_chip
+        .unwrap();
+    let skeleton = AggregatorCircuit::<G1Affine>::without_witnesses_light::<Bn256>(
+        params_verifier,
+        vec![vkey],
+        config,
+    );
+
+    // The skeleton builds its own dummy witnesses, keygen only sees the layout.
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+    let k = 22;
+    let params = load_or_build_unsafe_params::<Bn256>(
+        k,
+        Some(&Path::new(path).join(format!("K{}.params", k))),
+    );
+    let vk = keygen_vk(&params, &agg).unwrap();
+    let skeleton_vk = keygen_vk(&params, &skeleton).unwrap();
+    assert_eq!(
+        format!("{:?}", vk.pinned()),
+        format!("{:?}", skeleton_vk.pinned())
+    );
+}
+
+#[test]
+fn test_estimate_k() {
+    use halo2_proofs::pairing::bn256::G1Affine;
+
+    let config =
+        AggregatorConfig::default_aggregator_config(TranscriptHash::Poseidon, vec![vec![1]], false);
+    let (params_verifier, vkey, agg) = simple_circuit_dry_run(&config);
+    let rows = agg.row_utilization(0).total_rows;
+
+    // the smallest k that keeps a 10% margin over the rows
+    let k = AggregatorCircuit::<G1Affine>::estimate_k::<Bn256>(&params_verifier, &[&vkey], &config);
+    assert!(1usize << k > rows + rows / 10);
+    assert!(1usize << (k - 1) <= rows + rows / 10);
+}
+
+#[test]
+fn test_recommended_pairing_rows() {
+    use halo2_proofs::pairing::bn256::G1Affine;
+
+    let config =
+        AggregatorConfig::default_aggregator_config(TranscriptHash::Poseidon, vec![vec![1]], false);
+    let (params_verifier, vkey, agg) = simple_circuit_dry_run(&config);
+    let util = agg.row_utilization(0);
+
+    let pairing_rows = AggregatorCircuit::<G1Affine>::recommended_pairing_rows::<Bn256>(
+        &params_verifier,
+        &[&vkey],
+    );
+    assert_eq!(pairing_rows, util.pairing_rows);
+    assert!(pairing_rows > 0 && pairing_rows < util.total_rows);
+}
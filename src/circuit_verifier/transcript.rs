@@ -58,6 +58,16 @@ impl<R: io::Read, C: CurveAffine> PoseidonChipRead<R, C> {
         s
     }
 
+    // Read and assign the next scalar without absorbing it into the in-circuit state,
+    // the caller absorbs it later by common_scalar.
+    pub fn peek_scalar(
+        &mut self,
+        circuit: &mut NativeScalarEccContext<C>,
+    ) -> AssignedValue<C::ScalarExt> {
+        let s = self.read.read_scalar().unwrap();
+        circuit.base_integer_chip().base_chip().assign(s)
+    }
+
     pub fn read_point(
         &mut self,
         circuit: &mut NativeScalarEccContext<C>,
@@ -74,9 +84,9 @@ impl<R: io::Read, C: CurveAffine> PoseidonChipRead<R, C> {
         circuit: &mut NativeScalarEccContext<C>,
         s: &AssignedValue<C::ScalarExt>,
     ) {
-        self.state.update(
+        self.state.absorb_batch(
             &mut circuit.base_integer_chip().base_chip(),
-            vec![self.prefix[2], s.clone()],
+            &[self.prefix[2], s.clone()],
         );
     }
 
@@ -85,13 +95,14 @@ impl<R: io::Read, C: CurveAffine> PoseidonChipRead<R, C> {
         circuit: &mut NativeScalarEccContext<C>,
         p: &AssignedPoint<C, C::ScalarExt>,
     ) {
-        self.state.update(
+        // the prefix is absorbed before encoding to keep the cell layout of the circuit
+        self.state.absorb_batch(
             &mut circuit.base_integer_chip().base_chip(),
-            vec![self.prefix[1]],
+            &[self.prefix[1]],
         );
         let sl = circuit.ecc_encode(p);
         self.state
-            .update(&mut circuit.base_integer_chip().base_chip(), sl);
+            .absorb_batch(&mut circuit.base_integer_chip().base_chip(), &sl);
     }
 
     pub fn squeeze(
@@ -127,6 +138,28 @@ impl<F: FieldExt> PoseidonChipContext<F> {
         }
     }
 
+    // Copy the current state into new cells constrained to be equal with the old ones,
+    // so that the fork and the original can absorb and squeeze independently.
+    pub fn fork(&self, chip: &mut RefMut<'_, dyn BaseChipOps<F>>) -> Self {
+        let one = F::one();
+        let state = self
+            .state
+            .0
+            .iter()
+            .map(|x| chip.sum_with_constant(vec![(x, one)], None))
+            .collect::<Vec<_>>();
+        let absorbing = self
+            .absorbing
+            .iter()
+            .map(|x| chip.sum_with_constant(vec![(x, one)], None))
+            .collect();
+        Self {
+            spec: self.spec.clone(),
+            state: PoseidonChipState(state.try_into().unwrap()),
+            absorbing,
+        }
+    }
+
     pub fn update(
         &mut self,
         chip: &mut RefMut<'_, dyn BaseChipOps<F>>,
@@ -150,6 +183,40 @@ impl<F: FieldExt> PoseidonChipContext<F> {
         }
     }
 
+    // Same as update, but takes a slice and only moves the inputs of the last partial chunk
+    // into absorbing, full chunks are permuted in place.
+    pub fn absorb_batch(
+        &mut self,
+        chip: &mut RefMut<'_, dyn BaseChipOps<F>>,
+        inputs: &[AssignedValue<F>],
+    ) {
+        let mut inputs = inputs;
+
+        if !self.absorbing.is_empty() {
+            let n = usize::min(RATE - self.absorbing.len(), inputs.len());
+            self.absorbing.extend_from_slice(&inputs[..n]);
+            inputs = &inputs[n..];
+
+            if self.absorbing.len() < RATE {
+                return;
+            }
+
+            let chunk = std::mem::take(&mut self.absorbing);
+            self.permute(chip, &chunk, false);
+        }
+
+        let mut chunks = inputs.chunks_exact(RATE);
+        for chunk in &mut chunks {
+            self.permute(chip, chunk, false);
+        }
+        self.absorbing = chunks.remainder().to_vec();
+    }
+
+    // Values of the sponge state cells, pending absorbed inputs are not included.
+    pub fn get_state_values(&self) -> [F; T] {
+        self.state.0.map(|x| x.val)
+    }
+
     pub fn squeeze(&mut self, chip: &mut RefMut<'_, dyn BaseChipOps<F>>) -> AssignedValue<F> {
         assert!(self.absorbing.len() < RATE);
 
@@ -0,0 +1,93 @@
+use halo2_proofs::arithmetic::FieldExt;
+use halo2_proofs::circuit::floor_planner::V1;
+use halo2_proofs::circuit::Layouter;
+use halo2_proofs::plonk::Advice;
+use halo2_proofs::plonk::Circuit;
+use halo2_proofs::plonk::Column;
+use halo2_proofs::plonk::ConstraintSystem;
+use halo2_proofs::plonk::Error;
+use halo2_proofs::plonk::Fixed;
+use halo2_proofs::poly::Rotation;
+
+#[derive(Clone)]
+pub struct TwoInstanceConfig {
+    advice: Column<Advice>,
+    sel: Column<Fixed>,
+}
+
+// A circuit with two instance columns, both equal to `a` at row 0.
+// The rows after row 0 are free, e.g. to carry encoded commitments to absorb.
+#[derive(Default, Clone)]
+pub struct TwoInstanceCircuit<F: FieldExt> {
+    pub a: F,
+}
+
+impl<F: FieldExt> TwoInstanceCircuit<F> {
+    pub fn new_with_instance(a: F) -> (Self, Vec<Vec<F>>) {
+        (Self { a }, vec![vec![a], vec![a]])
+    }
+}
+
+impl<F: FieldExt> Circuit<F> for TwoInstanceCircuit<F> {
+    type Config = TwoInstanceConfig;
+
+    type FloorPlanner = V1;
+
+    fn without_witnesses(&self) -> Self {
+        Self { a: F::from(0) }
+    }
+
+    fn configure(meta: &mut ConstraintSystem<F>) -> Self::Config {
+        let advice = meta.advice_column();
+        let instances = [meta.instance_column(), meta.instance_column()];
+        let sel = meta.fixed_column();
+
+        meta.create_gate("a equals to instances", |meta| {
+            let sel = meta.query_fixed(sel, Rotation(0));
+            let a = meta.query_advice(advice, Rotation(0));
+            let instance0 = meta.query_instance(instances[0], Rotation(0));
+            let instance1 = meta.query_instance(instances[1], Rotation(0));
+            vec![sel.clone() * (a.clone() - instance0), sel * (a - instance1)]
+        });
+
+        TwoInstanceConfig { advice, sel }
+    }
+
+    fn synthesize(&self, config: Self::Config, layouter: impl Layouter<F>) -> Result<(), Error> {
+        layouter.assign_region(
+            || "main",
+            |region| {
+                region.assign_advice(|| "a", config.advice, 0, || Ok(self.a))?;
+                region.assign_fixed(|| "sel", config.sel, 0, || Ok(F::one()))?;
+                Ok(())
+            },
+        )?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_two_instance() {
+    use halo2_proofs::dev::MockProver;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    const K: u32 = 8;
+    let (circuit, mut instances) = TwoInstanceCircuit::<Fr>::new_with_instance(Fr::from(10u64));
+    // Rows after row 0 are not constrained.
+    instances[1].push(Fr::from(20u64));
+    let prover = match MockProver::run(K, &circuit, instances) {
+        Ok(prover) => prover,
+        Err(e) => panic!("{:#?}", e),
+    };
+    assert_eq!(prover.verify(), Ok(()));
+
+    let prover = match MockProver::run(
+        K,
+        &circuit,
+        vec![vec![Fr::from(10u64)], vec![Fr::from(0u64)]],
+    ) {
+        Ok(prover) => prover,
+        Err(e) => panic!("{:#?}", e),
+    };
+    assert!(prover.verify().is_err());
+}
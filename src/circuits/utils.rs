@@ -1,9 +1,12 @@
+use crate::api::halo2::builder::proof_transcript_layout;
 use crate::circuit_verifier::build_aggregate_verify_circuit;
 use crate::circuit_verifier::circuit::AggregatorCircuit;
 use crate::circuit_verifier::circuit::AggregatorCircuitOption;
 use crate::circuit_verifier::G2AffineBaseHelper;
 use crate::circuit_verifier::GtHelper;
+use crate::native_verifier::try_verify_proofs;
 use crate::native_verifier::verify_proofs;
+use crate::native_verifier::ProofVerifyError;
 use crate::transcript::poseidon::PoseidonPure;
 use crate::transcript::poseidon::PoseidonRead;
 use crate::transcript::poseidon::PoseidonWrite;
@@ -11,6 +14,10 @@ use crate::transcript::sha256::ShaRead;
 use crate::transcript::sha256::ShaWrite;
 use ark_std::end_timer;
 use ark_std::rand::rngs::OsRng;
+use ark_std::rand::rngs::StdRng;
+use ark_std::rand::CryptoRng;
+use ark_std::rand::RngCore;
+use ark_std::rand::SeedableRng;
 use ark_std::start_timer;
 use halo2_proofs::arithmetic::BaseExt;
 use halo2_proofs::arithmetic::CurveAffine;
@@ -32,17 +39,56 @@ use halo2_proofs::transcript::Blake2bWrite;
 use halo2_proofs::transcript::Transcript;
 use halo2ecc_s::circuit::pairing_chip::PairingChipOnProvePairingOps;
 use halo2ecc_s::context::NativeScalarEccContext;
+use rayon::iter::IntoParallelIterator;
+use rayon::iter::IntoParallelRefIterator;
+use rayon::iter::ParallelIterator;
+use serde::Deserialize;
+use serde::Serialize;
+use std::cell::RefCell;
 use std::io::Read;
 use std::io::Write;
 use std::path::Path;
+use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Instant;
 
-#[derive(Clone, Copy, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum TranscriptHash {
     Blake2b,
     Poseidon,
     Sha,
     Keccak,
+    Blake2s,
+    Sha512,
+    // FIPS 202 SHA3-256, which is not the same as Keccak256 (different padding)
+    Sha3_256,
+}
+
+// Multi-open protocol used to create a target proof.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum ProofProtocol {
+    GWC,
+    SHPLONK,
+}
+
+impl ProofProtocol {
+    // Proofs verified by solidity/gnark (non-poseidon transcript) must use shplonk.
+    pub fn default_for(hash: TranscriptHash) -> Self {
+        if hash == TranscriptHash::Poseidon {
+            ProofProtocol::GWC
+        } else {
+            ProofProtocol::SHPLONK
+        }
+    }
+}
+
+pub fn shplonk_proof_indices(protocols: &[ProofProtocol]) -> Vec<usize> {
+    protocols
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| **p == ProofProtocol::SHPLONK)
+        .map(|(i, _)| i)
+        .collect()
 }
 
 pub fn load_or_build_unsafe_params<E: MultiMillerLoop>(
@@ -68,6 +114,32 @@ pub fn load_or_build_unsafe_params<E: MultiMillerLoop>(
     params
 }
 
+// Deterministic version of load_or_build_unsafe_params, the secret is derived from seed.
+pub fn load_or_build_unsafe_params_seeded<E: MultiMillerLoop>(
+    k: u32,
+    seed: [u8; 32],
+    cache_file_opt: Option<&Path>,
+) -> Params<E::G1Affine> {
+    if let Some(cache_file) = &cache_file_opt {
+        if Path::exists(&cache_file) {
+            println!("read params K={} from {:?}", k, cache_file);
+            let mut fd = std::fs::File::open(&cache_file).unwrap();
+            return Params::<E::G1Affine>::read(&mut fd).unwrap();
+        }
+    }
+
+    let s = E::Scalar::random(StdRng::from_seed(seed));
+    let params = Params::<E::G1Affine>::unsafe_setup_with_s::<E>(k, s);
+
+    if let Some(cache_file) = &cache_file_opt {
+        println!("write params K={} to {:?}", k, cache_file);
+        let mut fd = std::fs::File::create(&cache_file).unwrap();
+        params.write(&mut fd).unwrap();
+    };
+
+    params
+}
+
 pub fn load_vkey<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
     params: &Params<E::G1Affine>,
     cache_file: &Path,
@@ -98,6 +170,39 @@ pub fn load_or_build_vkey<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
     verify_circuit_vk
 }
 
+/// Caches the vkey under `cache_dir` by the Blake2b hash of its pinned form,
+/// so a changed circuit never picks up a stale vkey from the cache.
+/// `keygen_vk` still runs on every call since the hash is derived from it.
+pub fn load_or_build_vkey_content_addressed<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
+    params: &Params<E::G1Affine>,
+    circuit: &C,
+    cache_dir: &Path,
+) -> VerifyingKey<E::G1Affine> {
+    let vkey = keygen_vk(&params, circuit).expect("keygen_vk should not fail");
+
+    let hash_hex = {
+        let mut hasher = blake2b_simd::Params::new()
+            .hash_length(32)
+            .personal(b"Halo2-Verify-Key")
+            .to_state();
+
+        let s = format!("{:?}", vkey.pinned());
+        hasher.update(&(s.len() as u64).to_le_bytes());
+        hasher.update(s.as_bytes());
+        hasher.finalize().to_hex().to_string()
+    };
+
+    let cache_file = cache_dir.join(format!("{}.vkey", hash_hex));
+    if Path::exists(&cache_file) {
+        return load_vkey::<E, C>(params, &cache_file);
+    }
+
+    let mut fd = std::fs::File::create(&cache_file).unwrap();
+    vkey.write(&mut fd).unwrap();
+
+    vkey
+}
+
 pub fn load_instance<E: MultiMillerLoop>(n_rows: &[u32], cache_file: &Path) -> Vec<Vec<E::Scalar>> {
     assert!(Path::exists(&cache_file));
     let mut fd = std::fs::File::open(&cache_file).unwrap();
@@ -121,11 +226,29 @@ pub fn store_instance<F: FieldExt>(instances: &Vec<Vec<F>>, cache_file: &Path) {
     }
 }
 
+// An empty instance column is committed to the identity point.
 pub fn instance_to_instance_commitment<E: MultiMillerLoop>(
     params: &ParamsVerifier<E>,
     vk: &[&VerifyingKey<E::G1Affine>],
     instances: Vec<&Vec<Vec<E::Scalar>>>,
 ) -> Vec<Vec<E::G1Affine>> {
+    instance_to_instance_commitment_checked(params, vk, instances)
+        .into_iter()
+        .map(|commitments| {
+            commitments
+                .into_iter()
+                .map(|x| x.unwrap_or(E::G1Affine::identity()))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+}
+
+// Same as instance_to_instance_commitment, but returns None for empty instance columns.
+pub fn instance_to_instance_commitment_checked<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vk: &[&VerifyingKey<E::G1Affine>],
+    instances: Vec<&Vec<Vec<E::Scalar>>>,
+) -> Vec<Vec<Option<E::G1Affine>>> {
     instances
         .iter()
         .zip(vk.iter())
@@ -135,7 +258,56 @@ pub fn instance_to_instance_commitment<E: MultiMillerLoop>(
                 .map(|instance| {
                     assert!(instance.len() <= params.n as usize - (vk.cs.blinding_factors() + 1));
 
-                    params.commit_lagrange(instance.to_vec()).to_affine()
+                    if instance.is_empty() {
+                        None
+                    } else {
+                        Some(params.commit_lagrange(instance.to_vec()).to_affine())
+                    }
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>()
+}
+
+// Same as instance_to_instance_commitment, but the msm of all non-empty columns of all proofs
+// run in parallel and the results are converted to affine by one batch normalization.
+// Each column still needs its own msm, as one msm would only give the sum of the commitments.
+pub fn instance_to_instance_commitment_batched<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vk: &[&VerifyingKey<E::G1Affine>],
+    instances: Vec<&Vec<Vec<E::Scalar>>>,
+) -> Vec<Vec<E::G1Affine>> {
+    let columns = instances
+        .iter()
+        .zip(vk.iter())
+        .flat_map(|(instances, vk)| {
+            instances.iter().map(|instance| {
+                assert!(instance.len() <= params.n as usize - (vk.cs.blinding_factors() + 1));
+                instance
+            })
+        })
+        .filter(|instance| !instance.is_empty())
+        .collect::<Vec<_>>();
+
+    let projective = columns
+        .par_iter()
+        .map(|instance| params.commit_lagrange(instance.to_vec()))
+        .collect::<Vec<_>>();
+    let mut affine = vec![E::G1Affine::identity(); projective.len()];
+    Curve::batch_normalize(&projective[..], &mut affine[..]);
+
+    let mut affine = affine.into_iter();
+    instances
+        .iter()
+        .map(|instances| {
+            instances
+                .iter()
+                .map(|instance| {
+                    if instance.is_empty() {
+                        E::G1Affine::identity()
+                    } else {
+                        affine.next().unwrap()
+                    }
                 })
                 .collect::<Vec<_>>()
         })
@@ -149,6 +321,92 @@ pub fn load_proof(cache_file: &Path) -> Vec<u8> {
     buf
 }
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Proof {
+    pub data: Vec<u8>,
+    pub hash: Option<TranscriptHash>,
+}
+
+// Bn256 points and scalars are 32 bytes in every transcript, so a proof with a
+// 1-byte hash header is the only one whose length is not a multiple of 32.
+const PROOF_ELEMENT_SIZE: usize = 32;
+
+impl TranscriptHash {
+    fn header_byte(&self) -> u8 {
+        match self {
+            TranscriptHash::Blake2b => 0,
+            TranscriptHash::Poseidon => 1,
+            TranscriptHash::Sha => 2,
+            TranscriptHash::Keccak => 3,
+            TranscriptHash::Blake2s => 4,
+            TranscriptHash::Sha512 => 5,
+            TranscriptHash::Sha3_256 => 6,
+        }
+    }
+
+    fn from_header_byte(b: u8) -> Option<Self> {
+        match b {
+            0 => Some(TranscriptHash::Blake2b),
+            1 => Some(TranscriptHash::Poseidon),
+            2 => Some(TranscriptHash::Sha),
+            3 => Some(TranscriptHash::Keccak),
+            4 => Some(TranscriptHash::Blake2s),
+            5 => Some(TranscriptHash::Sha512),
+            6 => Some(TranscriptHash::Sha3_256),
+            _ => None,
+        }
+    }
+}
+
+// A proof stored with a hash header can only be read back by load_proof_typed.
+pub fn store_proof(cache_file: &Path, proof: &[u8], hash: Option<TranscriptHash>) {
+    let mut fd = std::fs::File::create(&cache_file).unwrap();
+    if let Some(hash) = hash {
+        fd.write_all(&[hash.header_byte()]).unwrap();
+    }
+    fd.write_all(proof).unwrap();
+}
+
+pub fn load_proof_typed(cache_file: &Path) -> Proof {
+    let buf = load_proof(cache_file);
+    if buf.len() % PROOF_ELEMENT_SIZE == 1 {
+        if let Some(hash) = TranscriptHash::from_header_byte(buf[0]) {
+            return Proof {
+                data: buf[1..].to_vec(),
+                hash: Some(hash),
+            };
+        }
+    }
+    Proof {
+        data: buf,
+        hash: None,
+    }
+}
+
+// Expected byte size of a proof of vkey, before running keygen_pk and create_proof_ext.
+// The multi-open protocol is the default one of hash, see ProofProtocol::default_for.
+pub fn estimate_proof_size<E: MultiMillerLoop>(
+    vkey: &VerifyingKey<E::G1Affine>,
+    hash: TranscriptHash,
+) -> usize {
+    let use_gwc = ProofProtocol::default_for(hash) == ProofProtocol::GWC;
+    let (n_points, n_scalars, n_multiopen_points) = proof_transcript_layout(vkey, use_gwc);
+
+    // sha-like transcripts write both coordinates of a point
+    let point_size = match hash {
+        TranscriptHash::Blake2b | TranscriptHash::Poseidon => PROOF_ELEMENT_SIZE,
+        TranscriptHash::Sha
+        | TranscriptHash::Keccak
+        | TranscriptHash::Blake2s
+        | TranscriptHash::Sha512
+        | TranscriptHash::Sha3_256 => PROOF_ELEMENT_SIZE * 2,
+    };
+
+    (n_points + n_multiopen_points) * point_size + n_scalars * PROOF_ELEMENT_SIZE
+}
+
+// The caller picks the multi-open protocol explicitly, see ProofProtocol::default_for
+// for the protocol an aggregator expects for a given transcript hash.
 pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
     params: &Params<E::G1Affine>,
     vkey: VerifyingKey<E::G1Affine>,
@@ -157,8 +415,40 @@ pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
     cache_file_opt: Option<&Path>,
     hash: TranscriptHash,
     try_load_proof: bool,
-    use_shplonk: bool,
+    protocol: ProofProtocol,
+) -> Vec<u8> {
+    load_or_create_proof_with_rng::<E, C, _>(
+        params,
+        vkey,
+        circuit,
+        instances,
+        cache_file_opt,
+        hash,
+        try_load_proof,
+        protocol,
+        OsRng,
+    )
+}
+
+// Same as load_or_create_proof, but the blinding randomness is drawn from rng
+// so that a seeded rng gives reproducible proofs.
+pub fn load_or_create_proof_with_rng<
+    E: MultiMillerLoop,
+    C: Circuit<E::Scalar>,
+    R: RngCore + CryptoRng,
+>(
+    params: &Params<E::G1Affine>,
+    vkey: VerifyingKey<E::G1Affine>,
+    circuit: C,
+    instances: &[&[E::Scalar]],
+    cache_file_opt: Option<&Path>,
+    hash: TranscriptHash,
+    try_load_proof: bool,
+    protocol: ProofProtocol,
+    rng: R,
 ) -> Vec<u8> {
+    let use_shplonk = protocol == ProofProtocol::SHPLONK;
+
     if let Some(cache_file) = &cache_file_opt {
         if try_load_proof && Path::exists(&cache_file) {
             return load_proof(&cache_file);
@@ -178,7 +468,7 @@ pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
                 &pkey,
                 &[circuit],
                 &[instances],
-                OsRng,
+                rng,
                 &mut transcript,
                 !use_shplonk,
             )
@@ -192,7 +482,7 @@ pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
                 &pkey,
                 &[circuit],
                 &[instances],
-                OsRng,
+                rng,
                 &mut transcript,
                 !use_shplonk,
             )
@@ -206,7 +496,7 @@ pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
                 &pkey,
                 &[circuit],
                 &[instances],
-                OsRng,
+                rng,
                 &mut transcript,
                 !use_shplonk,
             )
@@ -220,7 +510,49 @@ pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
                 &pkey,
                 &[circuit],
                 &[instances],
-                OsRng,
+                rng,
+                &mut transcript,
+                !use_shplonk,
+            )
+            .expect("proof generation should not fail");
+            transcript.finalize()
+        }
+        TranscriptHash::Blake2s => {
+            let mut transcript = ShaWrite::<_, _, _, blake2::Blake2s256>::init(vec![]);
+            create_proof_ext(
+                params,
+                &pkey,
+                &[circuit],
+                &[instances],
+                rng,
+                &mut transcript,
+                !use_shplonk,
+            )
+            .expect("proof generation should not fail");
+            transcript.finalize()
+        }
+        TranscriptHash::Sha512 => {
+            let mut transcript = ShaWrite::<_, _, _, sha2::Sha512>::init(vec![]);
+            create_proof_ext(
+                params,
+                &pkey,
+                &[circuit],
+                &[instances],
+                rng,
+                &mut transcript,
+                !use_shplonk,
+            )
+            .expect("proof generation should not fail");
+            transcript.finalize()
+        }
+        TranscriptHash::Sha3_256 => {
+            let mut transcript = ShaWrite::<_, _, _, sha3::Sha3_256>::init(vec![]);
+            create_proof_ext(
+                params,
+                &pkey,
+                &[circuit],
+                &[instances],
+                rng,
                 &mut transcript,
                 !use_shplonk,
             )
@@ -232,13 +564,109 @@ pub fn load_or_create_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
 
     if let Some(cache_file) = &cache_file_opt {
         println!("write transcript to {:?}", cache_file);
-        let mut fd = std::fs::File::create(&cache_file).unwrap();
-        fd.write_all(&transcript).unwrap();
+        store_proof(cache_file, &transcript, None);
     };
 
     transcript
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProofError<C: CurveAffine> {
+    VerifyFailed(ProofVerifyError<C>),
+}
+
+// Same as load_or_create_proof, but a newly created proof is verified natively
+// before it is written to cache_file_opt. A proof loaded from cache is returned as is.
+pub fn load_or_create_proof_and_verify<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
+    params: &Params<E::G1Affine>,
+    vkey: VerifyingKey<E::G1Affine>,
+    circuit: C,
+    instances: &[&[E::Scalar]],
+    cache_file_opt: Option<&Path>,
+    hash: TranscriptHash,
+    try_load_proof: bool,
+    protocol: ProofProtocol,
+    verify_after_create: bool,
+) -> Result<Vec<u8>, ProofError<E::G1Affine>> {
+    if let Some(cache_file) = &cache_file_opt {
+        if try_load_proof && Path::exists(&cache_file) {
+            return Ok(load_proof(&cache_file));
+        }
+    }
+
+    let proof = load_or_create_proof::<E, C>(
+        params,
+        vkey.clone(),
+        circuit,
+        instances,
+        None,
+        hash,
+        false,
+        protocol,
+    );
+
+    if verify_after_create {
+        let public_inputs_size = instances.iter().map(|x| x.len()).max().unwrap_or(0);
+        let params_verifier: ParamsVerifier<E> = params
+            .verifier(public_inputs_size + PUBLIC_INPUTS_SIZE_PADDING)
+            .unwrap();
+        let instances = instances.iter().map(|x| x.to_vec()).collect::<Vec<_>>();
+        try_verify_proofs::<E>(
+            &params_verifier,
+            &[&vkey],
+            vec![&instances],
+            vec![proof.clone()],
+            hash,
+            &vec![],
+            &[protocol],
+        )
+        .map_err(ProofError::VerifyFailed)?;
+    }
+
+    if let Some(cache_file) = &cache_file_opt {
+        println!("write transcript to {:?}", cache_file);
+        store_proof(cache_file, &proof, None);
+    };
+
+    Ok(proof)
+}
+
+// Fully explicit proof settings, deriving them from an AggregatorConfig is left to the caller.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ProofConfig {
+    pub hash: TranscriptHash,
+    pub use_shplonk: bool,
+}
+
+impl ProofConfig {
+    pub fn protocol(&self) -> ProofProtocol {
+        if self.use_shplonk {
+            ProofProtocol::SHPLONK
+        } else {
+            ProofProtocol::GWC
+        }
+    }
+}
+
+pub fn create_proof_with_config<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
+    params: &Params<E::G1Affine>,
+    vkey: VerifyingKey<E::G1Affine>,
+    circuit: C,
+    instances: &[&[E::Scalar]],
+    config: ProofConfig,
+) -> Vec<u8> {
+    load_or_create_proof::<E, C>(
+        params,
+        vkey,
+        circuit,
+        instances,
+        None,
+        config.hash,
+        false,
+        config.protocol(),
+    )
+}
+
 /* CARE: unsafe means that to review before used in real production */
 pub fn run_circuit_unsafe_full_pass_no_rec<
     E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
@@ -264,15 +692,69 @@ pub fn run_circuit_unsafe_full_pass_no_rec<
 where
     NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
 {
-    run_circuit_unsafe_full_pass::<E, C>(
+    run_circuit_unsafe_full_pass_no_rec_with_naming::<E, C>(
+        cache_folder,
+        k,
+        circuits,
+        instances,
+        shadow_instances,
+        hash,
+        commitment_check,
+        expose,
+        max_public_instance,
+        force_create_proof,
+        default_artifact_naming(cache_folder, prefix),
+    )
+}
+
+// {prefix}.{i}.{artifact}.data under cache_folder, the artifact is one of
+// "vkey", "transcript", "instance" and "shadow-instance".
+pub fn default_artifact_naming<'a>(
+    cache_folder: &'a Path,
+    prefix: &'a str,
+) -> impl Fn(usize, &str) -> PathBuf + 'a {
+    move |i, artifact| cache_folder.join(format!("{}.{}.{}.data", prefix, i, artifact))
+}
+
+/* CARE: unsafe means that to review before used in real production */
+// Same as run_circuit_unsafe_full_pass_no_rec, but the path of each target artifact is
+// naming(circuit index, artifact). The params file is still cached under cache_folder.
+pub fn run_circuit_unsafe_full_pass_no_rec_with_naming<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+    C: Circuit<E::Scalar>,
+>(
+    cache_folder: &Path,
+    k: u32,
+    circuits: Vec<C>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    hash: TranscriptHash,
+    commitment_check: Vec<[usize; 4]>,
+    expose: Vec<[usize; 2]>,
+    max_public_instance: Vec<Vec<usize>>,
+    force_create_proof: bool,
+    naming: impl Fn(usize, &str) -> PathBuf,
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    run_circuit_unsafe_full_pass_impl::<E, C, _>(
         cache_folder,
-        prefix,
         k,
         circuits,
         instances,
         shadow_instances,
         force_create_proof,
         &AggregatorConfig::new_for_non_rec(hash, commitment_check, expose, max_public_instance),
+        &naming,
+        &|_| {},
+        &mut OsRng,
+        create_target_proofs::<E, C>,
     )
 }
 
@@ -305,21 +787,116 @@ pub fn calc_hash<C: CurveAffine>(
     res
 }
 
+// Replay calc_hash and check the final hashes of a recursive aggregation chain,
+// computed_hashes[i] is the final hash of the (i + 1)-th round.
+pub fn verify_hash_chain<C: CurveAffine>(
+    computed_hashes: &[C::Scalar],
+    constant_hash_cont: [C::Scalar; 3],
+    constant_hash_tail: [C::Scalar; 3],
+) -> bool {
+    let expected = calc_hash::<C>(
+        constant_hash_cont,
+        constant_hash_tail,
+        computed_hashes.len(),
+    );
+    expected[..] == computed_hashes[..]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigValidationError {
+    OutOfBoundsProofIndex {
+        field: &'static str,
+        index: usize,
+        n_proofs: usize,
+    },
+    OutOfBoundsColumnIndex {
+        field: &'static str,
+        proof_idx: usize,
+        col_idx: usize,
+        n_cols: usize,
+    },
+    InvalidAbsorbTarget {
+        reason: String,
+    },
+    InvalidTargetProofMaxInstance(String),
+}
+
+// Extra public inputs reserved on top of the largest instance column when building ParamsVerifier.
+const PUBLIC_INPUTS_SIZE_PADDING: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapacityError {
+    pub required_public_inputs_size: usize,
+    pub available: usize,
+}
+
+// Field elements of a json config are hex strings, e.g. (proof_index, instance_col, "0x1a2b").
+pub mod field_hex {
+    use halo2_proofs::arithmetic::FieldExt;
+    use halo2ecc_s::utils::bn_to_field;
+    use halo2ecc_s::utils::field_to_bn;
+    use num_bigint::BigUint;
+    use serde::de::Error;
+    use serde::Deserialize;
+    use serde::Deserializer;
+    use serde::Serialize;
+    use serde::Serializer;
+
+    pub fn to_hex<F: FieldExt>(f: &F) -> String {
+        format!("0x{}", field_to_bn(f).to_str_radix(16))
+    }
+
+    pub fn from_hex<F: FieldExt>(s: &str) -> Option<F> {
+        let bn = BigUint::parse_bytes(s.trim_start_matches("0x").as_bytes(), 16)?;
+        let f = bn_to_field::<F>(&bn);
+        // Reject values that are not reduced by the field modulus.
+        if field_to_bn(&f) == bn {
+            Some(f)
+        } else {
+            None
+        }
+    }
+
+    pub fn serialize<F: FieldExt, S: Serializer>(
+        v: &Vec<(usize, usize, F)>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        v.iter()
+            .map(|(proof_idx, col, f)| (*proof_idx, *col, to_hex(f)))
+            .collect::<Vec<_>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, F: FieldExt, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<(usize, usize, F)>, D::Error> {
+        Vec::<(usize, usize, String)>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(proof_idx, col, s)| {
+                from_hex(&s)
+                    .map(|f| (proof_idx, col, f))
+                    .ok_or_else(|| D::Error::custom(format!("invalid field element {}", s)))
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub struct AggregatorConfig<F: FieldExt> {
     pub hash: TranscriptHash,
     pub commitment_check: Vec<[usize; 4]>,
     pub expose: Vec<[usize; 2]>,
     pub absorb: Vec<([usize; 3], [usize; 2])>,
     /* (proof_index, instance_col, hash) */
+    #[serde(with = "field_hex")]
     pub target_aggregator_constant_hash_instance_offset: Vec<(usize, usize, F)>,
-    /* the set of proof that genearted with shplonk (if target_proof_with_shplonk_as_default is false) */
-    pub target_proof_with_shplonk: Vec<usize>,
-    pub target_proof_with_shplonk_as_default: bool,
+    /* multi-open protocol of each target proof */
+    pub target_proof_protocol: Vec<ProofProtocol>,
     pub target_proof_max_instance: Vec<Vec<usize>>,
 
     // Absorb instance in each agg.
-    // (proof_idx_of_target, columns, proof_idx_of_prev_agg, expose_row)
-    pub absorb_instance: Vec<(usize, usize, usize, usize)>,
+    // (proof_idx_of_target, columns, proof_idx_of_prev_agg, column_of_prev_agg, expose_row)
+    pub absorb_instance: Vec<(usize, usize, usize, usize, usize)>,
 
     // final aggregator is a different aggregator, it merge all instance into a hash
     pub is_final_aggregator: bool,
@@ -331,20 +908,217 @@ pub struct AggregatorConfig<F: FieldExt> {
 }
 
 impl<F: FieldExt> AggregatorConfig<F> {
-    pub fn new_for_non_rec(
-        hash: TranscriptHash,
-        commitment_check: Vec<[usize; 4]>,
-        expose: Vec<[usize; 2]>,
-        target_proof_max_instance: Vec<Vec<usize>>,
-    ) -> Self {
-        Self {
-            hash,
-            commitment_check,
-            expose,
-            absorb: vec![],
+    // Check the config against the proofs before running into circuit synthesis.
+    pub fn validate(
+        &self,
+        n_proofs: usize,
+        instance_col_count: &[usize],
+    ) -> Result<(), ConfigValidationError> {
+        if instance_col_count.len() != n_proofs {
+            return Err(ConfigValidationError::InvalidTargetProofMaxInstance(
+                format!(
+                    "expect instance column count for {} proofs, got {}",
+                    n_proofs,
+                    instance_col_count.len()
+                ),
+            ));
+        }
+
+        let check_proof = |field: &'static str, index: usize| {
+            if index < n_proofs {
+                Ok(())
+            } else {
+                Err(ConfigValidationError::OutOfBoundsProofIndex {
+                    field,
+                    index,
+                    n_proofs,
+                })
+            }
+        };
+
+        let check_proof_col = |field: &'static str,
+                               proof_idx: usize,
+                               col_idx: usize|
+         -> Result<(), ConfigValidationError> {
+            check_proof(field, proof_idx)?;
+            if col_idx < instance_col_count[proof_idx] {
+                Ok(())
+            } else {
+                Err(ConfigValidationError::OutOfBoundsColumnIndex {
+                    field,
+                    proof_idx,
+                    col_idx,
+                    n_cols: instance_col_count[proof_idx],
+                })
+            }
+        };
+
+        for check in self.commitment_check.iter() {
+            check_proof("commitment_check", check[0])?;
+            check_proof("commitment_check", check[2])?;
+        }
+
+        for expose in self.expose.iter() {
+            check_proof("expose", expose[0])?;
+        }
+
+        for (i, absorb) in self.absorb_instance.iter().enumerate() {
+            let (proof_idx_of_target, columns, proof_idx_of_prev_agg, column_of_prev_agg, row) =
+                *absorb;
+            check_proof_col("absorb_instance", proof_idx_of_target, columns)?;
+            check_proof_col("absorb_instance", proof_idx_of_prev_agg, column_of_prev_agg)?;
+
+            if proof_idx_of_target == proof_idx_of_prev_agg {
+                return Err(ConfigValidationError::InvalidAbsorbTarget {
+                    reason: format!("{:?} absorbs instance from itself", absorb),
+                });
+            }
+
+            // Each absorb takes 3 rows for the encoded commitment.
+            for other in self.absorb_instance[..i].iter() {
+                if other.2 == proof_idx_of_prev_agg
+                    && other.3 == column_of_prev_agg
+                    && other.4 < row + 3
+                    && row < other.4 + 3
+                {
+                    return Err(ConfigValidationError::InvalidAbsorbTarget {
+                        reason: format!("{:?} overlaps with {:?}", absorb, other),
+                    });
+                }
+            }
+        }
+
+        for (proof_idx, _) in self.prev_aggregator_skip_instance.iter() {
+            check_proof("prev_aggregator_skip_instance", *proof_idx)?;
+        }
+
+        // Surplus entries are ignored, only missing ones would fail when aligning instances.
+        if self.target_proof_max_instance.len() < n_proofs {
+            return Err(ConfigValidationError::InvalidTargetProofMaxInstance(
+                format!(
+                    "expect {} proofs, got {}",
+                    n_proofs,
+                    self.target_proof_max_instance.len()
+                ),
+            ));
+        }
+
+        for (i, max_instance) in self.target_proof_max_instance[..n_proofs]
+            .iter()
+            .enumerate()
+        {
+            if max_instance.len() < instance_col_count[i] {
+                return Err(ConfigValidationError::InvalidTargetProofMaxInstance(
+                    format!(
+                        "proof {} has {} instance columns, got {}",
+                        i,
+                        instance_col_count[i],
+                        max_instance.len()
+                    ),
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Check the advice columns of commitment_check and expose against the advice column count
+    // of each proof, which is only known once the vkeys are loaded.
+    pub fn validate_advice_columns(
+        &self,
+        advice_col_count: &[usize],
+    ) -> Result<(), ConfigValidationError> {
+        let n_proofs = advice_col_count.len();
+        let check_proof_col = |field: &'static str, proof_idx: usize, col_idx: usize| {
+            if proof_idx >= n_proofs {
+                return Err(ConfigValidationError::OutOfBoundsProofIndex {
+                    field,
+                    index: proof_idx,
+                    n_proofs,
+                });
+            }
+            if col_idx >= advice_col_count[proof_idx] {
+                return Err(ConfigValidationError::OutOfBoundsColumnIndex {
+                    field,
+                    proof_idx,
+                    col_idx,
+                    n_cols: advice_col_count[proof_idx],
+                });
+            }
+            Ok(())
+        };
+
+        for check in self.commitment_check.iter() {
+            check_proof_col("commitment_check", check[0], check[1])?;
+            check_proof_col("commitment_check", check[2], check[3])?;
+        }
+
+        for expose in self.expose.iter() {
+            check_proof_col("expose", expose[0], expose[1])?;
+        }
+
+        Ok(())
+    }
+
+    // Checks that params is large enough for the instance columns of target_proof_max_instance,
+    // so that params.verifier() does not fail after the target proofs have been created.
+    pub fn check_params_capacity<E: MultiMillerLoop<Scalar = F>>(
+        config: &AggregatorConfig<E::Scalar>,
+        params: &Params<E::G1Affine>,
+    ) -> Result<(), CapacityError> {
+        let required_public_inputs_size = config
+            .target_proof_max_instance
+            .iter()
+            .flatten()
+            .cloned()
+            .max()
+            .unwrap_or(0)
+            + PUBLIC_INPUTS_SIZE_PADDING;
+        let available = params.n as usize - 1;
+
+        if required_public_inputs_size > available {
+            return Err(CapacityError {
+                required_public_inputs_size,
+                available,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Proofs out of target_proof_protocol (e.g. the previous aggregator) use the default of hash.
+    // Proofs with a non-poseidon transcript are always verified by solidity/gnark and use shplonk,
+    // so that switching hash for the final aggregator keeps working.
+    pub fn proof_protocol(&self, i: usize) -> ProofProtocol {
+        if self.hash != TranscriptHash::Poseidon {
+            return ProofProtocol::SHPLONK;
+        }
+        self.target_proof_protocol
+            .get(i)
+            .cloned()
+            .unwrap_or(ProofProtocol::default_for(self.hash))
+    }
+
+    pub fn proof_protocols(&self, n_proofs: usize) -> Vec<ProofProtocol> {
+        (0..n_proofs).map(|i| self.proof_protocol(i)).collect()
+    }
+
+    pub fn new_for_non_rec(
+        hash: TranscriptHash,
+        commitment_check: Vec<[usize; 4]>,
+        expose: Vec<[usize; 2]>,
+        target_proof_max_instance: Vec<Vec<usize>>,
+    ) -> Self {
+        Self {
+            hash,
+            commitment_check,
+            expose,
+            absorb: vec![],
             target_aggregator_constant_hash_instance_offset: vec![],
-            target_proof_with_shplonk: vec![],
-            target_proof_with_shplonk_as_default: false,
+            target_proof_protocol: vec![
+                ProofProtocol::default_for(hash);
+                target_proof_max_instance.len()
+            ],
             target_proof_max_instance,
             is_final_aggregator: true,
             prev_aggregator_skip_instance: vec![],
@@ -353,6 +1127,35 @@ impl<F: FieldExt> AggregatorConfig<F> {
         }
     }
 
+    // target_proof_max_instance of the given target instances, each column is padded by extra_rows.
+    // Prefer it over a hand written value for new_for_non_rec when the instances are known.
+    pub fn infer_max_instance(instances: &[Vec<Vec<F>>], extra_rows: usize) -> Vec<Vec<usize>> {
+        instances
+            .iter()
+            .map(|proof_instances| {
+                proof_instances
+                    .iter()
+                    .map(|column| column.len() + extra_rows)
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Derive a conservative config from target vkeys, without commitment check, expose or absorb.
+    // Each instance column of the targets is aligned to max_instance_rows.
+    pub fn from_vkeys_analysis<E: MultiMillerLoop<Scalar = F>>(
+        vkeys: &[&VerifyingKey<E::G1Affine>],
+        hash: TranscriptHash,
+        max_instance_rows: usize,
+    ) -> Self {
+        let target_proof_max_instance = vkeys
+            .iter()
+            .map(|vkey| vec![max_instance_rows; vkey.cs.num_instance_columns])
+            .collect();
+
+        Self::new_for_non_rec(hash, vec![], vec![], target_proof_max_instance)
+    }
+
     pub fn default_aggregator_config(
         hash: TranscriptHash,
         target_proof_max_instance: Vec<Vec<usize>>,
@@ -364,8 +1167,10 @@ impl<F: FieldExt> AggregatorConfig<F> {
             expose: vec![],
             absorb: vec![],
             target_aggregator_constant_hash_instance_offset: vec![],
-            target_proof_with_shplonk: vec![],
-            target_proof_with_shplonk_as_default: false,
+            target_proof_protocol: vec![
+                ProofProtocol::default_for(hash);
+                target_proof_max_instance.len()
+            ],
             target_proof_max_instance,
             is_final_aggregator,
             prev_aggregator_skip_instance: vec![],
@@ -373,13 +1178,600 @@ impl<F: FieldExt> AggregatorConfig<F> {
             use_select_chip: !is_final_aggregator,
         }
     }
+
+    // Config of the final aggregator, the previous aggregator proof goes after the target proofs
+    // and its instance rows before prev_agg_hash_row are skipped in the final hash as they
+    // have been absorbed. target_aggregator_constant_hash_instance_offset is left to the caller.
+    pub fn default_final_aggregator_config(
+        hash: TranscriptHash,
+        prev_agg_instance_len: usize,
+        prev_agg_hash_row: usize,
+        target_proof_max_instance: Vec<Vec<usize>>,
+    ) -> Self {
+        assert!(prev_agg_hash_row <= prev_agg_instance_len);
+
+        let prev_agg_idx = target_proof_max_instance.len();
+        let mut config = Self::default_aggregator_config(hash, target_proof_max_instance, true);
+        config
+            .target_proof_max_instance
+            .push(vec![prev_agg_instance_len]);
+        config.prev_aggregator_skip_instance = vec![(prev_agg_idx, prev_agg_hash_row)];
+        config
+    }
+
+    // Config of the next recursive round. The previous aggregator proof goes after the
+    // target proofs, prev_hash is its constant hash instance (the first instance).
+    // absorb_instance entries are moved to the new previous aggregator, and options of
+    // the final aggregator are left to the caller.
+    pub fn clone_with_next_round(&self, prev_hash: F, prev_agg_instance_len: usize) -> Self {
+        let prev_agg_idx = self
+            .target_aggregator_constant_hash_instance_offset
+            .first()
+            .map(|(proof_idx, _, _)| *proof_idx)
+            .unwrap_or(self.target_proof_max_instance.len());
+
+        let mut target_proof_max_instance = self.target_proof_max_instance.clone();
+        target_proof_max_instance.truncate(prev_agg_idx);
+        target_proof_max_instance.push(vec![prev_agg_instance_len]);
+
+        Self {
+            hash: self.hash,
+            commitment_check: self.commitment_check.clone(),
+            expose: self.expose.clone(),
+            absorb: self.absorb.clone(),
+            target_aggregator_constant_hash_instance_offset: vec![(prev_agg_idx, 0, prev_hash)],
+            target_proof_protocol: self.target_proof_protocol.clone(),
+            target_proof_max_instance,
+            absorb_instance: self
+                .absorb_instance
+                .iter()
+                .map(|(target_idx, col, _, prev_col, row)| {
+                    (*target_idx, *col, prev_agg_idx, *prev_col, *row)
+                })
+                .collect(),
+            is_final_aggregator: self.is_final_aggregator,
+            prev_aggregator_skip_instance: self.prev_aggregator_skip_instance.clone(),
+            use_select_chip: self.use_select_chip,
+        }
+    }
+
+    // absorb_instance of this round for the commitments exposed by the previous aggregator,
+    // which was built with prev_config. The proof index and instance column of the previous
+    // aggregator are taken from target_aggregator_constant_hash_instance_offset, e.g. as set
+    // by clone_with_next_round. Its instance is [aggregator_hash, instance commitments of
+    // its targets, exposed commitments] and each commitment takes 3 rows.
+    // The i-th exposed commitment is absorbed by absorbing_columns[i], a
+    // (proof_idx_of_target, column) of this round.
+    pub fn derive_absorb_instance(
+        &self,
+        prev_config: &AggregatorConfig<F>,
+        absorbing_columns: &[(usize, usize)],
+    ) -> Vec<(usize, usize, usize, usize, usize)> {
+        assert!(!prev_config.is_final_aggregator);
+        assert_eq!(absorbing_columns.len(), prev_config.expose.len());
+
+        let (proof_idx_of_prev_agg, column_of_prev_agg, _) = *self
+            .target_aggregator_constant_hash_instance_offset
+            .first()
+            .expect("no previous aggregator in target_aggregator_constant_hash_instance_offset");
+
+        let n_instance_commitments = prev_config
+            .target_proof_max_instance
+            .iter()
+            .map(|cols| cols.len())
+            .sum::<usize>();
+        let expose_start_row = 1 + n_instance_commitments * 3;
+
+        absorbing_columns
+            .iter()
+            .enumerate()
+            .map(|(i, (proof_idx_of_target, column))| {
+                (
+                    *proof_idx_of_target,
+                    *column,
+                    proof_idx_of_prev_agg,
+                    column_of_prev_agg,
+                    expose_start_row + i * 3,
+                )
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AggregatorProgress {
+    ProofGenerated { index: usize, elapsed_ms: u64 },
+    NativeVerifyDone,
+    CircuitSynthesisStarted,
+    CircuitSynthesisDone { rows_used: usize },
+}
+
+/* CARE: unsafe means that to review before used in production */
+pub fn run_circuit_unsafe_full_pass<
+    'a,
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+    C: Circuit<E::Scalar>,
+>(
+    cache_folder: &'a Path,
+    prefix: &'a str,
+    k: u32,
+    circuits: Vec<C>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    run_circuit_unsafe_full_pass_with_progress::<E, C>(
+        cache_folder,
+        prefix,
+        k,
+        circuits,
+        instances,
+        shadow_instances,
+        force_create_proof,
+        config,
+        |_| {},
+    )
+}
+
+/* CARE: unsafe means that to review before used in production */
+// Same as run_circuit_unsafe_full_pass, but target proofs draw their blinding randomness
+// from rng, a seeded rng gives reproducible proofs for test vectors.
+pub fn run_circuit_unsafe_full_pass_with_rng<
+    'a,
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+    C: Circuit<E::Scalar>,
+    R: RngCore + CryptoRng,
+>(
+    cache_folder: &'a Path,
+    prefix: &'a str,
+    k: u32,
+    circuits: Vec<C>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+    mut rng: R,
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    run_circuit_unsafe_full_pass_impl::<E, C, _>(
+        cache_folder,
+        k,
+        circuits,
+        instances,
+        shadow_instances,
+        force_create_proof,
+        config,
+        &default_artifact_naming(cache_folder, prefix),
+        &|_| {},
+        &mut rng,
+        create_target_proofs::<E, C>,
+    )
+}
+
+/* CARE: unsafe means that to review before used in production */
+pub fn run_circuit_unsafe_full_pass_with_progress<
+    'a,
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+    C: Circuit<E::Scalar>,
+>(
+    cache_folder: &'a Path,
+    prefix: &'a str,
+    k: u32,
+    circuits: Vec<C>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+    progress_cb: impl Fn(AggregatorProgress),
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    run_circuit_unsafe_full_pass_impl::<E, C, _>(
+        cache_folder,
+        k,
+        circuits,
+        instances,
+        shadow_instances,
+        force_create_proof,
+        config,
+        &default_artifact_naming(cache_folder, prefix),
+        &progress_cb,
+        &mut OsRng,
+        create_target_proofs::<E, C>,
+    )
+}
+
+// A target proof to create, with the rng drawn for it from the caller's rng so that
+// the proofs do not depend on the order they are created in.
+struct TargetProofJob<E: MultiMillerLoop, C> {
+    index: usize,
+    circuit: C,
+    vkey: VerifyingKey<E::G1Affine>,
+    cache_file: PathBuf,
+    rng: StdRng,
+}
+
+fn create_target_proof_job<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
+    params: &Params<E::G1Affine>,
+    job: TargetProofJob<E, C>,
+    instances: &Vec<Vec<E::Scalar>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+) -> (Vec<u8>, u64) {
+    let start = Instant::now();
+    let proof = load_or_create_proof_with_rng::<E, C, _>(
+        params,
+        job.vkey,
+        job.circuit,
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        Some(&job.cache_file),
+        config.hash,
+        !force_create_proof,
+        config.proof_protocol(job.index),
+        job.rng,
+    );
+    (proof, start.elapsed().as_millis() as u64)
+}
+
+fn create_target_proofs<E: MultiMillerLoop, C: Circuit<E::Scalar>>(
+    params: &Params<E::G1Affine>,
+    jobs: Vec<TargetProofJob<E, C>>,
+    instances: &[Vec<Vec<E::Scalar>>],
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+    progress_cb: &dyn Fn(AggregatorProgress),
+) -> Vec<Vec<u8>> {
+    jobs.into_iter()
+        .map(|job| {
+            let index = job.index;
+            let (proof, elapsed_ms) =
+                create_target_proof_job(params, job, &instances[index], force_create_proof, config);
+            progress_cb(AggregatorProgress::ProofGenerated { index, elapsed_ms });
+            proof
+        })
+        .collect()
+}
+
+// progress_cb is not Sync, so ProofGenerated is reported in index order once all proofs are done.
+fn create_target_proofs_parallel<E: MultiMillerLoop, C: Circuit<E::Scalar> + Send>(
+    params: &Params<E::G1Affine>,
+    jobs: Vec<TargetProofJob<E, C>>,
+    instances: &[Vec<Vec<E::Scalar>>],
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+    progress_cb: &dyn Fn(AggregatorProgress),
+) -> Vec<Vec<u8>> {
+    let res = jobs
+        .into_par_iter()
+        .map(|job| {
+            let index = job.index;
+            create_target_proof_job(params, job, &instances[index], force_create_proof, config)
+        })
+        .collect::<Vec<_>>();
+
+    res.into_iter()
+        .enumerate()
+        .map(|(index, (proof, elapsed_ms))| {
+            progress_cb(AggregatorProgress::ProofGenerated { index, elapsed_ms });
+            proof
+        })
+        .collect()
+}
+
+fn run_circuit_unsafe_full_pass_impl<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+    C: Circuit<E::Scalar>,
+    R: RngCore + CryptoRng,
+>(
+    cache_folder: &Path,
+    k: u32,
+    circuits: Vec<C>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+    naming: &dyn Fn(usize, &str) -> PathBuf,
+    progress_cb: &dyn Fn(AggregatorProgress),
+    rng: &mut R,
+    create_proofs: fn(
+        &Params<E::G1Affine>,
+        Vec<TargetProofJob<E, C>>,
+        &[Vec<Vec<E::Scalar>>],
+        bool,
+        &AggregatorConfig<E::Scalar>,
+        &dyn Fn(AggregatorProgress),
+    ) -> Vec<Vec<u8>>,
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    config
+        .validate(
+            circuits.len(),
+            &instances.iter().map(|x| x.len()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+    // 1. setup params
+    let params =
+        load_or_build_unsafe_params::<E>(k, Some(&cache_folder.join(format!("K{}.params", k))));
+    AggregatorConfig::check_params_capacity::<E>(config, &params).unwrap();
+
+    // 2. setup vkey, keep it sequential
+    let jobs = circuits
+        .into_iter()
+        .enumerate()
+        .map(|(i, circuit)| {
+            let vkey = load_or_build_vkey::<E, C>(&params, &circuit, Some(&naming(i, "vkey")));
+            let mut seed = [0u8; 32];
+            rng.fill_bytes(&mut seed);
+            TargetProofJob {
+                index: i,
+                circuit,
+                vkey,
+                cache_file: naming(i, "transcript"),
+                rng: StdRng::from_seed(seed),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // 3. create proof
+    let proofs = create_proofs(
+        &params,
+        jobs,
+        &instances,
+        force_create_proof,
+        config,
+        progress_cb,
+    );
+
+    verify_and_build_aggregate_verify_circuit::<E>(
+        naming,
+        &params,
+        proofs,
+        instances,
+        shadow_instances,
+        config,
+        &|_, cache_file| load_vkey::<E, C>(&params, cache_file),
+        progress_cb,
+    )
+}
+
+// Object safe view of a target circuit so that circuits of different types can be
+// aggregated in the same batch.
+pub trait ErasedCircuit<E: MultiMillerLoop> {
+    fn load_or_build_vkey(
+        &self,
+        params: &Params<E::G1Affine>,
+        cache_file_opt: Option<&Path>,
+    ) -> VerifyingKey<E::G1Affine>;
+
+    fn vkey_loader(&self) -> fn(&Params<E::G1Affine>, &Path) -> VerifyingKey<E::G1Affine>;
+
+    fn create_target_proof(
+        self: Box<Self>,
+        params: &Params<E::G1Affine>,
+        vkey: VerifyingKey<E::G1Affine>,
+        naming: &dyn Fn(usize, &str) -> PathBuf,
+        i: usize,
+        instances: &Vec<Vec<E::Scalar>>,
+        force_create_proof: bool,
+        config: &AggregatorConfig<E::Scalar>,
+    ) -> Vec<u8>;
+}
+
+impl<E: MultiMillerLoop, C: Circuit<E::Scalar>> ErasedCircuit<E> for C {
+    fn load_or_build_vkey(
+        &self,
+        params: &Params<E::G1Affine>,
+        cache_file_opt: Option<&Path>,
+    ) -> VerifyingKey<E::G1Affine> {
+        load_or_build_vkey::<E, C>(params, self, cache_file_opt)
+    }
+
+    fn vkey_loader(&self) -> fn(&Params<E::G1Affine>, &Path) -> VerifyingKey<E::G1Affine> {
+        load_vkey::<E, C>
+    }
+
+    fn create_target_proof(
+        self: Box<Self>,
+        params: &Params<E::G1Affine>,
+        vkey: VerifyingKey<E::G1Affine>,
+        naming: &dyn Fn(usize, &str) -> PathBuf,
+        i: usize,
+        instances: &Vec<Vec<E::Scalar>>,
+        force_create_proof: bool,
+        config: &AggregatorConfig<E::Scalar>,
+    ) -> Vec<u8> {
+        create_target_proof::<E, C, _>(
+            params,
+            vkey,
+            *self,
+            naming,
+            i,
+            instances,
+            force_create_proof,
+            config,
+            OsRng,
+        )
+    }
+}
+
+/* CARE: unsafe means that to review before used in production */
+// Same as run_circuit_unsafe_full_pass but the target circuits may have different types,
+// each vkey is built and loaded by its own circuit.
+pub fn run_circuit_unsafe_full_pass_heterogeneous<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+>(
+    cache_folder: &Path,
+    prefix: &str,
+    k: u32,
+    circuits: Vec<Box<dyn ErasedCircuit<E>>>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    config
+        .validate(
+            circuits.len(),
+            &instances.iter().map(|x| x.len()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+
+    // 1. setup params
+    let params =
+        load_or_build_unsafe_params::<E>(k, Some(&cache_folder.join(format!("K{}.params", k))));
+    AggregatorConfig::check_params_capacity::<E>(config, &params).unwrap();
+
+    let naming = default_artifact_naming(cache_folder, prefix);
+    let mut proofs = vec![];
+    let mut vkey_loaders = vec![];
+    for (i, circuit) in circuits.into_iter().enumerate() {
+        // 2. setup vkey
+        let vkey = circuit.load_or_build_vkey(&params, Some(&naming(i, "vkey")));
+        vkey_loaders.push(circuit.vkey_loader());
+
+        // 3. create proof
+        let proof = circuit.create_target_proof(
+            &params,
+            vkey,
+            &naming,
+            i,
+            &instances[i],
+            force_create_proof,
+            config,
+        );
+        proofs.push(proof);
+    }
+
+    verify_and_build_aggregate_verify_circuit::<E>(
+        &naming,
+        &params,
+        proofs,
+        instances,
+        shadow_instances,
+        config,
+        &|i, cache_file| vkey_loaders[i](&params, cache_file),
+        &|_| {},
+    )
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AggregatorTiming {
+    pub proof_gen_ms: Vec<u64>,
+    // from the last generated proof to the end of native verification
+    pub native_verify_ms: u64,
+    pub agg_circuit_build_ms: u64,
+    pub elapsed_ms: u64,
+}
+
+/* CARE: unsafe means that to review before used in production */
+pub fn run_circuit_unsafe_full_pass_timed<
+    'a,
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+    C: Circuit<E::Scalar>,
+>(
+    cache_folder: &'a Path,
+    prefix: &'a str,
+    k: u32,
+    circuits: Vec<C>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+) -> (
+    Option<(
+        AggregatorCircuitOption<E::G1Affine>,
+        Vec<E::Scalar>,
+        Vec<E::Scalar>,
+        E::Scalar,
+    )>,
+    AggregatorTiming,
+)
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    let start = Instant::now();
+    let last = RefCell::new(Instant::now());
+    let timing = RefCell::new(AggregatorTiming::default());
+
+    let res = run_circuit_unsafe_full_pass_with_progress::<E, C>(
+        cache_folder,
+        prefix,
+        k,
+        circuits,
+        instances,
+        shadow_instances,
+        force_create_proof,
+        config,
+        |progress| {
+            let elapsed_ms = last.borrow().elapsed().as_millis() as u64;
+            let mut timing = timing.borrow_mut();
+            match progress {
+                AggregatorProgress::ProofGenerated { elapsed_ms, .. } => {
+                    timing.proof_gen_ms.push(elapsed_ms)
+                }
+                AggregatorProgress::NativeVerifyDone => timing.native_verify_ms = elapsed_ms,
+                AggregatorProgress::CircuitSynthesisStarted => {}
+                AggregatorProgress::CircuitSynthesisDone { .. } => {
+                    timing.agg_circuit_build_ms = elapsed_ms
+                }
+            }
+            *last.borrow_mut() = Instant::now();
+        },
+    );
+
+    let mut timing = timing.into_inner();
+    timing.elapsed_ms = start.elapsed().as_millis() as u64;
+    (res, timing)
 }
 
 /* CARE: unsafe means that to review before used in production */
-pub fn run_circuit_unsafe_full_pass<
+// Same as run_circuit_unsafe_full_pass_with_rng, but target proofs are created concurrently.
+// It is a separate entry because it needs Send circuits, which AggregatorCircuit is not.
+// Each proof draws its rng from rng in index order, so the proofs are the same as the
+// sequential ones for the same rng.
+pub fn run_circuit_unsafe_full_pass_parallel<
     'a,
     E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
-    C: Circuit<E::Scalar>,
+    C: Circuit<E::Scalar> + Send,
+    R: RngCore + CryptoRng,
 >(
     cache_folder: &'a Path,
     prefix: &'a str,
@@ -389,6 +1781,8 @@ pub fn run_circuit_unsafe_full_pass<
     shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
     force_create_proof: bool,
     config: &AggregatorConfig<E::Scalar>,
+    progress_cb: impl Fn(AggregatorProgress),
+    mut rng: R,
 ) -> Option<(
     AggregatorCircuitOption<E::G1Affine>,
     Vec<E::Scalar>,
@@ -398,54 +1792,80 @@ pub fn run_circuit_unsafe_full_pass<
 where
     NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
 {
-    let hash = config.hash;
-
-    // 1. setup params
-    let params =
-        load_or_build_unsafe_params::<E>(k, Some(&cache_folder.join(format!("K{}.params", k))));
+    run_circuit_unsafe_full_pass_impl::<E, C, _>(
+        cache_folder,
+        k,
+        circuits,
+        instances,
+        shadow_instances,
+        force_create_proof,
+        config,
+        &default_artifact_naming(cache_folder, prefix),
+        &progress_cb,
+        &mut rng,
+        create_target_proofs_parallel::<E, C>,
+    )
+}
 
-    let mut proofs = vec![];
-    for (i, circuit) in circuits.into_iter().enumerate() {
-        // 2. setup vkey
-        let vkey = load_or_build_vkey::<E, C>(
-            &params,
-            &circuit,
-            Some(&cache_folder.join(format!("{}.{}.vkey.data", prefix, i))),
-        );
+fn create_target_proof<E: MultiMillerLoop, C: Circuit<E::Scalar>, R: RngCore + CryptoRng>(
+    params: &Params<E::G1Affine>,
+    vkey: VerifyingKey<E::G1Affine>,
+    circuit: C,
+    naming: &dyn Fn(usize, &str) -> PathBuf,
+    i: usize,
+    instances: &Vec<Vec<E::Scalar>>,
+    force_create_proof: bool,
+    config: &AggregatorConfig<E::Scalar>,
+    rng: R,
+) -> Vec<u8> {
+    load_or_create_proof_with_rng::<E, C, _>(
+        params,
+        vkey,
+        circuit,
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        Some(&naming(i, "transcript")),
+        config.hash,
+        !force_create_proof,
+        config.proof_protocol(i),
+        rng,
+    )
+}
 
-        // 3. create proof
-        let proof = load_or_create_proof::<E, C>(
-            &params,
-            vkey,
-            circuit,
-            &instances[i].iter().map(|x| &x[..]).collect::<Vec<_>>(),
-            Some(&cache_folder.join(format!("{}.{}.transcript.data", prefix, i))),
-            config.hash,
-            !force_create_proof,
-            hash != TranscriptHash::Poseidon
-                || config.target_proof_with_shplonk_as_default
-                || config.target_proof_with_shplonk.contains(&i),
-        );
-        proofs.push(proof);
+fn verify_and_build_aggregate_verify_circuit<
+    E: MultiMillerLoop + G2AffineBaseHelper + GtHelper + MultiMillerLoopOnProvePairing,
+>(
+    naming: &dyn Fn(usize, &str) -> PathBuf,
+    params: &Params<E::G1Affine>,
+    proofs: Vec<Vec<u8>>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    shadow_instances: Vec<Vec<Vec<E::Scalar>>>,
+    config: &AggregatorConfig<E::Scalar>,
+    load_vkey_at: &dyn Fn(usize, &Path) -> VerifyingKey<E::G1Affine>,
+    progress_cb: &dyn Fn(AggregatorProgress),
+) -> Option<(
+    AggregatorCircuitOption<E::G1Affine>,
+    Vec<E::Scalar>,
+    Vec<E::Scalar>,
+    E::Scalar,
+)>
+where
+    NativeScalarEccContext<E::G1Affine>: PairingChipOnProvePairingOps<E::G1Affine, E::Scalar>,
+{
+    let hash = config.hash;
 
+    for i in 0..proofs.len() {
         let mut aligned_instances = instances[i].clone();
         // We need to align instance to max according to config
         for j in 0..instances[i].len() {
             assert!(instances[i][j].len() <= config.target_proof_max_instance[i][j]);
             aligned_instances[j].resize(config.target_proof_max_instance[i][j], E::Scalar::zero());
         }
-        store_instance(
-            &aligned_instances,
-            &cache_folder.join(format!("{}.{}.instance.data", prefix, i)),
-        );
+        store_instance(&aligned_instances, &naming(i, "instance"));
 
         if hash != TranscriptHash::Poseidon {
             // Store fake instaces for solidity verifier when create proof for final aggregator.
             assert!(shadow_instances.len() > i);
-            store_instance(
-                &shadow_instances[i],
-                &cache_folder.join(format!("{}.{}.shadow-instance.data", prefix, i)),
-            );
+            store_instance(&shadow_instances[i], &naming(i, "shadow-instance"));
         }
     }
 
@@ -453,21 +1873,18 @@ where
     let public_inputs_size = instances.iter().fold(0usize, |acc, x| {
         usize::max(acc, x.iter().fold(0, |acc, x| usize::max(acc, x.len())))
     });
-    let params_verifier: ParamsVerifier<E> = params.verifier(public_inputs_size + 10).unwrap();
+    let params_verifier: ParamsVerifier<E> = params
+        .verifier(public_inputs_size + PUBLIC_INPUTS_SIZE_PADDING)
+        .unwrap();
 
     let mut vkeys = vec![];
 
     for (i, proof) in proofs.iter().enumerate() {
-        let vkey = load_vkey::<E, C>(
-            &params,
-            &cache_folder.join(format!("{}.{}.vkey.data", prefix, i)),
-        );
+        let vkey = load_vkey_at(i, &naming(i, "vkey"));
 
         // origin check
         if true {
-            let use_shplonk = hash != TranscriptHash::Poseidon
-                || config.target_proof_with_shplonk_as_default
-                || config.target_proof_with_shplonk.contains(&i);
+            let use_shplonk = config.proof_protocol(i) == ProofProtocol::SHPLONK;
             let timer = start_timer!(|| "origin verify single proof");
             let strategy = SingleVerifier::new(&params_verifier);
             match hash {
@@ -503,6 +1920,30 @@ where
                     &mut ShaRead::<_, _, _, sha3::Keccak256>::init(&proof[..]),
                     !use_shplonk,
                 ),
+                TranscriptHash::Blake2s => verify_proof_ext(
+                    &params_verifier,
+                    &vkey,
+                    strategy,
+                    &[&instances[i].iter().map(|x| &x[..]).collect::<Vec<_>>()[..]],
+                    &mut ShaRead::<_, _, _, blake2::Blake2s256>::init(&proof[..]),
+                    !use_shplonk,
+                ),
+                TranscriptHash::Sha512 => verify_proof_ext(
+                    &params_verifier,
+                    &vkey,
+                    strategy,
+                    &[&instances[i].iter().map(|x| &x[..]).collect::<Vec<_>>()[..]],
+                    &mut ShaRead::<_, _, _, sha2::Sha512>::init(&proof[..]),
+                    !use_shplonk,
+                ),
+                TranscriptHash::Sha3_256 => verify_proof_ext(
+                    &params_verifier,
+                    &vkey,
+                    strategy,
+                    &[&instances[i].iter().map(|x| &x[..]).collect::<Vec<_>>()[..]],
+                    &mut ShaRead::<_, _, _, sha3::Sha3_256>::init(&proof[..]),
+                    !use_shplonk,
+                ),
             }
             .unwrap();
             end_timer!(timer);
@@ -511,23 +1952,29 @@ where
         // native single check
         if true {
             let timer = start_timer!(|| "native verify single proof");
-            for (i, proof) in proofs.iter().enumerate() {
-                crate::native_verifier::verify_single_proof::<E>(
-                    &params_verifier,
-                    &vkey,
-                    &instances[i],
-                    proof.clone(),
-                    hash,
-                    hash != TranscriptHash::Poseidon || config.target_proof_with_shplonk_as_default,
-                    &config.target_proof_with_shplonk,
-                );
-            }
+            crate::native_verifier::verify_single_proof::<E>(
+                &params_verifier,
+                &vkey,
+                &instances[i],
+                proof.clone(),
+                hash,
+                config.proof_protocol(i),
+            );
             end_timer!(timer);
         }
 
         vkeys.push(vkey);
     }
 
+    config
+        .validate_advice_columns(
+            &vkeys
+                .iter()
+                .map(|vkey| vkey.cs.num_advice_columns)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+
     // native multi check
     if true {
         let timer = start_timer!(|| "native verify aggregated proofs");
@@ -538,14 +1985,15 @@ where
             proofs.clone(),
             config.hash,
             &config.commitment_check,
-            hash != TranscriptHash::Poseidon || config.target_proof_with_shplonk_as_default,
-            &config.target_proof_with_shplonk,
+            &config.proof_protocols(proofs.len()),
         );
         end_timer!(timer);
     }
+    progress_cb(AggregatorProgress::NativeVerifyDone);
 
     // circuit multi check
     if hash == TranscriptHash::Poseidon {
+        progress_cb(AggregatorProgress::CircuitSynthesisStarted);
         let timer = start_timer!(|| "build_aggregate_verify_circuit");
         let (circuit, instances, shadow_instance, hash) = build_aggregate_verify_circuit::<E>(
             &params_verifier,
@@ -555,6 +2003,9 @@ where
             config,
         );
         end_timer!(timer);
+        progress_cb(AggregatorProgress::CircuitSynthesisDone {
+            rows_used: circuit.rows_used,
+        });
 
         Some((circuit, instances, shadow_instance, hash))
     } else {
@@ -611,8 +2062,7 @@ where
             Some(&cache_folder.join(format!("{}.{}.transcript.data", prefix, i))),
             config.hash,
             !force_create_proof,
-            config.target_proof_with_shplonk_as_default
-                || config.target_proof_with_shplonk.contains(&i),
+            config.proof_protocol(i),
         );
         proofs.push(proof);
 
@@ -637,7 +2087,7 @@ where
         Some(&cache_folder.join(format!("{}.agg.{}.transcript.data", prefix, prev_agg_idx))),
         config.hash,
         !force_create_proof,
-        config.target_proof_with_shplonk_as_default,
+        config.proof_protocol(proofs.len()),
     );
     proofs.push(prev_agg_proof);
 
@@ -892,3 +2342,596 @@ fn test_checkpairing_with_c_wi() {
     );
     println!("Accumulated f_wi done!");
 }
+
+#[test]
+fn test_config_from_vkeys_analysis() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let config = AggregatorConfig::from_vkeys_analysis::<Bn256>(
+        &[&vkey, &vkey],
+        TranscriptHash::Poseidon,
+        4,
+    );
+    assert_eq!(config.target_proof_max_instance, vec![vec![4], vec![4]]);
+    assert!(config.commitment_check.is_empty());
+    assert!(config.expose.is_empty());
+    assert!(config.absorb_instance.is_empty());
+    config
+        .validate(2, &[instances.len(), instances.len()])
+        .unwrap();
+}
+
+#[test]
+fn test_config_validation_error() {
+    let config = AggregatorConfig::<halo2_proofs::pairing::bn256::Fr>::new_for_non_rec(
+        TranscriptHash::Poseidon,
+        vec![[0, 0, 2, 0]],
+        vec![],
+        vec![vec![1], vec![1]],
+    );
+    assert_eq!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::OutOfBoundsProofIndex {
+            field: "commitment_check",
+            index: 2,
+            n_proofs: 2,
+        })
+    );
+
+    let mut config = AggregatorConfig::<halo2_proofs::pairing::bn256::Fr>::new_for_non_rec(
+        TranscriptHash::Poseidon,
+        vec![],
+        vec![[1, 1]],
+        vec![vec![1], vec![1]],
+    );
+    // expose refers to an advice column, which is not bounded by the instance columns
+    config.validate(2, &[1, 1]).unwrap();
+    config.expose = vec![[2, 0]];
+    assert_eq!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::OutOfBoundsProofIndex {
+            field: "expose",
+            index: 2,
+            n_proofs: 2,
+        })
+    );
+
+    config.expose = vec![];
+    config.absorb_instance = vec![(0, 0, 0, 0, 0)];
+    assert!(matches!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::InvalidAbsorbTarget { .. })
+    ));
+
+    // The indices are checked whatever the transcript hash is.
+    config.hash = TranscriptHash::Keccak;
+    assert!(matches!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::InvalidAbsorbTarget { .. })
+    ));
+    config.absorb_instance = vec![(0, 1, 1, 0, 0)];
+    assert_eq!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::OutOfBoundsColumnIndex {
+            field: "absorb_instance",
+            proof_idx: 0,
+            col_idx: 1,
+            n_cols: 1,
+        })
+    );
+    config.absorb_instance = vec![];
+    config.expose = vec![[2, 0]];
+    assert_eq!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::OutOfBoundsProofIndex {
+            field: "expose",
+            index: 2,
+            n_proofs: 2,
+        })
+    );
+    config.expose = vec![];
+    config.hash = TranscriptHash::Poseidon;
+
+    config.absorb_instance = vec![];
+    config.validate(2, &[1, 1]).unwrap();
+
+    // A surplus target_proof_max_instance entry is accepted, a missing column is not.
+    config.target_proof_max_instance = vec![vec![1], vec![1], vec![1]];
+    config.validate(2, &[1, 1]).unwrap();
+    config.target_proof_max_instance = vec![vec![1], vec![]];
+    assert!(matches!(
+        config.validate(2, &[1, 1]),
+        Err(ConfigValidationError::InvalidTargetProofMaxInstance(_))
+    ));
+
+    config.commitment_check = vec![[0, 1, 1, 3]];
+    config.validate_advice_columns(&[2, 4]).unwrap();
+    assert_eq!(
+        config.validate_advice_columns(&[2, 3]),
+        Err(ConfigValidationError::OutOfBoundsColumnIndex {
+            field: "commitment_check",
+            proof_idx: 1,
+            col_idx: 3,
+            n_cols: 3,
+        })
+    );
+
+    config.commitment_check = vec![];
+    config.expose = vec![[0, 1]];
+    config.validate_advice_columns(&[2, 4]).unwrap();
+    assert_eq!(
+        config.validate_advice_columns(&[1, 4]),
+        Err(ConfigValidationError::OutOfBoundsColumnIndex {
+            field: "expose",
+            proof_idx: 0,
+            col_idx: 1,
+            n_cols: 1,
+        })
+    );
+}
+
+#[test]
+fn test_config_json_round_trip() {
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let mut config = AggregatorConfig::<Fr>::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1], vec![2, 1]],
+        false,
+    );
+    config.commitment_check = vec![[0, 1, 1, 2]];
+    config.expose = vec![[1, 0]];
+    config.absorb = vec![([0, 1, 2], [1, 1])];
+    config.target_aggregator_constant_hash_instance_offset =
+        vec![(0, 0, Fr::from(0x1234u64)), (1, 1, -Fr::one())];
+    config.target_proof_protocol = vec![ProofProtocol::GWC, ProofProtocol::SHPLONK];
+    config.absorb_instance = vec![(1, 0, 0, 0, 3)];
+    config.prev_aggregator_skip_instance = vec![(1, 2)];
+
+    let json = serde_json::to_string(&config).unwrap();
+    assert!(json.contains("\"0x1234\""));
+
+    let decoded: AggregatorConfig<Fr> = serde_json::from_str(&json).unwrap();
+    assert_eq!(
+        decoded.target_aggregator_constant_hash_instance_offset,
+        config.target_aggregator_constant_hash_instance_offset
+    );
+    assert_eq!(decoded.target_proof_protocol, config.target_proof_protocol);
+    assert_eq!(decoded.absorb, config.absorb);
+    assert_eq!(serde_json::to_string(&decoded).unwrap(), json);
+
+    let invalid = json.replace("0x1234", "0xzz");
+    assert!(serde_json::from_str::<AggregatorConfig<Fr>>(&invalid).is_err());
+}
+
+#[test]
+fn test_instance_to_instance_commitment_empty_column() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::group::prime::PrimeCurveAffine;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let empty_instances = vec![vec![]; instances.len()];
+    let commitments = instance_to_instance_commitment_checked(
+        &params_verifier,
+        &[&vkey, &vkey],
+        vec![&instances, &empty_instances],
+    );
+    assert!(commitments[0].iter().all(|x| x.is_some()));
+    assert!(commitments[1].iter().all(|x| x.is_none()));
+
+    let commitments = instance_to_instance_commitment(
+        &params_verifier,
+        &[&vkey, &vkey],
+        vec![&instances, &empty_instances],
+    );
+    assert!(commitments[1].iter().all(|x| bool::from(x.is_identity())));
+}
+
+#[test]
+fn test_instance_to_instance_commitment_batched() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let (_, other_instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let empty_instances = vec![vec![]; instances.len()];
+    let all_instances = vec![&instances, &empty_instances, &other_instances];
+    assert_eq!(
+        instance_to_instance_commitment_batched(
+            &params_verifier,
+            &[&vkey, &vkey, &vkey],
+            all_instances.clone(),
+        ),
+        instance_to_instance_commitment(&params_verifier, &[&vkey, &vkey, &vkey], all_instances)
+    );
+}
+
+#[test]
+fn test_unsafe_params_seeded() {
+    use halo2_proofs::pairing::bn256::Bn256;
+
+    let seed = [7u8; 32];
+    let params0 = load_or_build_unsafe_params_seeded::<Bn256>(4, seed, None);
+    let params1 = load_or_build_unsafe_params_seeded::<Bn256>(4, seed, None);
+
+    let mut buf0 = vec![];
+    let mut buf1 = vec![];
+    params0.write(&mut buf0).unwrap();
+    params1.write(&mut buf1).unwrap();
+    assert_eq!(buf0, buf1);
+
+    let params2 = load_or_build_unsafe_params_seeded::<Bn256>(4, [8u8; 32], None);
+    let mut buf2 = vec![];
+    params2.write(&mut buf2).unwrap();
+    assert_ne!(buf0, buf2);
+}
+
+#[test]
+fn test_verify_hash_chain() {
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+
+    let hash_cont = [Fr::from(1u64), Fr::from(2u64), Fr::from(3u64)];
+    let hash_tail = [Fr::from(4u64), Fr::from(5u64), Fr::from(6u64)];
+    let mut hashes = calc_hash::<G1Affine>(hash_cont, hash_tail, 5);
+
+    let verify = verify_hash_chain::<G1Affine>;
+
+    assert!(verify(&hashes, hash_cont, hash_tail));
+    assert!(verify(&hashes[..2], hash_cont, hash_tail));
+    assert!(!verify(&hashes, hash_tail, hash_cont));
+
+    hashes[3] += Fr::one();
+    assert!(!verify(&hashes, hash_cont, hash_tail));
+}
+
+#[test]
+fn test_config_infer_max_instance() {
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let instances = vec![vec![vec![Fr::one(); 3], vec![]], vec![vec![Fr::zero(); 1]]];
+    assert_eq!(
+        AggregatorConfig::infer_max_instance(&instances, 0),
+        vec![vec![3, 0], vec![1]]
+    );
+    assert_eq!(
+        AggregatorConfig::infer_max_instance(&instances, 2),
+        vec![vec![5, 2], vec![3]]
+    );
+
+    let config = AggregatorConfig::new_for_non_rec(
+        TranscriptHash::Poseidon,
+        vec![],
+        vec![],
+        AggregatorConfig::infer_max_instance(&instances, 0),
+    );
+    config
+        .validate(
+            instances.len(),
+            &instances.iter().map(|x| x.len()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+}
+
+#[test]
+fn test_config_clone_with_next_round() {
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let mut config = AggregatorConfig::<Fr>::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1], vec![2]],
+        false,
+    );
+    config.absorb_instance = vec![(1, 0, 0, 0, 3)];
+
+    let config = config.clone_with_next_round(Fr::from(1u64), 7);
+    assert_eq!(
+        config.target_aggregator_constant_hash_instance_offset,
+        vec![(2, 0, Fr::from(1u64))]
+    );
+    assert_eq!(config.absorb_instance, vec![(1, 0, 2, 0, 3)]);
+    assert_eq!(
+        config.target_proof_max_instance,
+        vec![vec![1], vec![2], vec![7]]
+    );
+
+    // the previous aggregator is replaced instead of appended
+    let config = config.clone_with_next_round(Fr::from(2u64), 7);
+    assert_eq!(
+        config.target_aggregator_constant_hash_instance_offset,
+        vec![(2, 0, Fr::from(2u64))]
+    );
+    assert_eq!(config.absorb_instance, vec![(1, 0, 2, 0, 3)]);
+    assert_eq!(
+        config.target_proof_max_instance,
+        vec![vec![1], vec![2], vec![7]]
+    );
+
+    // the rounds of test_single_rec, one target proof and a previous aggregator of 7 instances
+    let mut config = AggregatorConfig::<Fr>::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1]],
+        false,
+    );
+    config.absorb_instance = vec![(0, 0, 0, 0, 1)];
+    for prev_hash in [Fr::from(3u64), Fr::from(4u64)] {
+        config = config.clone_with_next_round(prev_hash, 7);
+        assert_eq!(
+            config.target_aggregator_constant_hash_instance_offset,
+            vec![(1, 0, prev_hash)]
+        );
+        assert_eq!(config.absorb_instance, vec![(0, 0, 1, 0, 1)]);
+        assert_eq!(config.target_proof_max_instance, vec![vec![1], vec![7]]);
+    }
+}
+
+#[test]
+fn test_config_derive_absorb_instance() {
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let mut prev_config = AggregatorConfig::<Fr>::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1], vec![2, 3]],
+        false,
+    );
+    prev_config.expose = vec![[1, 0], [0, 2], [1, 4]];
+    let absorbing_columns = [(1, 0), (0, 0), (1, 1)];
+
+    // 1 hash row, 3 instance commitments and 3 exposed commitments of 3 rows
+    let mut config = prev_config.clone_with_next_round(Fr::from(1u64), 19);
+    config.absorb_instance = config.derive_absorb_instance(&prev_config, &absorbing_columns);
+    assert_eq!(
+        config.absorb_instance,
+        vec![(1, 0, 2, 0, 10), (0, 0, 2, 0, 13), (1, 1, 2, 0, 16)]
+    );
+    config.validate(3, &[1, 2, 1]).unwrap();
+
+    // the previous aggregator of the next round also has an instance commitment
+    // of its own previous aggregator, which shifts the exposed rows
+    let mut next_config = config.clone_with_next_round(Fr::from(2u64), 22);
+    next_config.absorb_instance = next_config.derive_absorb_instance(&config, &absorbing_columns);
+    assert_eq!(
+        next_config.absorb_instance,
+        vec![(1, 0, 2, 0, 13), (0, 0, 2, 0, 16), (1, 1, 2, 0, 19)]
+    );
+    for (_, _, prev_agg_idx, col, row) in next_config.absorb_instance.iter() {
+        assert!(row + 3 <= next_config.target_proof_max_instance[*prev_agg_idx][*col]);
+    }
+    next_config.validate(3, &[1, 2, 1]).unwrap();
+
+    prev_config.expose = vec![];
+    assert!(config.derive_absorb_instance(&prev_config, &[]).is_empty());
+}
+
+#[test]
+fn test_config_check_params_capacity() {
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+
+    let config = AggregatorConfig::<Fr>::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1], vec![245]],
+        false,
+    );
+    assert_eq!(
+        AggregatorConfig::check_params_capacity::<Bn256>(&config, &params),
+        Ok(())
+    );
+
+    let config = AggregatorConfig::<Fr>::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1], vec![2, 246]],
+        false,
+    );
+    assert_eq!(
+        AggregatorConfig::check_params_capacity::<Bn256>(&config, &params),
+        Err(CapacityError {
+            required_public_inputs_size: 256,
+            available: 255,
+        })
+    );
+}
+
+#[test]
+fn test_config_default_final_aggregator_config() {
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let config = AggregatorConfig::<Fr>::default_final_aggregator_config(
+        TranscriptHash::Poseidon,
+        7,
+        7,
+        vec![vec![1]],
+    );
+    assert!(config.is_final_aggregator);
+    assert!(!config.use_select_chip);
+    assert_eq!(config.prev_aggregator_skip_instance, vec![(1, 7)]);
+    assert_eq!(config.target_proof_max_instance, vec![vec![1], vec![7]]);
+    assert_eq!(config.target_proof_protocol, vec![ProofProtocol::GWC]);
+    assert_eq!(config.proof_protocol(1), ProofProtocol::GWC);
+}
+
+#[test]
+fn test_load_or_build_vkey_content_addressed() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::fs::DirBuilder;
+
+    let path = "./output/vkey-content-addressed";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+    let path = Path::new(path);
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let circuit = SimpleCircuit::<Fr>::default_with_instance().0;
+
+    let vkey = load_or_build_vkey_content_addressed::<Bn256, _>(&params, &circuit, path);
+    let cached = load_or_build_vkey_content_addressed::<Bn256, _>(&params, &circuit, path);
+    assert_eq!(
+        format!("{:?}", vkey.pinned()),
+        format!("{:?}", cached.pinned())
+    );
+    assert!(std::fs::read_dir(path).unwrap().any(|e| e
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .ends_with(".vkey")));
+}
+
+#[test]
+fn test_load_or_create_proof_and_verify() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::fs::DirBuilder;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+    let file = Path::new(path).join("proof-and-verify.data");
+    let _ = std::fs::remove_file(&file);
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    // an instance that does not match the witness gives a proof that fails verification
+    let wrong_instances = vec![vec![instances[0][0] + Fr::one()]];
+    let res = load_or_create_proof_and_verify::<Bn256, _>(
+        &params,
+        vkey.clone(),
+        circuit.clone(),
+        &wrong_instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        Some(&file),
+        TranscriptHash::Poseidon,
+        true,
+        ProofProtocol::GWC,
+        true,
+    );
+    assert_eq!(
+        res,
+        Err(ProofError::VerifyFailed(ProofVerifyError::PairingFailed))
+    );
+    assert!(!file.exists());
+
+    let proof = load_or_create_proof_and_verify::<Bn256, _>(
+        &params,
+        vkey.clone(),
+        circuit.clone(),
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        Some(&file),
+        TranscriptHash::Poseidon,
+        true,
+        ProofProtocol::GWC,
+        true,
+    )
+    .unwrap();
+    assert_eq!(load_proof(&file), proof);
+
+    // the cached proof is returned without creating a new one
+    let cached = load_or_create_proof_and_verify::<Bn256, _>(
+        &params,
+        vkey,
+        circuit,
+        &wrong_instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        Some(&file),
+        TranscriptHash::Poseidon,
+        true,
+        ProofProtocol::GWC,
+        true,
+    )
+    .unwrap();
+    assert_eq!(cached, proof);
+}
+
+#[test]
+fn test_load_or_create_proof_with_rng() {
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let instances = instances.iter().map(|x| &x[..]).collect::<Vec<_>>();
+
+    let create = |seed| {
+        load_or_create_proof_with_rng::<Bn256, _, _>(
+            &params,
+            vkey.clone(),
+            circuit.clone(),
+            &instances,
+            None,
+            TranscriptHash::Poseidon,
+            false,
+            ProofProtocol::GWC,
+            StdRng::seed_from_u64(seed),
+        )
+    };
+
+    assert_eq!(create(0), create(0));
+    assert_ne!(create(0), create(1));
+}
+
+#[test]
+fn test_store_and_load_proof_typed() {
+    use std::fs::DirBuilder;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+    let path = Path::new(path);
+
+    let proof = (0..PROOF_ELEMENT_SIZE * 3)
+        .map(|i| i as u8)
+        .collect::<Vec<_>>();
+
+    for hash in [
+        TranscriptHash::Blake2b,
+        TranscriptHash::Poseidon,
+        TranscriptHash::Sha,
+        TranscriptHash::Keccak,
+        TranscriptHash::Blake2s,
+        TranscriptHash::Sha512,
+        TranscriptHash::Sha3_256,
+    ] {
+        let file = path.join("proof-typed.with-header.data");
+        store_proof(&file, &proof, Some(hash));
+        assert_eq!(load_proof(&file).len(), proof.len() + 1);
+        assert_eq!(
+            load_proof_typed(&file),
+            Proof {
+                data: proof.clone(),
+                hash: Some(hash),
+            }
+        );
+    }
+
+    let file = path.join("proof-typed.no-header.data");
+    store_proof(&file, &proof, None);
+    assert_eq!(load_proof(&file), proof);
+    assert_eq!(
+        load_proof_typed(&file),
+        Proof {
+            data: proof.clone(),
+            hash: None,
+        }
+    );
+}
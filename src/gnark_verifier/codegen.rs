@@ -12,19 +12,22 @@ use halo2_proofs::pairing::group::Curve;
 use halo2_proofs::pairing::group::Group;
 use halo2_proofs::plonk::VerifyingKey;
 use halo2_proofs::poly::commitment::ParamsVerifier;
-use halo2_proofs::transcript::Challenge255;
 use halo2_proofs::transcript::EncodedChallenge;
 use halo2_proofs::transcript::Transcript;
 use halo2_proofs::transcript::TranscriptRead;
 use halo2ecc_s::utils::field_to_bn;
 use sha2::Digest;
 use std::collections::HashMap;
-use std::io::Read;
+use std::marker::PhantomData;
 
-struct GnarkEvalContext<R: Read, E: MultiMillerLoop, D: Digest> {
+struct GnarkEvalContext<
+    E: MultiMillerLoop,
+    EC: EncodedChallenge<E::G1Affine>,
+    T: TranscriptRead<E::G1Affine, EC>,
+> {
     c: EvalContext<E::G1Affine>,
     instance_commitments: Vec<E::G1Affine>,
-    t: ShaRead<R, E::G1Affine, Challenge255<E::G1Affine>, D>,
+    t: T,
 
     commiment_idx: usize,
     eval_idx: usize,
@@ -40,14 +43,23 @@ struct GnarkEvalContext<R: Read, E: MultiMillerLoop, D: Digest> {
     max_idx: usize,
 
     statements: Vec<String>,
+    // index of the op that emits each statement
+    statement_ops: Vec<usize>,
+    _mark: PhantomData<EC>,
 }
 
-impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
-    pub fn new(
-        c: EvalContext<E::G1Affine>,
-        instance_commitments: Vec<E::G1Affine>,
-        t: ShaRead<R, E::G1Affine, Challenge255<E::G1Affine>, D>,
-    ) -> Self {
+// A generated statement with the native value of the op that emits it.
+#[derive(Clone, Debug)]
+pub struct ValidationEntry<E: MultiMillerLoop> {
+    pub statement: String,
+    pub expected_scalar: Option<E::Scalar>,
+    pub expected_point: Option<E::G1Affine>,
+}
+
+impl<E: MultiMillerLoop, EC: EncodedChallenge<E::G1Affine>, T: TranscriptRead<E::G1Affine, EC>>
+    GnarkEvalContext<E, EC, T>
+{
+    pub fn new(c: EvalContext<E::G1Affine>, instance_commitments: Vec<E::G1Affine>, t: T) -> Self {
         let ops_len = c.ops.len();
         Self {
             c,
@@ -60,9 +72,11 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
             commiment_idx: 0,
             eval_idx: 0,
             statements: vec![],
+            statement_ops: vec![],
             deps: vec![0; ops_len],
             lifetime: vec![0; ops_len],
             max_idx: 0,
+            _mark: PhantomData,
         }
     }
 
@@ -128,6 +142,7 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
                     None,
                     Some(self.eval_scalar_pos(a) - self.eval_scalar_pos(b)),
                 ),
+                EvalOps::ScalarNeg(a) => (None, Some(-self.eval_scalar_pos(a))),
                 EvalOps::ScalarMul(a, b, _) => (
                     None,
                     Some(self.eval_scalar_pos(a) * self.eval_scalar_pos(b)),
@@ -136,6 +151,7 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
                     let t = self.eval_scalar_pos(a) * self.eval_scalar_pos(b).invert().unwrap();
                     (None, Some(t))
                 }
+                EvalOps::ScalarInv(a) => (None, Some(self.eval_scalar_pos(a).invert().unwrap())),
                 EvalOps::ScalarPow(a, n) => {
                     (None, Some(self.eval_scalar_pos(a).pow_vartime([*n as u64])))
                 }
@@ -215,10 +231,16 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
                     self.tag_lifetime(a, i);
                     self.tag_lifetime(b, i);
                 }
+                EvalOps::ScalarNeg(a) => {
+                    self.tag_lifetime(a, i);
+                }
                 EvalOps::ScalarDiv(a, b) => {
                     self.tag_lifetime(a, i);
                     self.tag_lifetime(b, i);
                 }
+                EvalOps::ScalarInv(a) => {
+                    self.tag_lifetime(a, i);
+                }
                 EvalOps::ScalarPow(a, _) => {
                     self.tag_lifetime(a, i);
                 }
@@ -307,6 +329,15 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
                     ));
                     op_res_map.insert(i, format!("t[{}]", t_idx));
                 }
+                EvalOps::ScalarNeg(a) => {
+                    let a_expr =
+                        self.render_scalar_pos(a, &op_res_map, &op_t_idx_map, &mut available_idx);
+                    let t_idx = alloc_idx(&mut available_idx, &mut self.max_idx);
+                    op_t_idx_map.insert(i, t_idx);
+                    self.statements
+                        .push(format!("t[{}]= halo2Api.api.Neg({})", t_idx, a_expr));
+                    op_res_map.insert(i, format!("t[{}]", t_idx));
+                }
                 EvalOps::ScalarDiv(a, b) => {
                     const OP: &str = "Div";
                     let a_expr =
@@ -321,6 +352,15 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
                     ));
                     op_res_map.insert(i, format!("t[{}]", t_idx));
                 }
+                EvalOps::ScalarInv(a) => {
+                    let a_expr =
+                        self.render_scalar_pos(a, &op_res_map, &op_t_idx_map, &mut available_idx);
+                    let t_idx = alloc_idx(&mut available_idx, &mut self.max_idx);
+                    op_t_idx_map.insert(i, t_idx);
+                    self.statements
+                        .push(format!("t[{}]= halo2Api.api.Inverse({})", t_idx, a_expr));
+                    op_res_map.insert(i, format!("t[{}]", t_idx));
+                }
                 EvalOps::ScalarPow(a, n) => {
                     let a_expr =
                         self.render_scalar_pos(a, &op_res_map, &op_t_idx_map, &mut available_idx);
@@ -353,31 +393,46 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> GnarkEvalContext<R, E, D> {
                 }
                 _ => (),
             }
+            self.statement_ops.resize(self.statements.len(), i);
         }
     }
+
+    // Pair each statement of code_gen with the value of its op computed by value_gen.
+    pub fn validate_statements(&self) -> Vec<ValidationEntry<E>> {
+        self.statements
+            .iter()
+            .zip(self.statement_ops.iter())
+            .map(|(statement, op)| ValidationEntry {
+                statement: statement.clone(),
+                expected_scalar: self.values[*op].1,
+                expected_point: self.values[*op].0,
+            })
+            .collect()
+    }
 }
 
-pub fn gnark_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
+pub fn gnark_codegen_with_proof<
+    E: MultiMillerLoop,
+    EC: EncodedChallenge<E::G1Affine>,
+    T: TranscriptRead<E::G1Affine, EC>,
+>(
     params: &ParamsVerifier<E>,
     vkey: &VerifyingKey<E::G1Affine>,
-    instances: &Vec<E::Scalar>,
-    proofs: Vec<u8>,
+    instances: &Vec<Vec<E::Scalar>>,
+    t: T,
     check: bool,
 ) -> String {
     let (w_x, w_g, _) = verify_aggregation_proofs(params, &[vkey], &vec![], true, &vec![]);
 
+    // one commitment per instance column, rendered as instanceCommitments[i]
     let instance_commitments =
-        instance_to_instance_commitment(params, &[vkey], vec![&vec![instances.clone()]])[0].clone();
+        instance_to_instance_commitment(params, &[vkey], vec![instances])[0].clone();
 
     let targets = vec![w_x.0, w_g.0];
 
     let c = EvalContext::translate(&targets[..]);
 
-    let mut ctx = GnarkEvalContext::<_, E, D>::new(
-        c,
-        instance_commitments,
-        ShaRead::<_, _, _, D>::init(&proofs[..]),
-    );
+    let mut ctx = GnarkEvalContext::<E, EC, T>::new(c, instance_commitments, t);
 
     ctx.value_gen();
     ctx.code_gen();
@@ -395,27 +450,45 @@ pub fn gnark_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
         );
 
         assert!(success);
+
+        for entry in ctx.validate_statements() {
+            assert!(
+                entry.expected_scalar.is_some() || entry.expected_point.is_some(),
+                "no native value for statement {}",
+                entry.statement
+            );
+        }
     }
 
     for challenge in ctx.challenges {
         println!("challenge: {:?}", challenge)
     }
 
+    let mut statements_pre = const_setup_statements(&ctx.c, ctx.max_idx);
+    statements_pre.append(&mut ctx.statements);
+    statements_pre
+        .into_iter()
+        .reduce(|a, b| format!("{}\n{}", a, b))
+        .unwrap()
+}
+
+// Allocate the temp buffer and assign the constants of c, shared by all proofs of a vkey.
+fn const_setup_statements<C: CurveAffine>(c: &EvalContext<C>, max_idx: usize) -> Vec<String> {
     let mut statements_pre = vec![
         format!("x := big.NewInt(0)"),
         format!("y := big.NewInt(0)"),
-        format!("t := make([]frontend.Variable, {})", ctx.max_idx),
+        format!("t := make([]frontend.Variable, {})", max_idx),
         format!(
             "const_scalars := make([]frontend.Variable, {})",
-            ctx.c.const_scalars.len()
+            c.const_scalars.len()
         ),
         format!(
             "const_points := make([]*sw_emulated.AffinePoint[emparams.BN254Fp], {})",
-            ctx.c.const_points.len()
+            c.const_points.len()
         ),
     ];
 
-    for (i, cs) in ctx.c.const_scalars.iter().enumerate() {
+    for (i, cs) in c.const_scalars.iter().enumerate() {
         statements_pre.push(format!(
             "const_scalars[{}], _ = new(big.Int).SetString(\"{}\",10)",
             i,
@@ -423,7 +496,7 @@ pub fn gnark_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
         ));
     }
 
-    for (i, cp) in ctx.c.const_points.iter().enumerate() {
+    for (i, cp) in c.const_points.iter().enumerate() {
         statements_pre.push(format!(
             "const_points[{}] = new(sw_emulated.AffinePoint[emparams.BN254Fp])",
             i
@@ -446,9 +519,195 @@ pub fn gnark_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
         ));
     }
 
-    statements_pre.append(&mut ctx.statements);
     statements_pre
+}
+
+// Generate a go function verifyBatch for proofs of the same vkey. The constants are set up once,
+// then the verify statements run for each proof and the (p0, p1) pairs are returned. The pairs
+// are not randomized, so the caller pairing-checks each one (see gnark/batch.go).
+pub fn gnark_codegen_batch_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    all_instances: &[Vec<Vec<E::Scalar>>],
+    all_proofs: Vec<Vec<u8>>,
+    check: bool,
+) -> String {
+    assert_eq!(all_instances.len(), all_proofs.len());
+    assert!(!all_proofs.is_empty());
+
+    let (w_x, w_g, _) = verify_aggregation_proofs(params, &[vkey], &vec![], true, &vec![]);
+    let c = EvalContext::translate(&[w_x.0, w_g.0]);
+
+    let mut contexts = all_instances
+        .iter()
+        .zip(all_proofs.iter())
+        .map(|(instances, proof)| {
+            let instance_commitments =
+                instance_to_instance_commitment(params, &[vkey], vec![instances])[0].clone();
+            GnarkEvalContext::<E, _, _>::new(
+                c.clone(),
+                instance_commitments,
+                ShaRead::<_, _, _, D>::init(&proof[..]),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    if check {
+        let s_g2_prepared = E::G2Prepared::from(params.s_g2);
+        let n_g2_prepared = E::G2Prepared::from(-params.g2);
+        for ctx in contexts.iter_mut() {
+            ctx.value_gen();
+            let success = bool::from(
+                E::multi_miller_loop(&[
+                    (&ctx.finals[0], &s_g2_prepared),
+                    (&ctx.finals[1], &n_g2_prepared),
+                ])
+                .final_exponentiation()
+                .is_identity(),
+            );
+            assert!(success);
+        }
+    }
+
+    // The statements only depend on the context, generate them once.
+    let ctx = &mut contexts[0];
+    ctx.code_gen();
+
+    let mut lines = vec![
+        format!("func (halo2Api *Halo2VerifierAPI) verifyBatch("),
+        format!("\tallInstanceCommitments [][]*sw_emulated.AffinePoint[emparams.BN254Fp],"),
+        format!("\tallCommitments [][]*sw_emulated.AffinePoint[emparams.BN254Fp],"),
+        format!("\tallEvals [][]frontend.Variable,"),
+        format!("\tallChallenges [][]frontend.Variable,"),
+        format!(") ([]*sw_emulated.AffinePoint[emparams.BN254Fp], []*sw_emulated.AffinePoint[emparams.BN254Fp]) {{"),
+    ];
+    lines.append(&mut const_setup_statements(&ctx.c, ctx.max_idx));
+    lines.push(format!(
+        "batchP0 := make([]*sw_emulated.AffinePoint[emparams.BN254Fp], 0, len(allCommitments))"
+    ));
+    lines.push(format!(
+        "batchP1 := make([]*sw_emulated.AffinePoint[emparams.BN254Fp], 0, len(allCommitments))"
+    ));
+    lines.push(format!("for i := range allCommitments {{"));
+    lines.push(format!("instanceCommitments := allInstanceCommitments[i]"));
+    lines.push(format!("commitments := allCommitments[i]"));
+    lines.push(format!("evals := allEvals[i]"));
+    lines.push(format!("challenges := allChallenges[i]"));
+    lines.append(&mut ctx.statements);
+    lines.push(format!("batchP0 = append(batchP0, p0)"));
+    lines.push(format!("batchP1 = append(batchP1, p1)"));
+    lines.push(format!("}}"));
+    lines.push(format!("return batchP0, batchP1"));
+    lines.push(format!("}}"));
+
+    lines
         .into_iter()
         .reduce(|a, b| format!("{}\n{}", a, b))
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::GnarkEvalContext;
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::halo2::verify_aggregation_proofs;
+    use crate::circuits::samples::simple::SimpleCircuit;
+    use crate::circuits::utils::instance_to_instance_commitment;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::circuits::utils::ProofProtocol;
+    use crate::circuits::utils::TranscriptHash;
+    use crate::transcript::sha256::ShaRead;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+
+    #[test]
+    fn test_gnark_codegen_batch_with_proof() {
+        use super::gnark_codegen_batch_with_proof;
+
+        let params = load_or_build_unsafe_params::<Bn256>(8, None);
+        let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+        let vkey = load_or_build_vkey::<Bn256, _>(
+            &params,
+            &SimpleCircuit::<Fr>::default_with_instance().0,
+            None,
+        );
+
+        let mut all_instances = vec![];
+        let mut all_proofs = vec![];
+        for _ in 0..2 {
+            let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+            all_proofs.push(load_or_create_proof::<Bn256, _>(
+                &params,
+                vkey.clone(),
+                circuit,
+                &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                None,
+                TranscriptHash::Sha,
+                false,
+                ProofProtocol::SHPLONK,
+            ));
+            all_instances.push(instances);
+        }
+
+        let code = gnark_codegen_batch_with_proof::<Bn256, sha2::Sha256>(
+            &params_verifier,
+            &vkey,
+            &all_instances,
+            all_proofs,
+            true,
+        );
+        assert!(code.starts_with("func (halo2Api *Halo2VerifierAPI) verifyBatch("));
+        assert_eq!(code.matches("for i := range allCommitments {").count(), 1);
+        assert_eq!(code.matches("const_scalars := make(").count(), 1);
+        assert!(code.contains("batchP0 = append(batchP0, p0)"));
+        assert!(code.ends_with("return batchP0, batchP1\n}"));
+    }
+
+    #[test]
+    fn test_gnark_validate_statements() {
+        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        let params = load_or_build_unsafe_params::<Bn256>(8, None);
+        let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+        let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+        let proof = load_or_create_proof::<Bn256, _>(
+            &params,
+            vkey.clone(),
+            circuit,
+            &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            TranscriptHash::Sha,
+            false,
+            ProofProtocol::SHPLONK,
+        );
+
+        let (w_x, w_g, _) =
+            verify_aggregation_proofs(&params_verifier, &[&vkey], &vec![], true, &vec![]);
+        let c = EvalContext::translate(&[w_x.0, w_g.0]);
+        let instance_commitments =
+            instance_to_instance_commitment(&params_verifier, &[&vkey], vec![&instances])[0]
+                .clone();
+
+        let mut ctx = GnarkEvalContext::<Bn256, _, _>::new(
+            c,
+            instance_commitments,
+            ShaRead::<_, _, _, sha2::Sha256>::init(&proof[..]),
+        );
+        ctx.value_gen();
+        ctx.code_gen();
+
+        let entries = ctx.validate_statements();
+        assert_eq!(entries.len(), ctx.statements.len());
+        for (entry, statement) in entries.iter().zip(ctx.statements.iter()) {
+            assert_eq!(&entry.statement, statement);
+            if statement.starts_with("t[") {
+                assert!(entry.expected_scalar.is_some());
+                assert!(entry.expected_point.is_none());
+            } else {
+                assert!(entry.expected_point.is_some());
+            }
+        }
+    }
+}
@@ -1,3 +1,5 @@
+use crate::circuits::utils::TranscriptHash;
+use crate::transcript::sha256::ShaRead;
 use halo2_proofs::arithmetic::BaseExt;
 use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::MultiMillerLoop;
@@ -7,10 +9,11 @@ use halo2ecc_s::utils::field_to_bn;
 use num_bigint::BigUint;
 use serde::Deserialize;
 use serde::Serialize;
-use sha2::Sha256;
 
 mod codegen;
 
+pub use codegen::gnark_codegen_batch_with_proof;
+
 #[derive(Serialize, Deserialize)]
 struct AggregatorConfig {
     verify_circuit_g_lagrange: Vec<[String; 2]>,
@@ -21,26 +24,72 @@ struct AggregatorConfig {
     nb_permutation_groups: u32,
     nb_evals: u32,
     degree: u32,
+    transcript_hash: String,
 }
 
 #[derive(Serialize, Deserialize)]
 struct AggregatorProofData {
     instance: Vec<Vec<String>>,
     transcript: Vec<String>,
+    transcript_hash: String,
 }
 
-pub fn gnark_export_proof<F: BaseExt>(gnark_root: &str, instances: &Vec<F>, proofs: Vec<u8>) {
+const GO_IMPORTS: &str = r#"
+package main
+
+import (
+	"math/big"
+
+	"github.com/consensys/gnark/frontend"
+	"github.com/consensys/gnark/std/algebra/emulated/sw_emulated"
+	"github.com/consensys/gnark/std/math/emulated"
+	"github.com/consensys/gnark/std/math/emulated/emparams"
+)
+"#;
+
+fn transcript_hash_name(hash: TranscriptHash) -> &'static str {
+    match hash {
+        TranscriptHash::Sha => "sha256",
+        TranscriptHash::Keccak => "keccak",
+        _ => panic!(
+            "gnark verifier does not support this transcript hash: {:?}",
+            hash
+        ),
+    }
+}
+
+fn proof_data<F: BaseExt>(
+    hash: TranscriptHash,
+    instances: &Vec<Vec<F>>,
+    proofs: &[u8],
+) -> AggregatorProofData {
+    let transcript_hash = transcript_hash_name(hash).to_owned();
+
     let instance_str = instances
         .iter()
-        .map(|x| field_to_bn(x).to_str_radix(10))
+        .map(|col| {
+            col.iter()
+                .map(|x| field_to_bn(x).to_str_radix(10))
+                .collect::<Vec<_>>()
+        })
         .collect::<Vec<_>>();
 
     let proof_str = proofs.iter().map(|x| format!("{}", x)).collect::<Vec<_>>();
 
-    let data = AggregatorProofData {
-        instance: vec![instance_str],
+    AggregatorProofData {
+        instance: instance_str,
         transcript: proof_str,
-    };
+        transcript_hash,
+    }
+}
+
+pub fn gnark_export_proof<F: BaseExt>(
+    gnark_root: &str,
+    hash: TranscriptHash,
+    instances: &Vec<Vec<F>>,
+    proofs: Vec<u8>,
+) {
+    let data = proof_data(hash, instances, &proofs);
 
     std::fs::write(
         format!("{}/halo2_verifier_proof.json", gnark_root),
@@ -51,11 +100,15 @@ pub fn gnark_export_proof<F: BaseExt>(gnark_root: &str, instances: &Vec<F>, proo
 
 pub fn gnark_render<E: MultiMillerLoop>(
     gnark_root: &str,
+    hash: TranscriptHash,
     verify_circuit_params: &ParamsVerifier<E>,
     vkey: &VerifyingKey<E::G1Affine>,
-    instances: &Vec<E::Scalar>,
+    instances: &Vec<Vec<E::Scalar>>,
     proofs: Vec<u8>,
 ) {
+    // Reject before rendering anything.
+    transcript_hash_name(hash);
+
     let verify_circuit_g_lagrange = verify_circuit_params
         .g_lagrange
         .iter()
@@ -137,6 +190,7 @@ pub fn gnark_render<E: MultiMillerLoop>(
         nb_permutation_groups,
         nb_evals,
         degree,
+        transcript_hash,
     };
 
     std::fs::write(
@@ -145,18 +199,8 @@ pub fn gnark_render<E: MultiMillerLoop>(
     )
     .unwrap();
 
-    let code_pre = r#"
-package main
-
-import (
-	"math/big"
-
-	"github.com/consensys/gnark/frontend"
-	"github.com/consensys/gnark/std/algebra/emulated/sw_emulated"
-	"github.com/consensys/gnark/std/math/emulated"
-	"github.com/consensys/gnark/std/math/emulated/emparams"
-)
-
+    let code_pre = GO_IMPORTS.to_owned()
+        + r#"
 func (halo2Api *Halo2VerifierAPI) verify(
 	instanceCommitments []*sw_emulated.AffinePoint[emparams.BN254Fp],
 	commitments []*sw_emulated.AffinePoint[emparams.BN254Fp],
@@ -165,13 +209,23 @@ func (halo2Api *Halo2VerifierAPI) verify(
 ) (*sw_emulated.AffinePoint[emparams.BN254Fp], *sw_emulated.AffinePoint[emparams.BN254Fp]) {
     "#;
 
-    let code = codegen::gnark_codegen_with_proof::<_, Sha256>(
-        verify_circuit_params,
-        vkey,
-        instances,
-        proofs.clone(),
-        true,
-    );
+    let code = match hash {
+        TranscriptHash::Sha => codegen::gnark_codegen_with_proof(
+            verify_circuit_params,
+            vkey,
+            instances,
+            ShaRead::<_, _, _, sha2::Sha256>::init(&proofs[..]),
+            true,
+        ),
+        TranscriptHash::Keccak => codegen::gnark_codegen_with_proof(
+            verify_circuit_params,
+            vkey,
+            instances,
+            ShaRead::<_, _, _, sha3::Keccak256>::init(&proofs[..]),
+            true,
+        ),
+        _ => panic!("gnark verifier does not support this transcript hash"),
+    };
 
     let code_post = r#"
         return p0, p1
@@ -184,28 +238,95 @@ func (halo2Api *Halo2VerifierAPI) verify(
     )
     .unwrap();
 
-    gnark_export_proof(gnark_root, instances, proofs)
+    gnark_export_proof(gnark_root, hash, instances, proofs)
+}
+
+// Same as gnark_render for the first proof, then renders verify_batch.go with verifyBatch and
+// exports all proofs to halo2_verifier_batch_proof.json, build with `go build -tags batch` and
+// run `./gnark-halo2-verify -batch` to verify them in one gnark circuit.
+pub fn gnark_render_batch<E: MultiMillerLoop>(
+    gnark_root: &str,
+    hash: TranscriptHash,
+    verify_circuit_params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    all_instances: &[Vec<Vec<E::Scalar>>],
+    all_proofs: Vec<Vec<u8>>,
+) {
+    transcript_hash_name(hash);
+    assert_eq!(all_instances.len(), all_proofs.len());
+    assert!(!all_proofs.is_empty());
+
+    gnark_render(
+        gnark_root,
+        hash,
+        verify_circuit_params,
+        vkey,
+        &all_instances[0],
+        all_proofs[0].clone(),
+    );
+
+    let data = all_instances
+        .iter()
+        .zip(all_proofs.iter())
+        .map(|(instances, proofs)| proof_data(hash, instances, proofs))
+        .collect::<Vec<_>>();
+
+    let code = match hash {
+        TranscriptHash::Sha => gnark_codegen_batch_with_proof::<_, sha2::Sha256>(
+            verify_circuit_params,
+            vkey,
+            all_instances,
+            all_proofs,
+            true,
+        ),
+        TranscriptHash::Keccak => gnark_codegen_batch_with_proof::<_, sha3::Keccak256>(
+            verify_circuit_params,
+            vkey,
+            all_instances,
+            all_proofs,
+            true,
+        ),
+        _ => unreachable!(),
+    };
+
+    std::fs::write(
+        format!("{}/verify_batch.go", gnark_root),
+        format!("//go:build batch\n\n{}\n{}\n", GO_IMPORTS, code),
+    )
+    .unwrap();
+
+    std::fs::write(
+        format!("{}/halo2_verifier_batch_proof.json", gnark_root),
+        serde_json::to_string_pretty(&data).unwrap(),
+    )
+    .unwrap();
 }
 
 #[cfg(test)]
 mod tests {
+    use super::gnark_export_proof;
     use super::gnark_render;
+    use super::gnark_render_batch;
+    use super::AggregatorProofData;
     use crate::circuits::samples::simple::SimpleCircuit;
     use crate::circuits::utils::load_or_build_unsafe_params;
     use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
     use crate::circuits::utils::load_proof;
     use crate::circuits::utils::run_circuit_unsafe_full_pass_no_rec;
+    use crate::circuits::utils::ProofProtocol;
     use crate::circuits::utils::TranscriptHash;
+    use ark_std::rand::rngs::OsRng;
+    use halo2_proofs::arithmetic::Field;
     use halo2_proofs::pairing::bn256::Bn256;
     use halo2_proofs::pairing::bn256::Fr;
     use halo2_proofs::plonk::Circuit;
     use halo2_proofs::poly::commitment::ParamsVerifier;
+    use halo2ecc_s::utils::field_to_bn;
     use std::fs::DirBuilder;
     use std::path::Path;
 
-    #[test]
-    fn test_gnark_render() {
-        let aggregator_circuit_hasher = TranscriptHash::Sha;
+    fn test_gnark_render(aggregator_circuit_hasher: TranscriptHash) {
         let path = "./output";
         DirBuilder::new().recursive(true).create(path).unwrap();
 
@@ -261,6 +382,170 @@ mod tests {
         );
 
         let proof = load_proof(&path.join(format!("{}.{}.transcript.data", "verify-circuit", 0)));
-        gnark_render("gnark", &verifier_params_verifier, &vkey, &instances, proof);
+        gnark_render(
+            "gnark",
+            aggregator_circuit_hasher,
+            &verifier_params_verifier,
+            &vkey,
+            &vec![instances],
+            proof,
+        );
+    }
+
+    #[test]
+    fn test_gnark_render_sha256() {
+        test_gnark_render(TranscriptHash::Sha)
+    }
+
+    #[test]
+    fn test_gnark_render_keccak() {
+        test_gnark_render(TranscriptHash::Keccak)
+    }
+
+    #[test]
+    fn test_gnark_render_batch() {
+        let path = "./output/gnark-render-batch";
+        DirBuilder::new().recursive(true).create(path).unwrap();
+
+        let params = load_or_build_unsafe_params::<Bn256>(8, None);
+        let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+        let vkey = load_or_build_vkey::<Bn256, _>(
+            &params,
+            &SimpleCircuit::<Fr>::default_with_instance().0,
+            None,
+        );
+
+        let mut all_instances = vec![];
+        let mut all_proofs = vec![];
+        for _ in 0..2 {
+            let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+            all_proofs.push(load_or_create_proof::<Bn256, _>(
+                &params,
+                vkey.clone(),
+                circuit,
+                &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+                None,
+                TranscriptHash::Sha,
+                false,
+                ProofProtocol::SHPLONK,
+            ));
+            all_instances.push(instances);
+        }
+
+        gnark_render_batch(
+            path,
+            TranscriptHash::Sha,
+            &params_verifier,
+            &vkey,
+            &all_instances,
+            all_proofs.clone(),
+        );
+
+        let code = std::fs::read_to_string(format!("{}/verify_batch.go", path)).unwrap();
+        assert!(code.starts_with("//go:build batch\n\npackage main"));
+        assert!(code.contains("func (halo2Api *Halo2VerifierAPI) verifyBatch("));
+        assert!(Path::new(path).join("verify.go").exists());
+        assert!(Path::new(path).join("halo2_verifier_config.json").exists());
+
+        let data: Vec<AggregatorProofData> = serde_json::from_str(
+            &std::fs::read_to_string(format!("{}/halo2_verifier_batch_proof.json", path)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(data.len(), all_proofs.len());
+        for ((data, instances), proofs) in
+            data.iter().zip(all_instances.iter()).zip(all_proofs.iter())
+        {
+            assert_eq!(data.instance.len(), instances.len());
+            assert_eq!(
+                data.instance[0][0],
+                field_to_bn(&instances[0][0]).to_str_radix(10)
+            );
+            assert_eq!(data.transcript.len(), proofs.len());
+            assert_eq!(data.transcript_hash, "sha256");
+        }
+    }
+
+    #[test]
+    fn test_gnark_export_proof_roundtrip() {
+        let path = "./output/gnark-export";
+        DirBuilder::new().recursive(true).create(path).unwrap();
+
+        let instances = vec![
+            (0..3).map(|_| Fr::random(OsRng)).collect::<Vec<_>>(),
+            vec![Fr::zero(), Fr::one(), -Fr::one()],
+        ];
+        let proofs = (0..=255u8).collect::<Vec<_>>();
+
+        gnark_export_proof(path, TranscriptHash::Keccak, &instances, proofs.clone());
+
+        let data: AggregatorProofData = serde_json::from_str(
+            &std::fs::read_to_string(format!("{}/halo2_verifier_proof.json", path)).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(data.instance.len(), instances.len());
+        for (col, expected) in data.instance.iter().zip(instances.iter()) {
+            assert_eq!(col.len(), expected.len());
+            for (x, expected) in col.iter().zip(expected.iter()) {
+                assert_eq!(x, &field_to_bn(expected).to_str_radix(10));
+            }
+        }
+        assert_eq!(data.transcript.len(), proofs.len());
+        for (x, expected) in data.transcript.iter().zip(proofs.iter()) {
+            assert_eq!(x.parse::<u8>().unwrap(), *expected);
+        }
+        assert_eq!(data.transcript_hash, "keccak");
+    }
+
+    #[test]
+    #[should_panic(expected = "gnark verifier does not support this transcript hash")]
+    fn test_gnark_export_proof_poseidon_unsupported() {
+        let path = "./output/gnark-export-poseidon";
+        DirBuilder::new().recursive(true).create(path).unwrap();
+
+        // transcript.go has no poseidon hasher
+        gnark_export_proof(
+            path,
+            TranscriptHash::Poseidon,
+            &vec![vec![Fr::one()]],
+            vec![],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "gnark verifier does not support this transcript hash")]
+    fn test_gnark_export_proof_blake2b_unsupported() {
+        let path = "./output/gnark-export-blake2b";
+        DirBuilder::new().recursive(true).create(path).unwrap();
+
+        // gnark std has no in-circuit blake2b
+        gnark_export_proof(
+            path,
+            TranscriptHash::Blake2b,
+            &vec![vec![Fr::one()]],
+            vec![],
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "gnark verifier does not support this transcript hash")]
+    fn test_gnark_render_blake2s_unsupported() {
+        let path = "./output/gnark-render-blake2s";
+        DirBuilder::new().recursive(true).create(path).unwrap();
+
+        let (circuit, instances) = SimpleCircuit::<Fr>::default_with_instance();
+        let params = load_or_build_unsafe_params::<Bn256>(8, None);
+        let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+        let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+        // gnark std has no in-circuit blake2s, rejected before any file is written
+        gnark_render(
+            path,
+            TranscriptHash::Blake2s,
+            &params_verifier,
+            &vkey,
+            &instances,
+            vec![],
+        );
     }
 }
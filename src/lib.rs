@@ -58,6 +58,1351 @@ fn test_batch_no_rec() {
     );
 }
 
+// Hashes without an in-circuit transcript only produce target proofs, which must verify natively.
+#[cfg(test)]
+fn test_batch_no_rec_native_only(hash: circuits::utils::TranscriptHash, prefix: &str) {
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::load_or_build_unsafe_params;
+    use circuits::utils::load_proof;
+    use circuits::utils::load_vkey;
+    use circuits::utils::run_circuit_unsafe_full_pass_no_rec;
+    use circuits::utils::ProofProtocol;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+
+    let path = Path::new(path);
+    let (circuit1, instance1) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let (circuit2, instance2) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let instances = vec![instance1, instance2];
+    let res = run_circuit_unsafe_full_pass_no_rec::<Bn256, _>(
+        path,
+        prefix,
+        8,
+        vec![circuit1, circuit2],
+        instances.clone(),
+        instances.clone(),
+        hash,
+        vec![],
+        vec![],
+        vec![vec![1], vec![1]],
+        true,
+    );
+    assert!(res.is_none());
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, Some(&path.join("K8.params")));
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    for (i, instances) in instances.iter().enumerate() {
+        let vkey = load_vkey::<Bn256, SimpleCircuit<Fr>>(
+            &params,
+            &path.join(format!("{}.{}.vkey.data", prefix, i)),
+        );
+        let proof = load_proof(&path.join(format!("{}.{}.transcript.data", prefix, i)));
+        native_verifier::verify_single_proof::<Bn256>(
+            &params_verifier,
+            &vkey,
+            instances,
+            proof,
+            hash,
+            ProofProtocol::default_for(hash),
+        );
+    }
+}
+
+#[test]
+fn test_batch_no_rec_sha512() {
+    test_batch_no_rec_native_only(
+        circuits::utils::TranscriptHash::Sha512,
+        "simple-circuit-sha512",
+    );
+}
+
+#[test]
+fn test_batch_no_rec_blake2s() {
+    test_batch_no_rec_native_only(
+        circuits::utils::TranscriptHash::Blake2s,
+        "simple-circuit-blake2s",
+    );
+}
+
+#[test]
+fn test_batch_no_rec_sha3_256() {
+    test_batch_no_rec_native_only(
+        circuits::utils::TranscriptHash::Sha3_256,
+        "simple-circuit-sha3_256",
+    );
+}
+
+#[test]
+fn test_batch_no_rec_with_naming() {
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::run_circuit_unsafe_full_pass_no_rec_with_naming;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let path = "./output/naming";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+
+    let path = Path::new(path);
+    let naming = |i: usize, artifact: &str| path.join(format!("target-{}-{}.bin", artifact, i));
+    let (circuit1, instance1) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let (circuit2, instance2) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let res = run_circuit_unsafe_full_pass_no_rec_with_naming::<Bn256, _>(
+        path,
+        8,
+        vec![circuit1, circuit2],
+        vec![instance1.clone(), instance2.clone()],
+        vec![instance1, instance2],
+        TranscriptHash::Sha,
+        vec![],
+        vec![],
+        vec![vec![1], vec![1]],
+        true,
+        naming,
+    );
+    assert!(res.is_none());
+
+    for i in 0..2 {
+        for artifact in ["vkey", "transcript", "instance", "shadow-instance"] {
+            assert!(naming(i, artifact).exists());
+        }
+    }
+}
+
+#[test]
+fn test_batch_heterogeneous() {
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::samples::two_instance::TwoInstanceCircuit;
+    use circuits::utils::run_circuit_unsafe_full_pass_heterogeneous;
+    use circuits::utils::AggregatorConfig;
+    use circuits::utils::ErasedCircuit;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+
+    let path = Path::new(path);
+    let (circuit1, instance1) = SimpleCircuit::<Fr>::random_new_with_instance();
+    // Different circuit types, each proof must be checked against its own vkey.
+    let (circuit2, instance2) = TwoInstanceCircuit::<Fr>::new_with_instance(Fr::from(10u64));
+    let circuits: Vec<Box<dyn ErasedCircuit<Bn256>>> = vec![Box::new(circuit1), Box::new(circuit2)];
+    let config = AggregatorConfig::default_aggregator_config(
+        TranscriptHash::Sha,
+        vec![vec![1], vec![1, 1]],
+        false,
+    );
+    let res = run_circuit_unsafe_full_pass_heterogeneous::<Bn256>(
+        path,
+        "simple-circuit-heterogeneous",
+        8,
+        circuits,
+        vec![instance1.clone(), instance2.clone()],
+        vec![instance1, instance2],
+        true,
+        &config,
+    );
+    assert!(res.is_none());
+}
+
+#[test]
+fn test_absorb_instance_non_zero_column() {
+    use circuit_verifier::encode_point;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::samples::two_instance::TwoInstanceCircuit;
+    use circuits::utils::load_or_build_unsafe_params;
+    use circuits::utils::run_circuit_unsafe_full_pass_heterogeneous;
+    use circuits::utils::AggregatorConfig;
+    use circuits::utils::ErasedCircuit;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::group::Curve;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+
+    let path = Path::new(path);
+    let k = 8;
+    let params =
+        load_or_build_unsafe_params::<Bn256>(k, Some(&path.join(format!("K{}.params", k))));
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(4).unwrap();
+
+    // Proof 1 carries the encoded instance commitment of proof 0 at rows 1..4 of its column 1.
+    let (target, target_instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let target_commitment = params_verifier
+        .commit_lagrange(target_instances[0].clone())
+        .to_affine();
+    let a = Fr::from(10u64);
+    let (prev, mut prev_instances) = TwoInstanceCircuit::<Fr>::new_with_instance(a);
+    prev_instances[1].append(&mut encode_point(&target_commitment).to_vec());
+    let unabsorbed = params_verifier
+        .commit_lagrange(prev_instances[1].clone())
+        .to_affine();
+
+    let mut config = AggregatorConfig::default_aggregator_config(
+        TranscriptHash::Poseidon,
+        vec![vec![1], vec![1, 4]],
+        false,
+    );
+    config.absorb_instance = vec![(0, 0, 1, 1, 1)];
+
+    let circuits: Vec<Box<dyn ErasedCircuit<Bn256>>> = vec![Box::new(target), Box::new(prev)];
+    let (_, instances, _, _) = run_circuit_unsafe_full_pass_heterogeneous::<Bn256>(
+        path,
+        "simple-circuit-absorb-column",
+        k,
+        circuits,
+        vec![target_instances.clone(), prev_instances.clone()],
+        vec![target_instances, prev_instances],
+        true,
+        &config,
+    )
+    .unwrap();
+
+    // Instance is [hash, proof 0 column 0, proof 1 column 0, proof 1 column 1],
+    // with the absorbed rows removed from proof 1 column 1.
+    let absorbed = params_verifier.commit_lagrange(vec![a]).to_affine();
+    assert_eq!(instances.len(), 10);
+    assert_eq!(instances[1..4], encode_point(&target_commitment));
+    assert_eq!(instances[4..7], encode_point(&absorbed));
+    assert_eq!(instances[7..10], encode_point(&absorbed));
+    assert_ne!(instances[7..10], encode_point(&unabsorbed));
+}
+
+#[test]
+fn test_batch_no_rec_timed() {
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::run_circuit_unsafe_full_pass_timed;
+    use circuits::utils::AggregatorConfig;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+
+    let path = Path::new(path);
+    let (circuit1, instance1) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let (circuit2, instance2) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let config = AggregatorConfig::new_for_non_rec(
+        TranscriptHash::Sha,
+        vec![],
+        vec![],
+        vec![vec![1], vec![1]],
+    );
+    let (res, timing) = run_circuit_unsafe_full_pass_timed::<Bn256, _>(
+        path,
+        "simple-circuit-timed",
+        8,
+        vec![circuit1, circuit2],
+        vec![instance1.clone(), instance2.clone()],
+        vec![instance1, instance2],
+        true,
+        &config,
+    );
+    assert!(res.is_none());
+    assert_eq!(timing.proof_gen_ms.len(), 2);
+    assert_eq!(timing.agg_circuit_build_ms, 0);
+    assert!(timing.elapsed_ms >= timing.proof_gen_ms.iter().sum::<u64>() + timing.native_verify_ms);
+
+    let json = serde_json::to_string(&timing).unwrap();
+    assert!(json.contains("\"proof_gen_ms\""));
+}
+
+#[test]
+fn test_batch_no_rec_parallel() {
+    use ark_std::rand::rngs::StdRng;
+    use ark_std::rand::SeedableRng;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::default_artifact_naming;
+    use circuits::utils::run_circuit_unsafe_full_pass_parallel;
+    use circuits::utils::run_circuit_unsafe_full_pass_with_rng;
+    use circuits::utils::AggregatorConfig;
+    use circuits::utils::AggregatorProgress;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::cell::RefCell;
+    use std::fs::DirBuilder;
+    use std::path::Path;
+
+    let path = "./output";
+    DirBuilder::new().recursive(true).create(path).unwrap();
+
+    let path = Path::new(path);
+    let (circuit1, instance1) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let (circuit2, instance2) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let config = AggregatorConfig::new_for_non_rec(
+        TranscriptHash::Sha,
+        vec![],
+        vec![],
+        vec![vec![1], vec![1]],
+    );
+
+    run_circuit_unsafe_full_pass_with_rng::<Bn256, _, _>(
+        path,
+        "simple-circuit-sequential",
+        8,
+        vec![circuit1.clone(), circuit2.clone()],
+        vec![instance1.clone(), instance2.clone()],
+        vec![instance1.clone(), instance2.clone()],
+        true,
+        &config,
+        StdRng::seed_from_u64(0),
+    );
+
+    let generated = RefCell::new(vec![]);
+    run_circuit_unsafe_full_pass_parallel::<Bn256, _, _>(
+        path,
+        "simple-circuit-parallel",
+        8,
+        vec![circuit1, circuit2],
+        vec![instance1.clone(), instance2.clone()],
+        vec![instance1, instance2],
+        true,
+        &config,
+        |progress| {
+            if let AggregatorProgress::ProofGenerated { index, .. } = progress {
+                generated.borrow_mut().push(index);
+            }
+        },
+        StdRng::seed_from_u64(0),
+    );
+    assert_eq!(generated.into_inner(), vec![0, 1]);
+
+    let sequential = default_artifact_naming(path, "simple-circuit-sequential");
+    let parallel = default_artifact_naming(path, "simple-circuit-parallel");
+    for i in 0..2 {
+        for artifact in ["transcript", "instance"] {
+            assert_eq!(
+                std::fs::read(sequential(i, artifact)).unwrap(),
+                std::fs::read(parallel(i, artifact)).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn test_verifier_params_dry_run() {
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::halo2::builder::proof_transcript_layout;
+    use crate::api::halo2::builder::VerifierParamsBuilder;
+    use crate::api::halo2::builder::GWC;
+    use crate::api::halo2::builder::SHPLONK;
+    use crate::api::halo2::format_circuit_key;
+    use crate::api::halo2::verify_aggregation_proofs;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    for use_gwc in [false, true] {
+        let estimate = if use_gwc {
+            VerifierParamsBuilder::<_, GWC>::new(format_circuit_key(0), 0, &params_verifier, &vkey)
+                .dry_run()
+        } else {
+            VerifierParamsBuilder::<_, SHPLONK>::new(
+                format_circuit_key(0),
+                0,
+                &params_verifier,
+                &vkey,
+            )
+            .dry_run()
+        };
+
+        let (w_x, w_g, _) =
+            verify_aggregation_proofs(&params_verifier, &[&vkey], &vec![], !use_gwc, &vec![]);
+        let stats = EvalContext::translate(&[w_x.0, w_g.0]).stats();
+
+        assert_eq!(
+            estimate.estimated_transcript_reads,
+            stats.transcript_read_count
+        );
+        assert!(estimate.evaluation_queries > 0);
+        assert!(estimate.msm_groups > 0);
+        if use_gwc {
+            // gwc opens one point per msm group
+            assert_eq!(estimate.msm_groups, proof_transcript_layout(&vkey, true).2);
+        }
+        assert!(estimate.estimated_msm_ops > estimate.evaluation_queries);
+        assert!(estimate.estimated_scalar_ops > 0);
+    }
+}
+
+#[test]
+fn test_permutation_describe() {
+    use crate::api::halo2::builder::VerifierParamsBuilder;
+    use crate::api::halo2::builder::GWC;
+    use crate::api::halo2::format_circuit_key;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let (verifier_params, _) =
+        VerifierParamsBuilder::<_, GWC>::new(format_circuit_key(0), 0, &params_verifier, &vkey)
+            .build();
+    let description = verifier_params.permutation_evaluated.describe(&vkey);
+
+    let n_chunks = vkey
+        .cs
+        .permutation
+        .columns
+        .chunks(vkey.cs.degree() - 2)
+        .len();
+    assert!(description.contains(&format!("{} z polynomials", n_chunks)));
+    assert_eq!(description.lines().count(), n_chunks + 1);
+}
+
+#[test]
+fn test_verifier_params_commitment_at() {
+    use crate::api::halo2::builder::VerifierParamsBuilder;
+    use crate::api::halo2::builder::GWC;
+    use crate::api::halo2::format_circuit_key;
+    use crate::api::halo2::verifier::VerifierError;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use std::rc::Rc;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let (verifier_params, _) =
+        VerifierParamsBuilder::<_, GWC>::new(format_circuit_key(0), 0, &params_verifier, &vkey)
+            .build();
+
+    let n_advice = verifier_params.advice_commitments.len();
+    let n_instance = verifier_params.instance_commitments.len();
+    let n_fixed = verifier_params.fixed_commitments.len();
+    assert!(Rc::ptr_eq(
+        &verifier_params
+            .advice_commitment_at(n_advice - 1)
+            .unwrap()
+            .0,
+        &verifier_params.advice_commitments[n_advice - 1].0
+    ));
+    assert!(verifier_params.instance_commitment_at(0).is_ok());
+    assert!(verifier_params.fixed_commitment_at(n_fixed - 1).is_ok());
+
+    assert_eq!(
+        verifier_params.advice_commitment_at(n_advice).err(),
+        Some(VerifierError::ColumnOutOfBounds {
+            col: n_advice,
+            n_advice
+        })
+    );
+    assert_eq!(
+        verifier_params.instance_commitment_at(n_instance).err(),
+        Some(VerifierError::InstanceColumnOutOfBounds {
+            col: n_instance,
+            n_instance
+        })
+    );
+    assert_eq!(
+        verifier_params.fixed_commitment_at(n_fixed + 3).err(),
+        Some(VerifierError::FixedColumnOutOfBounds {
+            col: n_fixed + 3,
+            n_fixed
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "debug_aggregation")]
+fn test_verify_aggregation_proofs_debug() {
+    use crate::api::halo2::verify_aggregation_proofs_debug;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let (_, _, _, challenges) = verify_aggregation_proofs_debug(
+        &params_verifier,
+        &[&vkey, &vkey],
+        &vec![],
+        false,
+        &vec![1],
+    );
+    assert_eq!(challenges.len(), 2);
+    let names = [
+        "theta",
+        "beta",
+        "gamma",
+        "y",
+        "x",
+        "multiopen_v",
+        "multiopen_u",
+    ];
+    for c in challenges.iter() {
+        assert!(names.iter().all(|name| c.contains_key(*name)));
+    }
+    assert!(!challenges[0].contains_key("multiopen_y"));
+    assert!(challenges[1].contains_key("multiopen_y"));
+}
+
+#[test]
+fn test_verify_single_proof_no_eval_cached() {
+    use crate::api::arith::AstPointRc;
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::halo2::verifier::MultiOpenProof;
+    use crate::api::halo2::verify_single_proof_no_eval;
+    use crate::api::halo2::verify_single_proof_no_eval_cached;
+    use crate::api::halo2::VerifierParamsCache;
+    use crate::api::transcript::AstTranscriptReader;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_unsafe_params_seeded;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use std::rc::Rc;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let mut cache = VerifierParamsCache::new();
+    let (proof, advices, transcript) =
+        verify_single_proof_no_eval_cached(&params_verifier, &vkey, 0, true, &mut cache);
+    assert_eq!(cache.len(), 1);
+
+    let (cached_proof, cached_advices, cached_transcript) =
+        verify_single_proof_no_eval_cached(&params_verifier, &vkey, 0, true, &mut cache);
+    assert_eq!(cache.len(), 1);
+    assert!(Rc::ptr_eq(&transcript, &cached_transcript));
+    assert_eq!(proof.w_x, cached_proof.w_x);
+    assert_eq!(proof.w_g, cached_proof.w_g);
+    assert_eq!(advices.len(), cached_advices.len());
+    for (a, b) in advices.iter().zip(cached_advices.iter()) {
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+    }
+
+    // another index re-indexes the entry, the result is the same as a fresh build
+    let (proof, advices, transcript) =
+        verify_single_proof_no_eval_cached(&params_verifier, &vkey, 1, true, &mut cache);
+    assert_eq!(cache.len(), 1);
+    assert_eq!(transcript.proof_index(), 1);
+    let (fresh_proof, fresh_advices, _) =
+        verify_single_proof_no_eval(&params_verifier, &vkey, 1, true);
+    let translate = |proof: MultiOpenProof<_>, advices: Vec<AstPointRc<_>>| {
+        let mut targets = vec![
+            proof.w_x.eval(params_verifier.g1, 0).0,
+            proof.w_g.eval(-params_verifier.g1, 1).0,
+        ];
+        targets.extend(advices.into_iter().map(|p| p.0));
+        EvalContext::translate(&targets[..])
+    };
+    let cached = translate(proof, advices);
+    let fresh = translate(fresh_proof, fresh_advices);
+    assert!(EvalContext::diff(&cached, &fresh).is_empty());
+    assert_eq!(cached.const_points, fresh.const_points);
+    assert_eq!(cached.const_scalars, fresh.const_scalars);
+
+    // another protocol builds a new entry
+    verify_single_proof_no_eval_cached(&params_verifier, &vkey, 0, false, &mut cache);
+    assert_eq!(cache.len(), 2);
+
+    // so do params from another setup of the same size
+    let other_params = load_or_build_unsafe_params_seeded::<Bn256>(8, [1u8; 32], None);
+    let other_params_verifier: ParamsVerifier<Bn256> = other_params.verifier(1).unwrap();
+    verify_single_proof_no_eval_cached(&other_params_verifier, &vkey, 0, true, &mut cache);
+    assert_eq!(cache.len(), 3);
+}
+
+#[test]
+fn test_verify_aggregation_proofs_with_offset() {
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::ast_eval::EvalOps;
+    use crate::api::halo2::verify_aggregation_proofs_with_offset;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use std::collections::BTreeSet;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let msm_groups = |msm_offset| {
+        let (w_x, w_g, _) = verify_aggregation_proofs_with_offset(
+            &params_verifier,
+            &[&vkey],
+            &vec![],
+            true,
+            &vec![],
+            msm_offset,
+        );
+        EvalContext::translate(&[w_x.0, w_g.0])
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                EvalOps::MSMSlice(_, _, group) => Some(*group),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>()
+    };
+
+    assert_eq!(msm_groups(0), BTreeSet::from([0, 1]));
+    assert_eq!(msm_groups(2), BTreeSet::from([2, 3]));
+}
+
+#[test]
+fn test_evaluation_query_simplify() {
+    use crate::api::arith::AstPoint;
+    use crate::api::arith::AstPointRc;
+    use crate::api::arith::AstScalar;
+    use crate::api::arith::AstScalarRc;
+    use crate::api::halo2::query::CommitQuery;
+    use crate::api::halo2::query::EvaluationQuerySchema;
+    use crate::api::halo2::query::EvaluationQuerySchemaRc;
+    use halo2_proofs::arithmetic::CurveAffine;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use std::rc::Rc;
+
+    let p = crate::pconst!(G1Affine::generator());
+    let a = Rc::new(CommitQuery::new(
+        "a".to_owned(),
+        Some(p),
+        Some(crate::sconst!(Fr::from(3u64))),
+    ));
+    let zero = || crate::scalar!(crate::sconst!(Fr::zero()));
+    let one = || crate::scalar!(crate::sconst!(Fr::one()));
+    let x = crate::eval!(a.clone()) * crate::scalar!(crate::sconst!(Fr::from(5u64)));
+
+    let tree = one() * (zero() + x.clone())
+        + (x.clone() + zero()) * one()
+        + zero() * crate::commit!(a.clone());
+    let simplified = tree.0.as_ref().clone().simplify();
+
+    assert_eq!(
+        simplified,
+        EvaluationQuerySchema::Add(x.0.clone(), x.0.clone(), false)
+    );
+    assert_eq!(
+        tree.0.get_eval().0.check_const_and_get(),
+        simplified.get_eval().0.check_const_and_get()
+    );
+    assert_eq!(
+        simplified.get_eval().0.check_const_and_get(),
+        Some(Fr::from(30u64))
+    );
+
+    let tree: EvaluationQuerySchemaRc<G1Affine> = zero() * crate::commit!(a);
+    assert!(tree.0.contains_commitment());
+    let simplified = tree.0.as_ref().clone().simplify();
+    assert!(!simplified.contains_commitment());
+    assert!(matches!(simplified, EvaluationQuerySchema::Scalar(s) if s.0.is_const_zero()));
+}
+
+#[test]
+fn test_evaluation_query_count_commitments() {
+    use crate::api::arith::AstPoint;
+    use crate::api::arith::AstPointRc;
+    use crate::api::arith::AstScalar;
+    use crate::api::arith::AstScalarRc;
+    use crate::api::halo2::query::CommitQuery;
+    use crate::api::halo2::query::EvaluationQuerySchemaRc;
+    use halo2_proofs::arithmetic::CurveAffine;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use std::rc::Rc;
+
+    let p = crate::pconst!(G1Affine::generator());
+    let q = crate::pconst!(G1Affine::generator());
+    let query = |key: &str, p: &AstPointRc<G1Affine>| {
+        Rc::new(CommitQuery::new(
+            key.to_owned(),
+            Some(p.clone()),
+            Some(crate::sconst!(Fr::from(3u64))),
+        ))
+    };
+    let a = query("a", &p);
+    let b = query("b", &q);
+    // same node as a under another key
+    let a_alias = query("a_alias", &p);
+    let s = || crate::scalar!(crate::sconst!(Fr::from(5u64)));
+
+    let tree: EvaluationQuerySchemaRc<G1Affine> = crate::commit!(a.clone()) * s()
+        + crate::echeckpoint!(
+            "b".to_owned(),
+            (crate::commit!(b.clone()) + crate::eval!(b.clone())).0
+        )
+        + crate::commit!(a_alias) * crate::eval!(a.clone())
+        + crate::eval!(a.clone()) * s();
+    assert_eq!(tree.0.count_commitments(), 2);
+    // the msm also has the g1 term of the evals
+    match tree.eval(G1Affine::generator(), 0).0.as_ref() {
+        AstPoint::MultiExp(pl, _) => assert_eq!(pl.len(), 2 + 1),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_gwc_multi_open_one_w_per_point() {
+    use crate::api::halo2::verify_single_proof_with_params;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use circuits::samples::simple::SimpleCircuit;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+    use std::collections::BTreeSet;
+
+    let (circuit, _) = SimpleCircuit::<Fr>::default_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let (_, _, verifier_params) = verify_single_proof_with_params(&params_verifier, &vkey, true);
+
+    // queries of the same rotation are merged into one evaluation proof
+    let queries = verifier_params.get_all_queries();
+    let rotations = queries.iter().map(|q| q.rotation).collect::<BTreeSet<_>>();
+    assert!(queries.len() > rotations.len());
+
+    // so no two evaluation proofs share a point, and each carries its own w of the proof
+    let proofs = verifier_params.get_point_schemas_gwc();
+    assert_eq!(proofs.len(), rotations.len());
+    assert_eq!(proofs.len(), verifier_params.multiopen_commitments.len());
+
+    // w_x is a msm over these w only, one term each
+    let multi_open = verifier_params.batch_multi_open_proofs_gwc();
+    assert_eq!(multi_open.w_x.0.count_commitments(), proofs.len());
+}
+
+#[test]
+fn test_commit_query_ptr_dedup() {
+    use crate::api::arith::AstPoint;
+    use crate::api::arith::AstPointRc;
+    use crate::api::halo2::query::CommitQuery;
+    use crate::api::halo2::query::EvaluationQuerySchemaRc;
+    use halo2_proofs::arithmetic::CurveAffine;
+    use halo2_proofs::pairing::bn256::G1Affine;
+    use std::rc::Rc;
+
+    let p = crate::pconst!(G1Affine::generator());
+    let q = crate::pconst!(G1Affine::generator());
+    let a = Rc::new(CommitQuery::new("a".to_owned(), Some(p.clone()), None));
+    let b = Rc::new(CommitQuery::new("b".to_owned(), Some(p), None));
+    let c = Rc::new(CommitQuery::new("c".to_owned(), Some(q), None));
+    // queries are equal by key, sharing the commitment node only merges their msm terms
+    assert!(a != b);
+    assert!(a.same_msm_term(&b));
+    assert!(!a.same_msm_term(&c));
+    assert!(a == Rc::new(CommitQuery::new("a".to_owned(), None, None)));
+
+    let msm_len =
+        |x: EvaluationQuerySchemaRc<G1Affine>| match x.eval(G1Affine::generator(), 0).0.as_ref() {
+            AstPoint::MultiExp(pl, _) => pl.len(),
+            _ => unreachable!(),
+        };
+    // a and b share one msm term
+    assert_eq!(
+        msm_len(crate::commit!(a.clone()) + crate::commit!(b)) + 1,
+        msm_len(crate::commit!(a) + crate::commit!(c))
+    );
+}
+
+#[test]
+fn test_multi_open_proof_verify_native() {
+    use crate::api::halo2::verify_single_proof_no_eval;
+    use crate::circuits::utils::instance_to_instance_commitment;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::transcript::poseidon::PoseidonRead;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use halo2_proofs::poly::commitment::ParamsVerifier;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let proof = load_or_create_proof::<Bn256, _>(
+        &params,
+        vkey.clone(),
+        circuit,
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        None,
+        TranscriptHash::Poseidon,
+        false,
+        ProofProtocol::SHPLONK,
+    );
+
+    let instance_commitments =
+        instance_to_instance_commitment(&params_verifier, &[&vkey], vec![&instances]);
+
+    let (pair, _, _) = verify_single_proof_no_eval(&params_verifier, &vkey, 0, false);
+    assert!(pair.verify_native(
+        &params_verifier,
+        params_verifier.g1,
+        instance_commitments,
+        vec![PoseidonRead::init(&proof[..])],
+    ));
+
+    let bad_instances = instances
+        .iter()
+        .map(|x| x.iter().map(|x| *x + Fr::one()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    let bad_instance_commitments =
+        instance_to_instance_commitment(&params_verifier, &[&vkey], vec![&bad_instances]);
+    let (pair, _, _) = verify_single_proof_no_eval(&params_verifier, &vkey, 0, false);
+    assert!(!pair.verify_native(
+        &params_verifier,
+        params_verifier.g1,
+        bad_instance_commitments,
+        vec![PoseidonRead::init(&proof[..])],
+    ));
+}
+
+#[test]
+#[cfg(not(target_arch = "wasm32"))]
+fn test_verify_proofs_with_timeout() {
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::verify_proofs_with_timeout;
+    use crate::native_verifier::VerificationError;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+    use std::time::Duration;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let proof = load_or_create_proof::<Bn256, _>(
+        &params,
+        vkey.clone(),
+        circuit,
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        None,
+        TranscriptHash::Poseidon,
+        false,
+        ProofProtocol::GWC,
+    );
+
+    let verify = |instances: &Vec<Vec<Fr>>, timeout| {
+        verify_proofs_with_timeout::<Bn256>(
+            params.verifier(1).unwrap(),
+            vec![vkey.clone()],
+            vec![instances.clone()],
+            vec![proof.clone()],
+            TranscriptHash::Poseidon,
+            vec![],
+            vec![ProofProtocol::GWC],
+            timeout,
+        )
+    };
+
+    assert_eq!(verify(&instances, Duration::from_secs(600)), Ok(()));
+    assert_eq!(
+        verify(&instances, Duration::ZERO),
+        Err(VerificationError::Timeout)
+    );
+
+    let bad_instances = instances
+        .iter()
+        .map(|x| x.iter().map(|x| *x + Fr::one()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        verify(&bad_instances, Duration::from_secs(600)),
+        Err(VerificationError::Failed)
+    );
+}
+
+#[test]
+fn test_verify_proofs_commitment_mismatch() {
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::try_verify_proofs;
+    use crate::native_verifier::ProofVerifyError;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let (circuit, _) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let mut instances = vec![];
+    let mut proofs = vec![];
+    for _ in 0..2 {
+        let (circuit, instance) = SimpleCircuit::<Fr>::random_new_with_instance();
+        proofs.push(load_or_create_proof::<Bn256, _>(
+            &params,
+            vkey.clone(),
+            circuit,
+            &instance.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            TranscriptHash::Poseidon,
+            false,
+            ProofProtocol::GWC,
+        ));
+        instances.push(instance);
+    }
+
+    let res = try_verify_proofs::<Bn256>(
+        &params.verifier(1).unwrap(),
+        &[&vkey, &vkey],
+        instances.iter().collect(),
+        proofs,
+        TranscriptHash::Poseidon,
+        &vec![[0, 0, 1, 0]],
+        &[ProofProtocol::GWC, ProofProtocol::GWC],
+    );
+
+    match res {
+        Err(ProofVerifyError::CommitmentMismatch {
+            check_index,
+            lhs,
+            rhs,
+        }) => {
+            assert_eq!(check_index, 0);
+            assert_ne!(lhs, rhs);
+        }
+        _ => panic!("expect commitment mismatch, got {:?}", res),
+    }
+}
+
+#[test]
+fn test_estimate_proof_size() {
+    use crate::circuits::utils::estimate_proof_size;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let (circuit, _) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    for hash in [
+        TranscriptHash::Blake2b,
+        TranscriptHash::Poseidon,
+        TranscriptHash::Sha,
+        TranscriptHash::Keccak,
+    ] {
+        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        let proof = load_or_create_proof::<Bn256, _>(
+            &params,
+            vkey.clone(),
+            circuit,
+            &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            hash,
+            false,
+            ProofProtocol::default_for(hash),
+        );
+        assert_eq!(estimate_proof_size::<Bn256>(&vkey, hash), proof.len());
+    }
+}
+
+#[test]
+fn test_batch_verify_proofs() {
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::batch_verify_proofs;
+    use crate::native_verifier::VerificationError;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let (circuit, _) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let mut all_instances = vec![];
+    let mut proofs = vec![];
+    for _ in 0..3 {
+        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        proofs.push(load_or_create_proof::<Bn256, _>(
+            &params,
+            vkey.clone(),
+            circuit,
+            &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            TranscriptHash::Poseidon,
+            false,
+            ProofProtocol::default_for(TranscriptHash::Poseidon),
+        ));
+        all_instances.push(instances);
+    }
+
+    let params_verifier = params.verifier(1).unwrap();
+    assert_eq!(
+        batch_verify_proofs::<Bn256>(
+            &params_verifier,
+            &vkey,
+            &all_instances,
+            &proofs,
+            TranscriptHash::Poseidon,
+        ),
+        Ok(())
+    );
+
+    all_instances[1][0][0] += Fr::one();
+    assert_eq!(
+        batch_verify_proofs::<Bn256>(
+            &params_verifier,
+            &vkey,
+            &all_instances,
+            &proofs,
+            TranscriptHash::Poseidon,
+        ),
+        Err(VerificationError::Failed)
+    );
+}
+
+#[test]
+fn test_verify_proofs_heterogeneous() {
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::verify_proofs_heterogeneous;
+    use crate::native_verifier::VerificationError;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let (circuit, _) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+    let mut all_instances = vec![];
+    let mut proofs = vec![];
+    for hash in [
+        TranscriptHash::Poseidon,
+        TranscriptHash::Keccak,
+        TranscriptHash::Poseidon,
+    ] {
+        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        let proof = load_or_create_proof::<Bn256, _>(
+            &params,
+            vkey.clone(),
+            circuit,
+            &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            hash,
+            false,
+            ProofProtocol::default_for(hash),
+        );
+        proofs.push((proof, hash));
+        all_instances.push(instances);
+    }
+
+    let params_verifier = params.verifier(1).unwrap();
+    let vkeys = [&vkey, &vkey, &vkey];
+    let verify = |all_instances: &Vec<Vec<Vec<Fr>>>, commitment_check: &Vec<[usize; 4]>| {
+        verify_proofs_heterogeneous::<Bn256>(
+            &params_verifier,
+            &vkeys,
+            all_instances.iter().collect(),
+            proofs.clone(),
+            commitment_check,
+        )
+    };
+
+    assert_eq!(verify(&all_instances, &vec![]), Ok(()));
+    assert_eq!(verify(&all_instances, &vec![[1, 0, 1, 0]]), Ok(()));
+    // advice commitments of different proofs are blinded differently
+    assert_eq!(
+        verify(&all_instances, &vec![[0, 0, 1, 0]]),
+        Err(VerificationError::Failed)
+    );
+
+    all_instances[1][0][0] += Fr::one();
+    assert_eq!(
+        verify(&all_instances, &vec![]),
+        Err(VerificationError::Failed)
+    );
+}
+
+#[test]
+fn test_inspect_proof() {
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::inspect_proof;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    for hash in [TranscriptHash::Poseidon, TranscriptHash::Sha] {
+        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+        let proof = load_or_create_proof::<Bn256, _>(
+            &params,
+            vkey.clone(),
+            circuit,
+            &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+            None,
+            hash,
+            false,
+            ProofProtocol::default_for(hash),
+        );
+
+        let inspection = inspect_proof::<Bn256>(&proof, &vkey, hash);
+        assert!(inspection.size_matches);
+        assert_eq!(inspection.byte_count, proof.len());
+        assert_eq!(
+            inspection.actual_point_count,
+            inspection.expected_point_count
+        );
+        assert_eq!(
+            inspection.actual_scalar_count,
+            inspection.expected_scalar_count
+        );
+
+        let truncated = inspect_proof::<Bn256>(&proof[..proof.len() - 1], &vkey, hash);
+        assert!(!truncated.size_matches);
+        assert_eq!(
+            truncated.actual_point_count + 1,
+            truncated.expected_point_count
+        );
+
+        let mut extended = proof.clone();
+        extended.push(0);
+        assert!(!inspect_proof::<Bn256>(&extended, &vkey, hash).size_matches);
+    }
+}
+
+#[test]
+fn test_native_context_eval_partial() {
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::halo2::verify_aggregation_proofs;
+    use crate::circuits::utils::instance_to_instance_commitment;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::NativeEvalContext;
+    use crate::transcript::poseidon::PoseidonRead;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let proof = load_or_create_proof::<Bn256, _>(
+        &params,
+        vkey.clone(),
+        circuit,
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        None,
+        TranscriptHash::Poseidon,
+        false,
+        ProofProtocol::GWC,
+    );
+
+    let params_verifier = params.verifier(1).unwrap();
+    let (w_x, w_g, _) =
+        verify_aggregation_proofs(&params_verifier, &[&vkey], &vec![], false, &vec![]);
+    let c = EvalContext::translate(&[w_x.0, w_g.0]);
+    let instance_commitments =
+        instance_to_instance_commitment(&params_verifier, &[&vkey], vec![&instances]);
+
+    let empty = vec![];
+    let transcripts = || {
+        vec![
+            PoseidonRead::init(&proof[..]),
+            PoseidonRead::init(&empty[..]),
+        ]
+    };
+
+    let mut ctx = NativeEvalContext::<Bn256, _, _>::new(
+        c.clone(),
+        instance_commitments.clone(),
+        transcripts(),
+    );
+    ctx.context_eval();
+    let finals = ctx.finals.clone();
+    let values = ctx.values.clone();
+
+    let mut partial_ctx =
+        NativeEvalContext::<Bn256, _, _>::new(c.clone(), instance_commitments, transcripts());
+    assert_eq!(partial_ctx.context_eval_partial(0), None);
+    for i in 1..=c.ops.len() {
+        assert_eq!(partial_ctx.context_eval_partial(i), Some(values[i - 1]));
+    }
+    partial_ctx.context_eval();
+    assert_eq!(partial_ctx.finals, finals);
+    assert_eq!(partial_ctx.values, values);
+
+    partial_ctx.reset();
+    assert!(partial_ctx.values.is_empty());
+    assert!(partial_ctx.finals.is_empty());
+    partial_ctx.set_transcripts(transcripts());
+    partial_ctx.context_eval_partial(c.ops.len() / 2);
+    partial_ctx.context_eval();
+    assert_eq!(partial_ctx.finals, finals);
+}
+
+#[test]
+fn test_native_context_checkpoint_values() {
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::ast_eval::EvalOps;
+    use crate::api::ast_eval::EvalPos;
+    use crate::api::ast_eval::OpType;
+    use crate::api::halo2::verify_aggregation_proofs;
+    use crate::circuits::utils::instance_to_instance_commitment;
+    use crate::circuits::utils::load_or_build_unsafe_params;
+    use crate::circuits::utils::load_or_build_vkey;
+    use crate::circuits::utils::load_or_create_proof;
+    use crate::native_verifier::NativeEvalContext;
+    use crate::transcript::poseidon::PoseidonRead;
+    use circuits::samples::simple::SimpleCircuit;
+    use circuits::utils::ProofProtocol;
+    use circuits::utils::TranscriptHash;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+    let params = load_or_build_unsafe_params::<Bn256>(8, None);
+    let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+    let proof = load_or_create_proof::<Bn256, _>(
+        &params,
+        vkey.clone(),
+        circuit,
+        &instances.iter().map(|x| &x[..]).collect::<Vec<_>>(),
+        None,
+        TranscriptHash::Poseidon,
+        false,
+        ProofProtocol::GWC,
+    );
+
+    let params_verifier = params.verifier(1).unwrap();
+    let (w_x, w_g, _) =
+        verify_aggregation_proofs(&params_verifier, &[&vkey], &vec![], false, &vec![]);
+    let mut c = EvalContext::translate(&[w_x.0, w_g.0]);
+    c.prune_checkpoints();
+    let j = c
+        .ops
+        .iter()
+        .position(|op| op.op_type() == OpType::TranscriptSqueeze)
+        .unwrap();
+    let k = c
+        .ops
+        .iter()
+        .position(|op| op.op_type() == OpType::TranscriptReadPoint)
+        .unwrap();
+    c.ops
+        .push(EvalOps::CheckPoint("squeeze".to_owned(), EvalPos::Ops(j)));
+    c.ops
+        .push(EvalOps::CheckPoint("point".to_owned(), EvalPos::Ops(k)));
+    let instance_commitments =
+        instance_to_instance_commitment(&params_verifier, &[&vkey], vec![&instances]);
+
+    let empty = vec![];
+    let transcripts = || {
+        vec![
+            PoseidonRead::init(&proof[..]),
+            PoseidonRead::init(&empty[..]),
+        ]
+    };
+
+    let mut ctx = NativeEvalContext::<Bn256, _, _>::new(
+        c.clone(),
+        instance_commitments.clone(),
+        transcripts(),
+    );
+    ctx.context_eval();
+    assert!(ctx.checkpoint_values().is_empty());
+
+    let mut ctx = NativeEvalContext::<Bn256, _, _>::new(c, instance_commitments, transcripts());
+    ctx.enable_checkpoints();
+    ctx.context_eval();
+    let checkpoints = ctx.checkpoint_values();
+    assert_eq!(
+        checkpoints,
+        vec![
+            ("squeeze".to_owned(), None, ctx.values[j].1),
+            ("point".to_owned(), ctx.values[k].0, None),
+        ]
+    );
+
+    ctx.reset();
+    assert!(ctx.checkpoint_values().is_empty());
+}
+
 #[test]
 fn test_single_rec() {
     use crate::circuits::utils::calc_hash;
@@ -121,7 +1466,7 @@ fn test_single_rec() {
     for i in 0..=end_of_non_final_agg_idx {
         config.target_aggregator_constant_hash_instance_offset =
             vec![(1, 0, last_agg_instances[0])];
-        config.absorb_instance = vec![(0, 0, 1, 1)];
+        config.absorb_instance = vec![(0, 0, 1, 0, 1)];
 
         if i == end_of_non_final_agg_idx {
             config.is_final_aggregator = true;
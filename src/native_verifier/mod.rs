@@ -1,12 +1,17 @@
 use crate::api::ast_eval::EvalContext;
 use crate::api::ast_eval::EvalOps;
 use crate::api::ast_eval::EvalPos;
+use crate::api::halo2::builder::proof_transcript_layout;
 use crate::api::halo2::verify_aggregation_proofs;
 use crate::circuits::utils::instance_to_instance_commitment;
+use crate::circuits::utils::shplonk_proof_indices;
+use crate::circuits::utils::ProofProtocol;
 use crate::circuits::utils::TranscriptHash;
 use crate::transcript::poseidon::PoseidonPure;
 use crate::transcript::poseidon::PoseidonRead;
 use crate::transcript::sha256::ShaRead;
+use ark_std::rand::rngs::OsRng;
+use halo2_proofs::arithmetic::CurveAffine;
 use halo2_proofs::arithmetic::Field;
 use halo2_proofs::arithmetic::MillerLoopResult;
 use halo2_proofs::arithmetic::MultiMillerLoop;
@@ -18,7 +23,14 @@ use halo2_proofs::transcript::Blake2bRead;
 use halo2_proofs::transcript::Challenge255;
 use halo2_proofs::transcript::EncodedChallenge;
 use halo2_proofs::transcript::TranscriptRead;
+use std::collections::HashMap;
 use std::marker::PhantomData;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 
 pub struct NativeEvalContext<
     E: MultiMillerLoop,
@@ -29,6 +41,8 @@ pub struct NativeEvalContext<
     pub values: Vec<(Option<E::G1Affine>, Option<E::Scalar>)>,
 
     c: EvalContext<E::G1Affine>,
+    checkpoints_enabled: bool,
+    checkpoints: Vec<(String, Option<E::G1Affine>, Option<E::Scalar>)>,
     instance_commitments: Vec<Vec<E::G1Affine>>,
     t: Vec<T>,
     _mark: PhantomData<EC>,
@@ -46,6 +60,8 @@ impl<E: MultiMillerLoop, EC: EncodedChallenge<E::G1Affine>, T: TranscriptRead<E:
             c,
             instance_commitments,
             t,
+            checkpoints_enabled: false,
+            checkpoints: vec![],
             values: vec![],
             finals: vec![],
             _mark: PhantomData,
@@ -76,65 +92,47 @@ impl<E: MultiMillerLoop, EC: EncodedChallenge<E::G1Affine>, T: TranscriptRead<E:
         }
     }
 
-    pub fn context_eval(&mut self) {
-        for (_, op) in self.c.ops.iter().enumerate() {
-            let v = match op {
-                EvalOps::TranscriptReadScalar(i, _) => {
-                    (None, Some(self.t[*i].read_scalar().unwrap()))
-                }
-                EvalOps::TranscriptReadPoint(i, _) => {
-                    (Some(self.t[*i].read_point().unwrap()), None)
-                }
-                EvalOps::TranscriptCommonScalar(i, _, s) => {
-                    let v = self.eval_scalar_pos(s);
-                    self.t[*i].common_scalar(v).unwrap();
-                    (None, None)
-                }
-                EvalOps::TranscriptCommonPoint(i, _, p) => {
-                    let v = self.eval_point_pos(p);
-                    self.t[*i].common_point(v).unwrap();
-                    (None, None)
-                }
-                EvalOps::TranscriptSqueeze(i, _) => {
-                    (None, Some(self.t[*i].squeeze_challenge().get_scalar()))
-                }
-                EvalOps::ScalarAdd(a, b) => (
-                    None,
-                    Some(self.eval_scalar_pos(a) + self.eval_scalar_pos(b)),
-                ),
-                EvalOps::ScalarSub(a, b) => (
-                    None,
-                    Some(self.eval_scalar_pos(a) - self.eval_scalar_pos(b)),
-                ),
-                EvalOps::ScalarMul(a, b, _) => (
-                    None,
-                    Some(self.eval_scalar_pos(a) * self.eval_scalar_pos(b)),
-                ),
-                EvalOps::ScalarDiv(a, b) => (
-                    None,
-                    Some(self.eval_scalar_pos(a) * self.eval_scalar_pos(b).invert().unwrap()),
-                ),
-                EvalOps::ScalarPow(a, n) => {
-                    (None, Some(self.eval_scalar_pos(a).pow_vartime([*n as u64])))
-                }
-                EvalOps::MSM(_, last) => (Some(self.eval_point_pos(last)), None),
-                EvalOps::MSMSlice((p, s), last, _) => {
-                    let curr = (self.eval_point_pos(p) * self.eval_scalar_pos(s)).to_affine();
-                    let acc = last
-                        .as_ref()
-                        .map(|x| (self.eval_point_pos(x) + curr).to_affine())
-                        .unwrap_or(curr);
-                    (Some(acc), None)
-                }
-                EvalOps::CheckPoint(tag, v) => {
-                    if false {
-                        println!("checkpoint {}: {:?}", tag, self.eval_any_pos(v));
-                    }
-                    self.eval_any_pos(v)
-                }
-            };
+    // Clear evaluated values so evaluation can restart from the first op.
+    // Transcripts are not rewound, replace them by set_transcripts before evaluating again.
+    pub fn reset(&mut self) {
+        self.values.clear();
+        self.finals.clear();
+        self.checkpoints.clear();
+    }
+
+    // Record the value of every CheckPoint op evaluated from now on.
+    // Disabled by default to keep evaluation free of the extra clones.
+    pub fn enable_checkpoints(&mut self) {
+        self.checkpoints_enabled = true;
+    }
+
+    // Tag and value of each evaluated CheckPoint op, in op order.
+    // Only populated after enable_checkpoints.
+    pub fn checkpoint_values(&self) -> Vec<(String, Option<E::G1Affine>, Option<E::Scalar>)> {
+        self.checkpoints.clone()
+    }
+
+    pub fn set_transcripts(&mut self, t: Vec<T>) {
+        self.t = t;
+    }
+
+    // Evaluate ops before until_op that have not been evaluated yet,
+    // returns the value of the last evaluated op.
+    pub fn context_eval_partial(
+        &mut self,
+        until_op: usize,
+    ) -> Option<(Option<E::G1Affine>, Option<E::Scalar>)> {
+        assert!(until_op <= self.c.ops.len());
+        while self.values.len() < until_op {
+            let v = self.eval_op(self.values.len());
             self.values.push(v);
         }
+        until_op.checked_sub(1).map(|i| self.values[i])
+    }
+
+    // Evaluate the remaining ops and collect finals.
+    pub fn context_eval(&mut self) {
+        self.context_eval_partial(self.c.ops.len());
 
         self.finals = self
             .c
@@ -143,6 +141,67 @@ impl<E: MultiMillerLoop, EC: EncodedChallenge<E::G1Affine>, T: TranscriptRead<E:
             .map(|x| self.values[*x].0.unwrap())
             .collect();
     }
+
+    fn eval_op(&mut self, idx: usize) -> (Option<E::G1Affine>, Option<E::Scalar>) {
+        let op = &self.c.ops[idx];
+        match op {
+            EvalOps::TranscriptReadScalar(i, _) => (None, Some(self.t[*i].read_scalar().unwrap())),
+            EvalOps::TranscriptReadPoint(i, _) => (Some(self.t[*i].read_point().unwrap()), None),
+            EvalOps::TranscriptCommonScalar(i, _, s) => {
+                let v = self.eval_scalar_pos(s);
+                self.t[*i].common_scalar(v).unwrap();
+                (None, None)
+            }
+            EvalOps::TranscriptCommonPoint(i, _, p) => {
+                let v = self.eval_point_pos(p);
+                self.t[*i].common_point(v).unwrap();
+                (None, None)
+            }
+            EvalOps::TranscriptSqueeze(i, _) => {
+                (None, Some(self.t[*i].squeeze_challenge().get_scalar()))
+            }
+            EvalOps::ScalarAdd(a, b) => (
+                None,
+                Some(self.eval_scalar_pos(a) + self.eval_scalar_pos(b)),
+            ),
+            EvalOps::ScalarSub(a, b) => (
+                None,
+                Some(self.eval_scalar_pos(a) - self.eval_scalar_pos(b)),
+            ),
+            EvalOps::ScalarNeg(a) => (None, Some(-self.eval_scalar_pos(a))),
+            EvalOps::ScalarMul(a, b, _) => (
+                None,
+                Some(self.eval_scalar_pos(a) * self.eval_scalar_pos(b)),
+            ),
+            EvalOps::ScalarDiv(a, b) => (
+                None,
+                Some(self.eval_scalar_pos(a) * self.eval_scalar_pos(b).invert().unwrap()),
+            ),
+            EvalOps::ScalarInv(a) => (None, Some(self.eval_scalar_pos(a).invert().unwrap())),
+            EvalOps::ScalarPow(a, n) => {
+                (None, Some(self.eval_scalar_pos(a).pow_vartime([*n as u64])))
+            }
+            EvalOps::MSM(_, last) => (Some(self.eval_point_pos(last)), None),
+            EvalOps::MSMSlice((p, s), last, _) => {
+                let curr = (self.eval_point_pos(p) * self.eval_scalar_pos(s)).to_affine();
+                let acc = last
+                    .as_ref()
+                    .map(|x| (self.eval_point_pos(x) + curr).to_affine())
+                    .unwrap_or(curr);
+                (Some(acc), None)
+            }
+            EvalOps::CheckPoint(tag, v) => {
+                let v = self.eval_any_pos(v);
+                if false {
+                    println!("checkpoint {}: {:?}", tag, v);
+                }
+                if self.checkpoints_enabled {
+                    self.checkpoints.push((tag.clone(), v.0, v.1));
+                }
+                v
+            }
+        }
+    }
 }
 pub fn verify_single_proof<E: MultiMillerLoop>(
     params: &ParamsVerifier<E>,
@@ -150,8 +209,7 @@ pub fn verify_single_proof<E: MultiMillerLoop>(
     instances: &Vec<Vec<E::Scalar>>,
     proof: Vec<u8>,
     hash: TranscriptHash,
-    use_shplonk_as_default: bool,
-    proofs_with_shplonk: &Vec<usize>,
+    protocol: ProofProtocol,
 ) {
     verify_proofs(
         params,
@@ -160,40 +218,19 @@ pub fn verify_single_proof<E: MultiMillerLoop>(
         vec![proof],
         hash,
         &vec![],
-        use_shplonk_as_default,
-        proofs_with_shplonk,
+        &[protocol],
     )
 }
 
-pub fn verify_proofs<E: MultiMillerLoop>(
-    params: &ParamsVerifier<E>,
-    vkey: &[&VerifyingKey<E::G1Affine>],
-    instances: Vec<&Vec<Vec<E::Scalar>>>,
-    proofs: Vec<Vec<u8>>,
+// Evaluate c against the transcripts of proofs, an empty transcript is appended for
+// the aggregator level challenges.
+fn native_eval_finals<E: MultiMillerLoop>(
+    c: EvalContext<E::G1Affine>,
+    instance_commitments: Vec<Vec<E::G1Affine>>,
+    proofs: &[Vec<u8>],
     hash: TranscriptHash,
-    commitment_check: &Vec<[usize; 4]>,
-    use_shplonk_as_default: bool,
-    proofs_with_shplonk: &Vec<usize>,
-) {
-    let (w_x, w_g, advices) = verify_aggregation_proofs(
-        params,
-        vkey,
-        commitment_check,
-        use_shplonk_as_default,
-        proofs_with_shplonk,
-    );
-
-    let instance_commitments = instance_to_instance_commitment(params, vkey, instances);
-
-    let mut targets = vec![w_x.0, w_g.0];
-    for idx in commitment_check {
-        targets.push(advices[idx[0]][idx[1]].0.clone());
-        targets.push(advices[idx[2]][idx[3]].0.clone());
-    }
-
-    let c = EvalContext::translate(&targets[..]);
-
-    let pl = match hash {
+) -> Vec<E::G1Affine> {
+    match hash {
         TranscriptHash::Blake2b => {
             let mut t = vec![];
             for i in 0..proofs.len() {
@@ -249,7 +286,110 @@ pub fn verify_proofs<E: MultiMillerLoop>(
             ctx.context_eval();
             ctx.finals
         }
-    };
+        TranscriptHash::Blake2s => {
+            let mut t = vec![];
+            for i in 0..proofs.len() {
+                t.push(ShaRead::<_, _, _, blake2::Blake2s256>::init(&proofs[i][..]));
+            }
+            let empty = vec![];
+            t.push(ShaRead::init(&empty[..]));
+            let mut ctx = NativeEvalContext::<E, _, _>::new(c, instance_commitments, t);
+            ctx.context_eval();
+            ctx.finals
+        }
+        TranscriptHash::Sha512 => {
+            let mut t = vec![];
+            for i in 0..proofs.len() {
+                t.push(ShaRead::<_, _, _, sha2::Sha512>::init(&proofs[i][..]));
+            }
+            let empty = vec![];
+            t.push(ShaRead::init(&empty[..]));
+            let mut ctx = NativeEvalContext::<E, _, _>::new(c, instance_commitments, t);
+            ctx.context_eval();
+            ctx.finals
+        }
+        TranscriptHash::Sha3_256 => {
+            let mut t = vec![];
+            for i in 0..proofs.len() {
+                t.push(ShaRead::<_, _, _, sha3::Sha3_256>::init(&proofs[i][..]));
+            }
+            let empty = vec![];
+            t.push(ShaRead::init(&empty[..]));
+            let mut ctx = NativeEvalContext::<E, _, _>::new(c, instance_commitments, t);
+            ctx.context_eval();
+            ctx.finals
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProofVerifyError<C: CurveAffine> {
+    PairingFailed,
+    // check_index is the index of the failed entry in commitment_check
+    CommitmentMismatch { check_index: usize, lhs: C, rhs: C },
+}
+
+pub fn verify_proofs<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vkey: &[&VerifyingKey<E::G1Affine>],
+    instances: Vec<&Vec<Vec<E::Scalar>>>,
+    proofs: Vec<Vec<u8>>,
+    hash: TranscriptHash,
+    commitment_check: &Vec<[usize; 4]>,
+    protocols: &[ProofProtocol],
+) {
+    try_verify_proofs(
+        params,
+        vkey,
+        instances,
+        proofs,
+        hash,
+        commitment_check,
+        protocols,
+    )
+    .unwrap()
+}
+
+// Commitment checks run before the pairing check, because a mismatched commitment
+// is replaced by its pair in the aggregated msm and fails the pairing as well.
+pub fn try_verify_proofs<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vkey: &[&VerifyingKey<E::G1Affine>],
+    instances: Vec<&Vec<Vec<E::Scalar>>>,
+    proofs: Vec<Vec<u8>>,
+    hash: TranscriptHash,
+    commitment_check: &Vec<[usize; 4]>,
+    protocols: &[ProofProtocol],
+) -> Result<(), ProofVerifyError<E::G1Affine>> {
+    let (w_x, w_g, advices) = verify_aggregation_proofs(
+        params,
+        vkey,
+        commitment_check,
+        false,
+        &shplonk_proof_indices(protocols),
+    );
+
+    let instance_commitments = instance_to_instance_commitment(params, vkey, instances);
+
+    let mut targets = vec![w_x.0, w_g.0];
+    for idx in commitment_check {
+        targets.push(advices[idx[0]][idx[1]].0.clone());
+        targets.push(advices[idx[2]][idx[3]].0.clone());
+    }
+
+    let c = EvalContext::translate(&targets[..]);
+
+    let pl = native_eval_finals::<E>(c, instance_commitments, &proofs, hash);
+
+    for (check_index, c) in pl.chunks(2).skip(1).enumerate() {
+        if c[0] != c[1] {
+            return Err(ProofVerifyError::CommitmentMismatch {
+                check_index,
+                lhs: c[0],
+                rhs: c[1],
+            });
+        }
+    }
 
     let s_g2_prepared = E::G2Prepared::from(params.s_g2);
     let n_g2_prepared = E::G2Prepared::from(-params.g2);
@@ -259,9 +399,316 @@ pub fn verify_proofs<E: MultiMillerLoop>(
             .is_identity(),
     );
 
-    assert!(success);
+    if !success {
+        return Err(ProofVerifyError::PairingFailed);
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofInspection {
+    pub byte_count: usize,
+    pub expected_point_count: usize,
+    pub expected_scalar_count: usize,
+    pub actual_point_count: usize,
+    pub actual_scalar_count: usize,
+    // counts match and no trailing bytes are left
+    pub size_matches: bool,
+}
+
+// Read points and scalars in the order of the proof layout, stop at the first failed read.
+fn count_transcript_reads<
+    E: MultiMillerLoop,
+    EC: EncodedChallenge<E::G1Affine>,
+    T: TranscriptRead<E::G1Affine, EC>,
+>(
+    mut t: T,
+    n_points: usize,
+    n_scalars: usize,
+    n_multiopen_points: usize,
+) -> (usize, usize) {
+    let points = (0..n_points).take_while(|_| t.read_point().is_ok()).count();
+    if points < n_points {
+        return (points, 0);
+    }
+    let scalars = (0..n_scalars)
+        .take_while(|_| t.read_scalar().is_ok())
+        .count();
+    if scalars < n_scalars {
+        return (points, scalars);
+    }
+    let multiopen_points = (0..n_multiopen_points)
+        .take_while(|_| t.read_point().is_ok())
+        .count();
+    (points + multiopen_points, scalars)
+}
+
+// Check the layout of a stored proof against vkey without verifying it.
+// The multi-open protocol is the default one of hash, see ProofProtocol::default_for.
+pub fn inspect_proof<E: MultiMillerLoop>(
+    proof: &[u8],
+    vkey: &VerifyingKey<E::G1Affine>,
+    hash: TranscriptHash,
+) -> ProofInspection {
+    let use_gwc = ProofProtocol::default_for(hash) == ProofProtocol::GWC;
+    let (n_points, n_scalars, n_multiopen_points) = proof_transcript_layout(vkey, use_gwc);
+
+    let mut reader = proof;
+    let (actual_point_count, actual_scalar_count) = match hash {
+        TranscriptHash::Blake2b => count_transcript_reads::<E, _, _>(
+            Blake2bRead::<_, E::G1Affine, Challenge255<_>>::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+        TranscriptHash::Poseidon => count_transcript_reads::<E, _, _>(
+            PoseidonRead::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+        TranscriptHash::Sha => count_transcript_reads::<E, _, _>(
+            ShaRead::<_, _, _, sha2::Sha256>::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+        TranscriptHash::Keccak => count_transcript_reads::<E, _, _>(
+            ShaRead::<_, _, _, sha3::Keccak256>::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+        TranscriptHash::Blake2s => count_transcript_reads::<E, _, _>(
+            ShaRead::<_, _, _, blake2::Blake2s256>::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+        TranscriptHash::Sha512 => count_transcript_reads::<E, _, _>(
+            ShaRead::<_, _, _, sha2::Sha512>::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+        TranscriptHash::Sha3_256 => count_transcript_reads::<E, _, _>(
+            ShaRead::<_, _, _, sha3::Sha3_256>::init(&mut reader),
+            n_points,
+            n_scalars,
+            n_multiopen_points,
+        ),
+    };
+
+    let expected_point_count = n_points + n_multiopen_points;
+    ProofInspection {
+        byte_count: proof.len(),
+        expected_point_count,
+        expected_scalar_count: n_scalars,
+        actual_point_count,
+        actual_scalar_count,
+        size_matches: actual_point_count == expected_point_count
+            && actual_scalar_count == n_scalars
+            && reader.is_empty(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerificationError {
+    Timeout,
+    Failed,
+}
+
+// Run verify_proofs on a new thread and wait at most `timeout` for it.
+// A timed out thread is detached and keeps running until it finishes.
+// Not available on wasm32 where std::thread is unsupported.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn verify_proofs_with_timeout<E: MultiMillerLoop + 'static>(
+    params: ParamsVerifier<E>,
+    vkey: Vec<VerifyingKey<E::G1Affine>>,
+    instances: Vec<Vec<Vec<E::Scalar>>>,
+    proofs: Vec<Vec<u8>>,
+    hash: TranscriptHash,
+    commitment_check: Vec<[usize; 4]>,
+    protocols: Vec<ProofProtocol>,
+    timeout: Duration,
+) -> Result<(), VerificationError> {
+    let (sender, receiver) = mpsc::channel();
+
+    thread::spawn(move || {
+        verify_proofs(
+            &params,
+            &vkey.iter().collect::<Vec<_>>()[..],
+            instances.iter().collect(),
+            proofs,
+            hash,
+            &commitment_check,
+            &protocols,
+        );
+        let _ = sender.send(());
+    });
+
+    match receiver.recv_timeout(timeout) {
+        Ok(()) => Ok(()),
+        Err(mpsc::RecvTimeoutError::Timeout) => Err(VerificationError::Timeout),
+        // sender is dropped without sending if verify_proofs panics
+        Err(mpsc::RecvTimeoutError::Disconnected) => Err(VerificationError::Failed),
+    }
+}
+
+// Verify proofs of the same circuit with one translated EvalContext and one
+// multi_miller_loop. The (w_x, w_g) pair of the i-th proof is scaled by r^i for
+// a random r, so that the pairs can not cancel each other. Proofs use the default
+// protocol of hash, see ProofProtocol::default_for.
+pub fn batch_verify_proofs<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    all_instances: &[Vec<Vec<E::Scalar>>],
+    proofs: &[Vec<u8>],
+    hash: TranscriptHash,
+) -> Result<(), VerificationError> {
+    assert_eq!(all_instances.len(), proofs.len());
+
+    let protocol = ProofProtocol::default_for(hash);
+    let (w_x, w_g, _) = verify_aggregation_proofs(
+        params,
+        &[vkey],
+        &vec![],
+        false,
+        &shplonk_proof_indices(&[protocol]),
+    );
+    let c = EvalContext::translate(&[w_x.0, w_g.0]);
+
+    let r = E::Scalar::random(OsRng);
+    let mut r_pow = E::Scalar::one();
+    let mut points = vec![];
+    for (instances, proof) in all_instances.iter().zip(proofs.iter()) {
+        let instance_commitments =
+            instance_to_instance_commitment(params, &[vkey], vec![instances]);
+        let pl = native_eval_finals::<E>(c.clone(), instance_commitments, &[proof.clone()], hash);
+        points.push((pl[0] * r_pow).to_affine());
+        points.push((pl[1] * r_pow).to_affine());
+        r_pow = r_pow * r;
+    }
+
+    let s_g2_prepared = E::G2Prepared::from(params.s_g2);
+    let n_g2_prepared = E::G2Prepared::from(-params.g2);
+    let terms = points
+        .chunks(2)
+        .flat_map(|pair| [(&pair[0], &s_g2_prepared), (&pair[1], &n_g2_prepared)])
+        .collect::<Vec<_>>();
+    let success = bool::from(
+        E::multi_miller_loop(&terms[..])
+            .final_exponentiation()
+            .is_identity(),
+    );
+
+    if success {
+        Ok(())
+    } else {
+        Err(VerificationError::Failed)
+    }
+}
+
+// Proofs are grouped by transcript hash and each group is evaluated in its own context,
+// the pairs of all groups are scaled by powers of a random r and checked in one pairing.
+// Each proof uses the default multi-open protocol of its hash, see ProofProtocol::default_for.
+pub fn verify_proofs_heterogeneous<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vkey: &[&VerifyingKey<E::G1Affine>],
+    instances: Vec<&Vec<Vec<E::Scalar>>>,
+    proofs: Vec<(Vec<u8>, TranscriptHash)>,
+    commitment_check: &Vec<[usize; 4]>,
+) -> Result<(), VerificationError> {
+    assert_eq!(vkey.len(), proofs.len());
+    assert_eq!(instances.len(), proofs.len());
+
+    let mut groups: Vec<(TranscriptHash, Vec<usize>)> = vec![];
+    for (i, (_, hash)) in proofs.iter().enumerate() {
+        match groups.iter_mut().find(|(h, _)| h == hash) {
+            Some((_, indices)) => indices.push(i),
+            None => groups.push((*hash, vec![i])),
+        }
+    }
+
+    let mut pairs = vec![];
+    let mut advice_values = HashMap::new();
+    for (hash, indices) in groups {
+        let group_vkey = indices.iter().map(|i| vkey[*i]).collect::<Vec<_>>();
+        let protocols = vec![ProofProtocol::default_for(hash); indices.len()];
+        let (w_x, w_g, advices) = verify_aggregation_proofs(
+            params,
+            &group_vkey,
+            &vec![],
+            false,
+            &shplonk_proof_indices(&protocols),
+        );
+
+        // advice commitments of this group referenced by commitment_check
+        let checked = commitment_check
+            .iter()
+            .flat_map(|c| [(c[0], c[1]), (c[2], c[3])])
+            .filter_map(|(proof, col)| {
+                indices
+                    .iter()
+                    .position(|i| *i == proof)
+                    .map(|local| (proof, col, local))
+            })
+            .collect::<Vec<_>>();
+
+        let mut targets = vec![w_x.0, w_g.0];
+        for (_, col, local) in checked.iter() {
+            targets.push(advices[*local][*col].0.clone());
+        }
+        let c = EvalContext::translate(&targets[..]);
+
+        let instance_commitments = instance_to_instance_commitment(
+            params,
+            &group_vkey,
+            indices.iter().map(|i| instances[*i]).collect(),
+        );
+        let group_proofs = indices
+            .iter()
+            .map(|i| proofs[*i].0.clone())
+            .collect::<Vec<_>>();
+        let pl = native_eval_finals::<E>(c, instance_commitments, &group_proofs, hash);
+
+        pairs.push((pl[0], pl[1]));
+        for ((proof, col, _), v) in checked.into_iter().zip(pl.into_iter().skip(2)) {
+            advice_values.insert((proof, col), v);
+        }
+    }
+
+    for c in commitment_check {
+        if advice_values[&(c[0], c[1])] != advice_values[&(c[2], c[3])] {
+            return Err(VerificationError::Failed);
+        }
+    }
+
+    let r = E::Scalar::random(OsRng);
+    let mut r_pow = E::Scalar::one();
+    let mut points = vec![];
+    for (w_x, w_g) in pairs {
+        points.push((w_x * r_pow).to_affine());
+        points.push((w_g * r_pow).to_affine());
+        r_pow = r_pow * r;
+    }
+
+    let s_g2_prepared = E::G2Prepared::from(params.s_g2);
+    let n_g2_prepared = E::G2Prepared::from(-params.g2);
+    let terms = points
+        .chunks(2)
+        .flat_map(|pair| [(&pair[0], &s_g2_prepared), (&pair[1], &n_g2_prepared)])
+        .collect::<Vec<_>>();
+    let success = bool::from(
+        E::multi_miller_loop(&terms[..])
+            .final_exponentiation()
+            .is_identity(),
+    );
 
-    for c in pl.chunks(2).skip(1) {
-        assert_eq!(c[0], c[1]);
+    if success {
+        Ok(())
+    } else {
+        Err(VerificationError::Failed)
     }
 }
@@ -18,9 +18,11 @@ use halo2_proofs::transcript::EncodedChallenge;
 use halo2_proofs::transcript::Transcript;
 use halo2_proofs::transcript::TranscriptRead;
 use halo2ecc_s::utils::field_to_bn;
+use num_bigint::BigUint;
 use sha2::Digest;
 use std::collections::BTreeSet;
 use std::io::Read;
+use std::io::Write;
 use std::path::Path;
 
 const INSTANCE_COLUMN_COUNT: usize = 1;
@@ -35,6 +37,10 @@ const SOLIDITY_VERIFY_FIRST_STEP_MAX_SIZE: usize = 99; // first step need to be
 const SOLIDITY_VERIFY_STEP_MAX_SIZE: usize = 135;
 
 const SOLIDITY_DEBUG: bool = false;
+// Spill msm points used by several slices into buf, see enable_memory_point_mode.
+const SOLIDITY_MEMORY_POINT: bool = false;
+// Emit scalar arithmetic as inline yul to skip the bound checks on buf and transcript.
+const SOLIDITY_ASSEMBLY_ARITH: bool = false;
 
 #[derive(Clone)]
 pub enum SolidityVar<E: MultiMillerLoop> {
@@ -45,6 +51,7 @@ pub enum SolidityVar<E: MultiMillerLoop> {
     ConstantScalar(E::Scalar),
     ConstantPoint(E::G1Affine),
     Expression(String, usize, Vec<(usize, usize)>),
+    Memory(usize, usize), // buf index of x, op_pos
 }
 
 impl<E: MultiMillerLoop> SolidityVar<E> {
@@ -88,6 +95,25 @@ impl<E: MultiMillerLoop> SolidityVar<E> {
                 )
             }
             SolidityVar::Expression(s, _, _) => s.to_owned(),
+            SolidityVar::Memory(i, _) => {
+                assert!(!is_scalar);
+                format!("(buf[{}], buf[{}])", i, i + 1)
+            }
+        }
+    }
+
+    // Memory arrays store their length in the first word.
+    pub fn to_yul_string(&self) -> String {
+        match &self {
+            SolidityVar::Transcript(i) => {
+                format!("calldataload(add(transcript.offset, {}))", i * 32)
+            }
+            SolidityVar::Challenge(i) => {
+                format!("mload(add(buf, {}))", (i + CHALLENGE_BUF_START + 1) * 32)
+            }
+            SolidityVar::Temp(i, _) => format!("mload(add(buf, {}))", (i + 1) * 32),
+            SolidityVar::ConstantScalar(i) => field_to_bn(i).to_str_radix(10),
+            _ => unreachable!(),
         }
     }
 }
@@ -108,9 +134,11 @@ struct SolidityEvalContext<R: Read, E: MultiMillerLoop, D: Digest> {
     challenge_idx: usize,
     temp_idx_allocator: (BTreeSet<usize>, usize),
     max_temp_buffer_index: usize,
+    memory_point: bool,
     constant_scalars: Vec<E::Scalar>,
     div_res: Vec<E::Scalar>,
     challenges: Vec<E::Scalar>,
+    yul_q_mod: Option<String>,
 }
 
 impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D> {
@@ -134,12 +162,74 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
             aux_index: 0,
             temp_idx_allocator: (BTreeSet::new(), TEMP_BUF_START),
             max_temp_buffer_index: 0,
+            memory_point: false,
             constant_scalars: vec![],
             div_res: vec![],
             challenges: vec![],
+            yul_q_mod: None,
         }
     }
 
+    // Scalar mul/add/sub/neg are emitted as assembly blocks and never inlined.
+    pub fn enable_assembly_mode(&mut self) {
+        let q_mod = field_to_bn(&-E::Scalar::one()) + 1u64;
+        self.yul_q_mod = Some(q_mod.to_str_radix(10));
+    }
+
+    // A point used by several msm slices is copied into buf on its first use and read from there
+    // afterwards. buf is passed between the steps, so the copy stays valid in later steps.
+    pub fn enable_memory_point_mode(&mut self) {
+        self.memory_point = true;
+    }
+
+    fn push_assembly_arith(
+        &mut self,
+        i: usize,
+        operands: Vec<SolidityVar<E>>,
+        render: impl Fn(&[String], &str) -> String,
+    ) -> SolidityVar<E> {
+        let mut yul_operands = vec![];
+        let mut materialized = vec![];
+        for v in operands.iter() {
+            match v {
+                SolidityVar::Expression(expr, _, _) => {
+                    self.try_release_temp_idx(v);
+                    let t = self.alloc_temp_idx();
+                    self.statements.push(format!("buf[{}] = {};", t, expr));
+                    yul_operands.push(format!("mload(add(buf, {}))", (t + 1) * 32));
+                    materialized.push(t);
+                }
+                _ => yul_operands.push(v.to_yul_string()),
+            }
+        }
+        for v in operands.iter() {
+            if !matches!(v, SolidityVar::Expression(..)) {
+                self.try_release_temp_idx(v);
+            }
+        }
+        for t in materialized {
+            self.temp_idx_allocator.0.insert(t);
+        }
+
+        let t = self.alloc_temp_idx();
+        let expr = render(&yul_operands, self.yul_q_mod.as_ref().unwrap());
+        self.statements.push(format!(
+            "assembly {{ mstore(add(buf, {}), {}) }}",
+            (t + 1) * 32,
+            expr
+        ));
+
+        if SOLIDITY_DEBUG {
+            self.statements.push(format!(
+                "require(buf[{}] == {}, \"ops {}\");",
+                t,
+                field_to_bn(self.values[i].1.as_ref().unwrap()).to_str_radix(10),
+                i
+            ));
+        }
+        SolidityVar::Temp(t, i)
+    }
+
     fn tag_lifetime(&mut self, to: &EvalPos, curr: usize) {
         match to {
             EvalPos::Ops(i) => {
@@ -158,6 +248,13 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                     self.temp_idx_allocator.0.insert(*t);
                 }
             }
+            SolidityVar::Memory(t, i) => {
+                self.deps[*i] -= 1;
+                if self.deps[*i] == 0 {
+                    self.temp_idx_allocator.0.insert(*t);
+                    self.temp_idx_allocator.0.insert(*t + 1);
+                }
+            }
             SolidityVar::Expression(_, _, dep) => {
                 for (t, i) in dep {
                     self.deps[*i] -= 1;
@@ -182,6 +279,51 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
         }
     }
 
+    // Statements generated by code_gen.
+    pub fn statement_count(&self) -> usize {
+        self.statements.len()
+    }
+
+    // Minimal length of buf in the solidity template, temps are allocated after the reserved slots.
+    pub fn temp_buffer_size_after_code_gen(&self) -> usize {
+        usize::max(self.max_temp_buffer_index, TEMP_BUF_START)
+    }
+
+    pub fn step_count(&self, step_size: usize) -> usize {
+        (self.statements.len() + step_size - 1) / step_size
+    }
+
+    // Two adjacent slots taken from the top, freed slots are not guaranteed to be adjacent.
+    fn alloc_point_idx(&mut self) -> usize {
+        self.temp_idx_allocator.1 += 2;
+        if self.temp_idx_allocator.1 > self.max_temp_buffer_index {
+            self.max_temp_buffer_index = self.temp_idx_allocator.1;
+        }
+        self.temp_idx_allocator.1 - 2
+    }
+
+    // Returns the point of pos, spilled into buf if it is used by later ops.
+    fn pos_to_spilled_point_var(&mut self, pos: &EvalPos) -> SolidityVar<E> {
+        let p = self.pos_to_point_var(pos);
+        match (pos, &p) {
+            (EvalPos::Ops(i), SolidityVar::Transcript(_))
+                if self.memory_point && self.deps[*i] > 1 =>
+            {
+                let t = self.alloc_point_idx();
+                self.statements.push(format!(
+                    "(buf[{}], buf[{}]) = {};",
+                    t,
+                    t + 1,
+                    p.to_string(false)
+                ));
+                let p = SolidityVar::Memory(t, *i);
+                self.exprs[*i] = Some(p.clone());
+                p
+            }
+            _ => p,
+        }
+    }
+
     fn pos_is_constant_zero(&self, p: &EvalPos) -> bool {
         match p {
             EvalPos::Constant(i) => {
@@ -196,7 +338,9 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
         match p {
             EvalPos::Constant(i) => {
                 let s = self.c.const_scalars[*i];
-                self.constant_scalars.push(s);
+                if !self.constant_scalars.contains(&s) {
+                    self.constant_scalars.push(s);
+                }
                 SolidityVar::ConstantScalar(s)
             }
             EvalPos::Ops(i) => self.exprs[*i].clone().unwrap(),
@@ -238,6 +382,11 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
     }
 
     pub fn value_gen(&mut self) {
+        self.value_gen_with(&mut |_| {})
+    }
+
+    // on_div is called with each division result (aux) once it is computed
+    pub fn value_gen_with(&mut self, on_div: &mut impl FnMut(&E::Scalar)) {
         for (_, op) in self.c.ops.iter().enumerate() {
             self.values.push(match op {
                 EvalOps::TranscriptReadScalar(_, _) => (None, Some(self.t.read_scalar().unwrap())),
@@ -265,12 +414,20 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                     None,
                     Some(self.eval_scalar_pos(a) - self.eval_scalar_pos(b)),
                 ),
+                EvalOps::ScalarNeg(a) => (None, Some(-self.eval_scalar_pos(a))),
                 EvalOps::ScalarMul(a, b, _) => (
                     None,
                     Some(self.eval_scalar_pos(a) * self.eval_scalar_pos(b)),
                 ),
                 EvalOps::ScalarDiv(a, b) => {
                     let t = self.eval_scalar_pos(a) * self.eval_scalar_pos(b).invert().unwrap();
+                    on_div(&t);
+                    self.div_res.push(t);
+                    (None, Some(t))
+                }
+                EvalOps::ScalarInv(a) => {
+                    let t = self.eval_scalar_pos(a).invert().unwrap();
+                    on_div(&t);
                     self.div_res.push(t);
                     (None, Some(t))
                 }
@@ -321,10 +478,16 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                     self.tag_lifetime(a, i);
                     self.tag_lifetime(b, i);
                 }
+                EvalOps::ScalarNeg(a) => {
+                    self.tag_lifetime(a, i);
+                }
                 EvalOps::ScalarDiv(a, b) => {
                     self.tag_lifetime(a, i);
                     self.tag_lifetime(b, i);
                 }
+                EvalOps::ScalarInv(a) => {
+                    self.tag_lifetime(a, i);
+                }
                 EvalOps::ScalarPow(a, _) => {
                     self.tag_lifetime(a, i);
                 }
@@ -359,6 +522,40 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                     self.challenge_idx += 1;
                     Some(SolidityVar::Challenge(self.challenge_idx - 1))
                 }
+                EvalOps::ScalarMul(a, b, _) if self.yul_q_mod.is_some() => {
+                    let a = self.pos_to_scalar_var(a);
+                    let b = self.pos_to_scalar_var(b);
+                    Some(self.push_assembly_arith(i, vec![a, b], |x, q| {
+                        format!("mulmod({}, {}, {})", x[0], x[1], q)
+                    }))
+                }
+                EvalOps::ScalarAdd(a, b) if self.yul_q_mod.is_some() => {
+                    let a = self.pos_to_scalar_var(a);
+                    let b = self.pos_to_scalar_var(b);
+                    Some(self.push_assembly_arith(i, vec![a, b], |x, q| {
+                        format!("addmod({}, {}, {})", x[0], x[1], q)
+                    }))
+                }
+                EvalOps::ScalarSub(a, b) if self.yul_q_mod.is_some() => {
+                    let b = self.pos_to_scalar_var(b);
+                    if self.pos_is_constant_zero(a) {
+                        // q - 0 is q, reduce it so that neg of zero gives zero
+                        Some(self.push_assembly_arith(i, vec![b], |x, q| {
+                            format!("mod(sub({}, {}), {})", q, x[0], q)
+                        }))
+                    } else {
+                        let a = self.pos_to_scalar_var(a);
+                        Some(self.push_assembly_arith(i, vec![a, b], |x, q| {
+                            format!("addmod({}, sub({}, {}), {})", x[0], q, x[1], q)
+                        }))
+                    }
+                }
+                EvalOps::ScalarNeg(a) if self.yul_q_mod.is_some() => {
+                    let a = self.pos_to_scalar_var(a);
+                    Some(self.push_assembly_arith(i, vec![a], |x, q| {
+                        format!("mod(sub({}, {}), {})", q, x[0], q)
+                    }))
+                }
                 EvalOps::ScalarMul(a, b, _) => {
                     let a = self.pos_to_scalar_var(a);
                     let b = self.pos_to_scalar_var(b);
@@ -462,6 +659,28 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                         Some(SolidityVar::Temp(t, i))
                     }
                 }
+                EvalOps::ScalarNeg(a) => {
+                    let a = self.pos_to_scalar_var(a);
+                    let expr = format!("AggregatorLib.q_mod - {}", a.to_string(true));
+
+                    if self.deps[i] == 1 && a.get_deep() + 1 < DEEP_LIMIT {
+                        Some(SolidityVar::Expression(expr, a.get_deep() + 1, a.get_dep()))
+                    } else {
+                        self.try_release_temp_idx(&a);
+                        let t = self.alloc_temp_idx();
+                        self.statements.push(format!("buf[{}] = {};", t, expr));
+
+                        if SOLIDITY_DEBUG {
+                            self.statements.push(format!(
+                                "require(buf[{}] == {}, \"ops {}\");",
+                                t,
+                                field_to_bn(self.values[i].1.as_ref().unwrap()).to_str_radix(10),
+                                i
+                            ));
+                        }
+                        Some(SolidityVar::Temp(t, i))
+                    }
+                }
                 EvalOps::ScalarDiv(a, b) => {
                     let aux_index = self.aux_index;
                     self.aux_index += 1;
@@ -499,13 +718,54 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                         Some(SolidityVar::Temp(t, i))
                     }
                 }
+                EvalOps::ScalarInv(a) => {
+                    let aux_index = self.aux_index;
+                    self.aux_index += 1;
+
+                    let a = self.pos_to_scalar_var(a);
+                    let expr = format!(
+                        "AggregatorLib.fr_div(1, {}, aux[{}])",
+                        a.to_string(true),
+                        aux_index
+                    );
+                    if self.deps[i] == 1 && a.get_deep() + 1 < DEEP_LIMIT {
+                        Some(SolidityVar::Expression(expr, a.get_deep() + 1, a.get_dep()))
+                    } else {
+                        self.try_release_temp_idx(&a);
+                        let t = self.alloc_temp_idx();
+                        self.statements.push(format!("buf[{}] = {};", t, expr));
+
+                        if SOLIDITY_DEBUG {
+                            self.statements.push(format!(
+                                "require(buf[{}] == {}, \"ops {}\");",
+                                t,
+                                field_to_bn(self.values[i].1.as_ref().unwrap()).to_str_radix(10),
+                                i
+                            ));
+                        }
+                        Some(SolidityVar::Temp(t, i))
+                    }
+                }
                 EvalOps::ScalarPow(a, n) => {
                     let a = self.pos_to_scalar_var(a);
                     self.try_release_temp_idx(&a);
+                    let is_expression = matches!(a, SolidityVar::Expression(..));
                     let a = a.to_string(true);
                     let t = self.alloc_temp_idx();
-                    self.statements
-                        .push(format!("buf[{}] = AggregatorLib.fr_pow({}, {});", t, a, n));
+                    if *n >= 2 && n.is_power_of_two() {
+                        // evaluate an expression once before squaring it
+                        let a = if is_expression {
+                            self.statements.push(format!("buf[{}] = {};", t, a));
+                            format!("buf[{}]", t)
+                        } else {
+                            a
+                        };
+                        self.statements
+                            .extend(square_chain_statements(t, &a, n.trailing_zeros()));
+                    } else {
+                        self.statements
+                            .push(format!("buf[{}] = AggregatorLib.fr_pow({}, {});", t, a, n));
+                    }
 
                     if SOLIDITY_DEBUG {
                         self.statements.push(format!(
@@ -518,9 +778,10 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
                     Some(SolidityVar::Temp(t, i))
                 }
                 EvalOps::MSMSlice((p, s), last, group) => {
-                    let p = self.pos_to_point_var(p);
+                    let p = self.pos_to_spilled_point_var(p);
                     let s = self.pos_to_scalar_var(s);
                     self.try_release_temp_idx(&s);
+                    self.try_release_temp_idx(&p);
                     let start: usize = MSM_BUF_START + group * 2;
                     let p_str = p.to_string(false);
                     let s_str = s.to_string(true);
@@ -618,6 +879,34 @@ impl<R: Read, E: MultiMillerLoop, D: Digest + Clone> SolidityEvalContext<R, E, D
     }
 }
 
+// buf[t] = a^(2^log_n) by log_n sequential squarings, cheaper than fr_pow.
+fn square_chain_statements(t: usize, a: &str, log_n: u32) -> Vec<String> {
+    (0..log_n)
+        .map(|i| {
+            let x = if i == 0 {
+                a.to_owned()
+            } else {
+                format!("buf[{}]", t)
+            };
+            format!("buf[{}] = mulmod({}, {}, AggregatorLib.q_mod);", t, x, x)
+        })
+        .collect()
+}
+
+// Statistics of the generated verifier for tuning the step splitting
+// and parameterizing the buffers of the solidity template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolidityCodegenStats {
+    pub total_statements: usize,
+    pub step_count: usize,
+    // minimal length of buf, including the reserved slots before the temps
+    pub max_temp_index: usize,
+    pub aux_count: usize,
+    // in words, a point takes two
+    pub transcript_reads: usize,
+    pub challenges: usize,
+}
+
 pub fn solidity_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
     params: &ParamsVerifier<E>,
     vkey: &VerifyingKey<E::G1Affine>,
@@ -625,22 +914,59 @@ pub fn solidity_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
     proofs: Vec<u8>,
     tera_context: &mut tera::Context,
     check: bool,
-) -> Vec<String> {
-    let (w_x, w_g, _) = verify_aggregation_proofs(params, &[vkey], &vec![], true, &vec![]);
+) -> (Vec<String>, SolidityCodegenStats) {
+    let c = solidity_eval_context(params, vkey);
+    solidity_codegen_from_eval_context::<E, D>(
+        &c,
+        params,
+        vkey,
+        instances,
+        proofs,
+        tera_context,
+        check,
+    )
+}
 
-    let instance_commitments =
-        instance_to_instance_commitment(params, &[vkey], vec![&vec![instances.clone()]])[0].clone();
+// The translated context of vkey, which only depends on the circuit shape and can be
+// shared by solidity_codegen_from_eval_context for proofs of the same vkey.
+pub fn solidity_eval_context<E: MultiMillerLoop>(
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+) -> EvalContext<E::G1Affine> {
+    let (w_x, w_g, _) = verify_aggregation_proofs(params, &[vkey], &vec![], true, &vec![]);
 
     let targets = vec![w_x.0, w_g.0];
 
-    let c = EvalContext::translate(&targets[..]);
+    EvalContext::translate(&targets[..])
+}
+
+// Same as solidity_codegen_with_proof, but skips translation by reusing ctx_template,
+// see solidity_eval_context.
+pub fn solidity_codegen_from_eval_context<E: MultiMillerLoop, D: Digest + Clone>(
+    ctx_template: &EvalContext<E::G1Affine>,
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &Vec<E::Scalar>,
+    proofs: Vec<u8>,
+    tera_context: &mut tera::Context,
+    check: bool,
+) -> (Vec<String>, SolidityCodegenStats) {
+    let instance_commitments =
+        instance_to_instance_commitment(params, &[vkey], vec![&vec![instances.clone()]])[0].clone();
 
     let mut ctx = SolidityEvalContext::<_, E, D>::new(
-        c,
+        ctx_template.clone(),
         instance_commitments,
         ShaRead::<_, _, _, D>::init(&proofs[..]),
     );
 
+    if SOLIDITY_ASSEMBLY_ARITH {
+        ctx.enable_assembly_mode();
+    }
+    if SOLIDITY_MEMORY_POINT {
+        ctx.enable_memory_point_mode();
+    }
+
     ctx.value_gen();
     ctx.code_gen();
 
@@ -682,11 +1008,15 @@ pub fn solidity_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
         );
     }
 
-    let mut res = vec![ctx.statements[..SOLIDITY_VERIFY_FIRST_STEP_MAX_SIZE]
-        .iter()
-        .map(|x| format!("{}\n", x))
-        .collect::<Vec<_>>()
-        .concat()];
+    // the shorter first step adds at most one step
+    let mut res = Vec::with_capacity(ctx.step_count(SOLIDITY_VERIFY_STEP_MAX_SIZE) + 1);
+    res.push(
+        ctx.statements[..SOLIDITY_VERIFY_FIRST_STEP_MAX_SIZE]
+            .iter()
+            .map(|x| format!("{}\n", x))
+            .collect::<Vec<_>>()
+            .concat(),
+    );
 
     res.append(
         &mut ctx.statements[SOLIDITY_VERIFY_FIRST_STEP_MAX_SIZE..]
@@ -699,7 +1029,74 @@ pub fn solidity_codegen_with_proof<E: MultiMillerLoop, D: Digest + Clone>(
             })
             .collect(),
     );
-    res
+
+    let stats = SolidityCodegenStats {
+        total_statements: ctx.statement_count(),
+        step_count: res.len(),
+        max_temp_index: ctx.temp_buffer_size_after_code_gen(),
+        aux_count: ctx.aux_index,
+        transcript_reads: ctx.transcript_idx,
+        challenges: ctx.challenge_idx,
+    };
+    (res, stats)
+}
+
+// Rough gas costs of the operations emitted by the code generator.
+// Precompile costs follow EIP-1108 and EIP-2565, plus the staticcall overhead.
+const GAS_STATICCALL: u64 = 700;
+const GAS_ECC_MUL: u64 = 6000 + GAS_STATICCALL;
+const GAS_ECC_MUL_ADD: u64 = GAS_ECC_MUL + 150 + GAS_STATICCALL;
+const GAS_MULMOD: u64 = 8;
+const GAS_ADDMOD: u64 = 8;
+const GAS_FR_DIV: u64 = 2 * GAS_MULMOD + 20;
+const GAS_FR_POW: u64 = 200 + GAS_STATICCALL;
+// memory load/store and stack ops of each statement
+const GAS_STATEMENT: u64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SolidityGasEstimate {
+    pub step_gas: Vec<u64>,
+    pub total_gas: u64,
+}
+
+fn solidity_statement_gas(statement: &str) -> u64 {
+    let count = |pattern: &str| statement.matches(pattern).count() as u64;
+
+    GAS_STATEMENT
+        + count("AggregatorLib.ecc_mul(") * GAS_ECC_MUL
+        + count("AggregatorLib.ecc_mul_add(") * GAS_ECC_MUL_ADD
+        + count("mulmod(") * GAS_MULMOD
+        + count("addmod(") * GAS_ADDMOD
+        + count("AggregatorLib.fr_div(") * GAS_FR_DIV
+        + count("AggregatorLib.fr_pow(") * GAS_FR_POW
+}
+
+// Estimate gas of each verifier step to check whether steps fit in the block gas limit.
+pub fn solidity_gas_estimate<E: MultiMillerLoop, D: Digest + Clone>(
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &[E::Scalar],
+    proofs: &[u8],
+) -> SolidityGasEstimate {
+    let (steps, _) = solidity_codegen_with_proof::<_, D>(
+        params,
+        vkey,
+        &instances.to_vec(),
+        proofs.to_vec(),
+        &mut tera::Context::new(),
+        false,
+    );
+
+    let step_gas = steps
+        .iter()
+        .map(|step| step.lines().map(solidity_statement_gas).sum())
+        .collect::<Vec<u64>>();
+    let total_gas = step_gas.iter().sum();
+
+    SolidityGasEstimate {
+        step_gas,
+        total_gas,
+    }
 }
 
 pub fn solidity_aux_gen<E: MultiMillerLoop, D: Digest + Clone>(
@@ -714,6 +1111,47 @@ pub fn solidity_aux_gen<E: MultiMillerLoop, D: Digest + Clone>(
     div_res.iter().for_each(|res| res.write(&mut fd).unwrap());
 }
 
+// Read the proof from a stream and write aux to aux_writer as soon as each one is computed.
+pub fn solidity_aux_gen_streaming<E: MultiMillerLoop, D: Digest + Clone>(
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &[E::Scalar],
+    proof_reader: impl Read,
+    mut aux_writer: impl Write,
+) {
+    let (w_x, w_g, _) = verify_aggregation_proofs(params, &[vkey], &vec![], true, &vec![]);
+
+    let instance_commitments =
+        instance_to_instance_commitment(params, &[vkey], vec![&vec![instances.to_vec()]])[0]
+            .clone();
+
+    let targets = vec![w_x.0, w_g.0];
+
+    let c = EvalContext::translate(&targets[..]);
+
+    let mut ctx = SolidityEvalContext::<_, E, D>::new(
+        c,
+        instance_commitments,
+        ShaRead::<_, _, _, D>::init(proof_reader),
+    );
+
+    ctx.value_gen_with(&mut |res| res.write(&mut aux_writer).unwrap());
+    aux_writer.flush().unwrap();
+
+    let s_g2_prepared = E::G2Prepared::from(params.s_g2);
+    let n_g2_prepared = E::G2Prepared::from(-params.g2);
+    let success = bool::from(
+        E::multi_miller_loop(&[
+            (&ctx.finals[0], &s_g2_prepared),
+            (&ctx.finals[1], &n_g2_prepared),
+        ])
+        .final_exponentiation()
+        .is_identity(),
+    );
+
+    assert!(success);
+}
+
 pub fn solidity_aux_gen_data<E: MultiMillerLoop, D: Digest + Clone>(
     params: &ParamsVerifier<E>,
     vkey: &VerifyingKey<E::G1Affine>,
@@ -754,3 +1192,283 @@ pub fn solidity_aux_gen_data<E: MultiMillerLoop, D: Digest + Clone>(
     }
     ctx.div_res
 }
+
+// Generate a foundry test which verifies the proof with the rendered verifier contracts.
+// The proof, aux and instances are embedded into the test, so it runs with `forge test` directly.
+pub fn solidity_test_gen<E: MultiMillerLoop, D: Digest + Clone>(
+    params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &Vec<E::Scalar>,
+    shadow_instances: &Vec<E::Scalar>,
+    target_instances: &Vec<Vec<E::Scalar>>,
+    proofs: Vec<u8>,
+    step_out_file_name: impl Fn(usize) -> String,
+    test_file: &Path,
+) {
+    let n_steps = solidity_codegen_with_proof::<_, D>(
+        params,
+        vkey,
+        instances,
+        proofs.clone(),
+        &mut tera::Context::new(),
+        false,
+    )
+    .1
+    .step_count;
+    let aux = solidity_aux_gen_data::<_, D>(params, vkey, instances, proofs.clone(), true);
+
+    let to_hex = |x: &BigUint| format!("0x{}", x.to_str_radix(16));
+    let scalars_to_hex = |v: &Vec<E::Scalar>| {
+        v.iter()
+            .map(|x| to_hex(&field_to_bn(x)))
+            .collect::<Vec<_>>()
+    };
+    let fill_array = |name: &str, values: Vec<String>| {
+        let mut lines = vec![format!(
+            "        uint256[] memory {} = new uint256[]({});",
+            name,
+            values.len()
+        )];
+        for (i, v) in values.iter().enumerate() {
+            lines.push(format!("        {}[{}] = {};", name, i, v));
+        }
+        lines.join("\n")
+    };
+
+    let proof_values = proofs
+        .chunks(32)
+        .map(|x| to_hex(&BigUint::from_bytes_le(x)))
+        .collect::<Vec<_>>();
+
+    let mut body = vec![];
+
+    body.push(format!(
+        "        AggregatorVerifierCoreStep[] memory steps = new AggregatorVerifierCoreStep[]({});",
+        n_steps
+    ));
+    for i in 0..n_steps {
+        body.push(format!(
+            "        steps[{}] = AggregatorVerifierCoreStep(deploy(\"{}:AggregatorVerifierCoreStep{}\"));",
+            i,
+            step_out_file_name(i),
+            i + 1
+        ));
+    }
+    body.push("        AggregatorVerifier verifier = new AggregatorVerifier(steps);".to_owned());
+
+    body.push(fill_array("proof", proof_values));
+    body.push(fill_array(
+        "verify_instance",
+        scalars_to_hex(shadow_instances),
+    ));
+    body.push(fill_array("aux", scalars_to_hex(&aux)));
+    body.push(format!(
+        "        uint256[][] memory target_instance = new uint256[][]({});",
+        target_instances.len()
+    ));
+    for (i, target_instance) in target_instances.iter().enumerate() {
+        let name = format!("target_instance{}", i);
+        body.push(fill_array(&name, scalars_to_hex(target_instance)));
+        body.push(format!("        target_instance[{}] = {};", i, name));
+    }
+
+    body.push("        verifier.verify(proof, verify_instance, aux, target_instance);".to_owned());
+
+    let code = format!(
+        r#"// SPDX-License-Identifier: MIT
+pragma solidity ^0.8.13;
+
+import "forge-std/Test.sol";
+import "../contracts/AggregatorVerifier.sol";
+
+contract AggregatorVerifierTest is Test {{
+    function deploy(string memory artifact) internal returns (address addr) {{
+        bytes memory code = vm.getCode(artifact);
+        assembly {{
+            addr := create(0, add(code, 0x20), mload(code))
+        }}
+        require(addr != address(0), "deploy failed");
+    }}
+
+    function test_verify() public {{
+{}
+    }}
+}}
+"#,
+        body.join("\n")
+    );
+
+    std::fs::write(test_file, code).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::square_chain_statements;
+    use super::SolidityEvalContext;
+    use super::MSM_BUF_START;
+    use super::TEMP_BUF_START;
+    use crate::api::ast_eval::EvalContext;
+    use crate::api::ast_eval::EvalOps;
+    use crate::api::ast_eval::EvalPos;
+    use crate::transcript::sha256::ShaRead;
+    use halo2_proofs::arithmetic::Field;
+    use halo2_proofs::pairing::bn256::Bn256;
+    use halo2_proofs::pairing::bn256::Fr;
+
+    #[test]
+    fn test_constant_scalar_dedup() {
+        let mut c = EvalContext::default();
+        c.const_scalars = vec![Fr::one(), Fr::from(2u64)];
+        let empty: Vec<u8> = vec![];
+        let mut ctx = SolidityEvalContext::<_, Bn256, sha3::Keccak256>::new(
+            c,
+            vec![],
+            ShaRead::init(&empty[..]),
+        );
+
+        for i in [0, 1, 0, 1, 1] {
+            ctx.pos_to_scalar_var(&EvalPos::Constant(i));
+        }
+        assert_eq!(ctx.constant_scalars, vec![Fr::one(), Fr::from(2u64)]);
+    }
+
+    #[test]
+    fn test_assembly_mode() {
+        let mut c = EvalContext::default();
+        c.const_scalars = vec![Fr::one(), Fr::from(2u64), Fr::zero()];
+        c.ops = vec![
+            EvalOps::ScalarMul(EvalPos::Constant(0), EvalPos::Constant(1), false),
+            EvalOps::ScalarAdd(EvalPos::Ops(0), EvalPos::Constant(1)),
+            EvalOps::ScalarSub(EvalPos::Constant(2), EvalPos::Ops(1)),
+            EvalOps::ScalarNeg(EvalPos::Ops(2)),
+            EvalOps::ScalarMul(EvalPos::Ops(3), EvalPos::Ops(0), false),
+        ];
+        let empty: Vec<u8> = vec![];
+        let mut ctx = SolidityEvalContext::<_, Bn256, sha3::Keccak256>::new(
+            c,
+            vec![],
+            ShaRead::init(&empty[..]),
+        );
+        ctx.enable_assembly_mode();
+        ctx.code_gen();
+
+        let q = ctx.yul_q_mod.clone().unwrap();
+        assert_eq!(ctx.statements.len(), 5);
+        assert_eq!(
+            ctx.statements[0],
+            format!(
+                "assembly {{ mstore(add(buf, {}), mulmod(1, 2, {})) }}",
+                (TEMP_BUF_START + 1) * 32,
+                q
+            )
+        );
+        for statement in &ctx.statements {
+            assert!(statement.starts_with("assembly { mstore(add(buf, "));
+            assert!(!statement.contains("AggregatorLib"));
+        }
+        // Negation is reduced, otherwise neg of zero would leave q in the buffer.
+        for statement in &ctx.statements[2..4] {
+            assert!(statement.contains(&format!("mod(sub({}, mload(add(buf, ", q)));
+            assert!(statement.ends_with(&format!(")), {})) }}", q)));
+        }
+    }
+
+    #[test]
+    fn test_memory_point_mode() {
+        let mut c = EvalContext::default();
+        c.const_scalars = vec![Fr::from(2u64)];
+        c.ops = vec![
+            EvalOps::TranscriptReadPoint(0, EvalPos::Empty),
+            EvalOps::TranscriptReadScalar(0, EvalPos::Empty),
+            EvalOps::MSMSlice((EvalPos::Ops(0), EvalPos::Ops(1)), None, 0),
+            EvalOps::MSMSlice(
+                (EvalPos::Ops(0), EvalPos::Constant(0)),
+                Some(EvalPos::Ops(2)),
+                0,
+            ),
+            EvalOps::TranscriptReadPoint(0, EvalPos::Empty),
+            EvalOps::MSMSlice((EvalPos::Ops(4), EvalPos::Ops(1)), Some(EvalPos::Ops(3)), 0),
+        ];
+        let empty: Vec<u8> = vec![];
+        let mut ctx = SolidityEvalContext::<_, Bn256, sha3::Keccak256>::new(
+            c.clone(),
+            vec![],
+            ShaRead::init(&empty[..]),
+        );
+        ctx.enable_memory_point_mode();
+        ctx.code_gen();
+
+        // the point read first is used twice and spilled, the other one is read directly
+        let t = TEMP_BUF_START;
+        assert_eq!(
+            ctx.statements,
+            vec![
+                format!(
+                    "(buf[{}], buf[{}]) = (transcript[0], transcript[1]);",
+                    t,
+                    t + 1
+                ),
+                format!(
+                    "(buf[{}], buf[{}]) = (buf[{}], buf[{}]);",
+                    MSM_BUF_START,
+                    MSM_BUF_START + 1,
+                    t,
+                    t + 1
+                ),
+                format!("buf[{}] = transcript[2];", MSM_BUF_START + 2),
+                format!("AggregatorLib.ecc_mul(buf, {});", MSM_BUF_START),
+                format!(
+                    "(buf[{}], buf[{}]) = (buf[{}], buf[{}]);",
+                    MSM_BUF_START + 2,
+                    MSM_BUF_START + 3,
+                    t,
+                    t + 1
+                ),
+                format!("buf[{}] = 2;", MSM_BUF_START + 4),
+                format!("AggregatorLib.ecc_mul_add(buf, {});", MSM_BUF_START),
+                format!(
+                    "(buf[{}], buf[{}]) = (transcript[3], transcript[4]);",
+                    MSM_BUF_START + 2,
+                    MSM_BUF_START + 3
+                ),
+                format!("buf[{}] = transcript[2];", MSM_BUF_START + 4),
+                format!("AggregatorLib.ecc_mul_add(buf, {});", MSM_BUF_START),
+            ]
+        );
+        // the slots are released after the last use
+        assert_eq!(ctx.max_temp_buffer_index, t + 2);
+        assert!(ctx.temp_idx_allocator.0.contains(&t));
+        assert!(ctx.temp_idx_allocator.0.contains(&(t + 1)));
+
+        // without the mode, the points are read from the transcript at each use
+        let mut ctx = SolidityEvalContext::<_, Bn256, sha3::Keccak256>::new(
+            c,
+            vec![],
+            ShaRead::init(&empty[..]),
+        );
+        ctx.code_gen();
+        assert_eq!(ctx.statements.len(), 9);
+        assert!(ctx
+            .statements
+            .iter()
+            .all(|x| !x.contains(&format!("buf[{}]", t))));
+    }
+
+    #[test]
+    fn test_square_chain_statements() {
+        for n in [4u32, 8, 16] {
+            let statements = square_chain_statements(20, "transcript[3]", n.trailing_zeros());
+            assert_eq!(statements.len() as u32, n.trailing_zeros());
+            assert_eq!(
+                statements[0],
+                "buf[20] = mulmod(transcript[3], transcript[3], AggregatorLib.q_mod);"
+            );
+            for statement in &statements[1..] {
+                assert_eq!(
+                    statement,
+                    "buf[20] = mulmod(buf[20], buf[20], AggregatorLib.q_mod);"
+                );
+            }
+        }
+    }
+}
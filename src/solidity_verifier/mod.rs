@@ -8,11 +8,22 @@ use halo2_proofs::poly::commitment::ParamsVerifier;
 use halo2ecc_s::utils::field_to_bn;
 use num_bigint::BigUint;
 use sha2::Digest;
+use std::collections::HashMap;
 use std::path::Path;
 use tera::Tera;
 
 pub mod codegen;
 
+// The EVM can only hash the transcript with sha256 or keccak,
+// entries check it before loading templates or reading the proof.
+fn solidity_hasher_name(hasher: TranscriptHash) -> &'static str {
+    match hasher {
+        TranscriptHash::Sha => "sha256",
+        TranscriptHash::Keccak => "keccak",
+        _ => panic!("solidity verifier does not support {:?} transcript", hasher),
+    }
+}
+
 pub fn solidity_render<E: MultiMillerLoop, D: Digest + Clone>(
     path_in: &str,
     path_out: &str,
@@ -26,8 +37,11 @@ pub fn solidity_render<E: MultiMillerLoop, D: Digest + Clone>(
     instances: &Vec<E::Scalar>,
     proofs: Vec<u8>,
 ) {
-    solidity_render_with_check_option::<_, D>(
-        path_in,
+    solidity_hasher_name(hasher);
+
+    let tera = Tera::new(path_in).unwrap();
+    solidity_render_with_tera::<_, D>(
+        &tera,
         path_out,
         common_template_name,
         start_step_template_name,
@@ -38,8 +52,40 @@ pub fn solidity_render<E: MultiMillerLoop, D: Digest + Clone>(
         vkey,
         instances,
         proofs,
+    );
+}
+
+// Same as solidity_render but uses a prepared Tera, e.g. with templates embedded by include_str!.
+pub fn solidity_render_with_tera<E: MultiMillerLoop, D: Digest + Clone>(
+    tera: &Tera,
+    path_out: &str,
+    common_template_name: Vec<(String, String)>,
+    start_step_template_name: &str,
+    end_step_template_name: &str,
+    step_out_file_name: impl Fn(usize) -> String,
+    hasher: TranscriptHash,
+    verify_circuit_params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &Vec<E::Scalar>,
+    proofs: Vec<u8>,
+) {
+    let outputs = solidity_render_to_strings_with_tera::<_, D>(
+        tera,
+        common_template_name,
+        start_step_template_name,
+        end_step_template_name,
+        step_out_file_name,
+        hasher,
+        verify_circuit_params,
+        vkey,
+        instances,
+        proofs,
         true,
     );
+
+    for (f_out, content) in outputs {
+        std::fs::write(Path::new(path_out).join(f_out), content).unwrap();
+    }
 }
 
 pub fn solidity_render_with_check_option<E: MultiMillerLoop, D: Digest + Clone>(
@@ -56,14 +102,100 @@ pub fn solidity_render_with_check_option<E: MultiMillerLoop, D: Digest + Clone>(
     proofs: Vec<u8>,
     check: bool,
 ) {
+    let outputs = solidity_render_to_strings_with_check_option::<_, D>(
+        path_in,
+        common_template_name,
+        start_step_template_name,
+        end_step_template_name,
+        step_out_file_name,
+        hasher,
+        verify_circuit_params,
+        vkey,
+        instances,
+        proofs,
+        check,
+    );
+
+    for (f_out, content) in outputs {
+        std::fs::write(Path::new(path_out).join(f_out), content).unwrap();
+    }
+}
+
+pub fn solidity_render_to_strings<E: MultiMillerLoop, D: Digest + Clone>(
+    path_in: &str,
+    common_template_name: Vec<(String, String)>,
+    start_step_template_name: &str,
+    end_step_template_name: &str,
+    step_out_file_name: impl Fn(usize) -> String,
+    hasher: TranscriptHash,
+    verify_circuit_params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &Vec<E::Scalar>,
+    proofs: Vec<u8>,
+) -> HashMap<String, String> {
+    solidity_render_to_strings_with_check_option::<_, D>(
+        path_in,
+        common_template_name,
+        start_step_template_name,
+        end_step_template_name,
+        step_out_file_name,
+        hasher,
+        verify_circuit_params,
+        vkey,
+        instances,
+        proofs,
+        true,
+    )
+}
+
+// Render the verifier contracts in memory, returns a map from output file name to content.
+pub fn solidity_render_to_strings_with_check_option<E: MultiMillerLoop, D: Digest + Clone>(
+    path_in: &str,
+    common_template_name: Vec<(String, String)>,
+    start_step_template_name: &str,
+    end_step_template_name: &str,
+    step_out_file_name: impl Fn(usize) -> String,
+    hasher: TranscriptHash,
+    verify_circuit_params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &Vec<E::Scalar>,
+    proofs: Vec<u8>,
+    check: bool,
+) -> HashMap<String, String> {
+    solidity_hasher_name(hasher);
+
     let tera = Tera::new(path_in).unwrap();
+    solidity_render_to_strings_with_tera::<_, D>(
+        &tera,
+        common_template_name,
+        start_step_template_name,
+        end_step_template_name,
+        step_out_file_name,
+        hasher,
+        verify_circuit_params,
+        vkey,
+        instances,
+        proofs,
+        check,
+    )
+}
+
+pub fn solidity_render_to_strings_with_tera<E: MultiMillerLoop, D: Digest + Clone>(
+    tera: &Tera,
+    common_template_name: Vec<(String, String)>,
+    start_step_template_name: &str,
+    end_step_template_name: &str,
+    step_out_file_name: impl Fn(usize) -> String,
+    hasher: TranscriptHash,
+    verify_circuit_params: &ParamsVerifier<E>,
+    vkey: &VerifyingKey<E::G1Affine>,
+    instances: &Vec<E::Scalar>,
+    proofs: Vec<u8>,
+    check: bool,
+) -> HashMap<String, String> {
     let mut tera_ctx = tera::Context::new();
 
-    match hasher {
-        TranscriptHash::Sha => tera_ctx.insert("hasher", "sha256"),
-        TranscriptHash::Keccak => tera_ctx.insert("hasher", "keccak"),
-        _ => unreachable!(),
-    }
+    tera_ctx.insert("hasher", solidity_hasher_name(hasher));
 
     let g2field_to_bn = |f: &<E::G2Affine as CurveAffine>::Base| {
         let mut bytes: Vec<u8> = Vec::new();
@@ -154,7 +286,7 @@ pub fn solidity_render_with_check_option<E: MultiMillerLoop, D: Digest + Clone>(
         + 2 * shuffles;
     tera_ctx.insert("evals", &evals);
 
-    let steps = solidity_codegen_with_proof::<_, D>(
+    let (steps, _) = solidity_codegen_with_proof::<_, D>(
         &verify_circuit_params,
         &vkey,
         instances,
@@ -163,11 +295,13 @@ pub fn solidity_render_with_check_option<E: MultiMillerLoop, D: Digest + Clone>(
         check,
     );
 
-    for (f_in, f_out) in common_template_name {
-        let fd = std::fs::File::create(Path::new(path_out).join(f_out)).unwrap();
+    let mut outputs = HashMap::new();
 
-        tera.render_to(&f_in, &tera_ctx, fd)
+    for (f_in, f_out) in common_template_name {
+        let content = tera
+            .render(&f_in, &tera_ctx)
             .expect("failed to render template");
+        outputs.insert(f_out, content);
     }
 
     for (i, step) in steps.iter().enumerate() {
@@ -176,14 +310,17 @@ pub fn solidity_render_with_check_option<E: MultiMillerLoop, D: Digest + Clone>(
         } else {
             start_step_template_name
         };
-        let fd = std::fs::File::create(Path::new(path_out).join(&step_out_file_name(i))).unwrap();
 
         tera_ctx.insert("step", step);
         tera_ctx.insert("step_index", &(i + 1));
-        tera.render_to(template, &tera_ctx, fd)
+        let content = tera
+            .render(template, &tera_ctx)
             .expect("failed to render template");
+        outputs.insert(step_out_file_name(i), content);
         tera_ctx.remove("step");
     }
+
+    outputs
 }
 
 #[cfg(test)]
@@ -195,6 +332,12 @@ mod tests {
     use crate::circuits::utils::run_circuit_unsafe_full_pass_no_rec;
     use crate::circuits::utils::TranscriptHash;
     use crate::solidity_verifier::codegen::solidity_aux_gen;
+    use crate::solidity_verifier::codegen::solidity_aux_gen_streaming;
+    use crate::solidity_verifier::codegen::solidity_codegen_from_eval_context;
+    use crate::solidity_verifier::codegen::solidity_codegen_with_proof;
+    use crate::solidity_verifier::codegen::solidity_eval_context;
+    use crate::solidity_verifier::codegen::solidity_gas_estimate;
+    use crate::solidity_verifier::codegen::solidity_test_gen;
     use crate::solidity_verifier::solidity_render;
     use halo2_proofs::pairing::bn256::Bn256;
     use halo2_proofs::pairing::bn256::Fr;
@@ -219,14 +362,14 @@ mod tests {
         let verify_circuit_k = 22;
 
         let path = Path::new(path);
-        let (circuit, instances) = SimpleCircuit::<Fr>::random_new_with_instance();
+        let (circuit, target_instances) = SimpleCircuit::<Fr>::random_new_with_instance();
         let (circuit, instances, shadow_instances, _) =
             run_circuit_unsafe_full_pass_no_rec::<Bn256, _>(
                 path,
                 "simple-circuit",
                 target_circuit_k,
                 vec![circuit.clone(), circuit],
-                vec![instances.clone(), instances],
+                vec![target_instances.clone(), target_instances.clone()],
                 vec![],
                 TranscriptHash::Poseidon,
                 vec![[0, 0, 1, 0]],
@@ -244,7 +387,7 @@ mod tests {
             verify_circuit_k,
             vec![circuit],
             vec![vec![instances.clone()]],
-            vec![vec![shadow_instances]],
+            vec![vec![shadow_instances.clone()]],
             aggregator_circuit_hasher,
             vec![],
             vec![],
@@ -283,13 +426,109 @@ mod tests {
             proof.clone(),
         );
 
+        // The foundry test deploys the rendered steps, it lives next to sol/contracts.
+        let test_dir = Path::new("sol/forge-test");
+        DirBuilder::new().recursive(true).create(test_dir).unwrap();
+        let test_file = test_dir.join("AggregatorVerifier.t.sol");
+        let target_instances = vec![target_instances[0].clone(), target_instances[0].clone()];
+        solidity_test_gen::<_, D>(
+            &verifier_params_verifier,
+            &vkey,
+            &instances,
+            &shadow_instances,
+            &target_instances,
+            proof.clone(),
+            |i| format!("AggregatorVerifierStep{}.sol", i + 1),
+            &test_file,
+        );
+
+        let gas =
+            solidity_gas_estimate::<_, D>(&verifier_params_verifier, &vkey, &instances, &proof);
+        assert!(!gas.step_gas.is_empty());
+        assert_eq!(gas.total_gas, gas.step_gas.iter().sum::<u64>());
+
+        let (steps, stats) = solidity_codegen_with_proof::<_, D>(
+            &verifier_params_verifier,
+            &vkey,
+            &instances,
+            proof.clone(),
+            &mut tera::Context::new(),
+            false,
+        );
+        assert_eq!(stats.step_count, steps.len());
+        assert_eq!(stats.step_count, gas.step_gas.len());
+        assert_eq!(
+            stats.total_statements,
+            steps.iter().map(|x| x.lines().count()).sum::<usize>()
+        );
+        assert!(stats.aux_count > 0);
+
+        let test_code = std::fs::read_to_string(&test_file).unwrap();
+        for i in 0..stats.step_count {
+            assert!(test_code.contains(&format!(
+                "steps[{}] = AggregatorVerifierCoreStep(deploy(\"AggregatorVerifierStep{}.sol:AggregatorVerifierCoreStep{}\"));",
+                i,
+                i + 1,
+                i + 1
+            )));
+        }
+        assert!(!test_code.contains(&format!("steps[{}]", stats.step_count)));
+        for (name, len) in [
+            ("proof", proof.len() / 32),
+            ("verify_instance", shadow_instances.len()),
+            ("aux", stats.aux_count),
+            ("target_instance0", target_instances[0].len()),
+            ("target_instance1", target_instances[1].len()),
+        ] {
+            assert!(test_code.contains(&format!(
+                "uint256[] memory {} = new uint256[]({});",
+                name, len
+            )));
+            assert!(test_code.contains(&format!("{}[{}] = 0x", name, len - 1)));
+            assert!(!test_code.contains(&format!("{}[{}] = 0x", name, len)));
+        }
+        assert!(
+            test_code.contains("verifier.verify(proof, verify_instance, aux, target_instance);")
+        );
+
+        assert!(stats.max_temp_index > 0);
+        assert!(stats.transcript_reads > 0 && stats.challenges > 0);
+
+        let c = solidity_eval_context(&verifier_params_verifier, &vkey);
+        for _ in 0..2 {
+            let (reused_steps, reused_stats) = solidity_codegen_from_eval_context::<_, D>(
+                &c,
+                &verifier_params_verifier,
+                &vkey,
+                &instances,
+                proof.clone(),
+                &mut tera::Context::new(),
+                true,
+            );
+            assert_eq!(reused_steps, steps);
+            assert_eq!(reused_stats, stats);
+        }
+
         solidity_aux_gen::<_, D>(
             &verifier_params_verifier,
             &vkey,
             &instances,
-            proof,
+            proof.clone(),
             &path.join(format!("{}.{}.aux.data", "verify-circuit", 0)),
         );
+
+        let mut aux = vec![];
+        solidity_aux_gen_streaming::<_, D>(
+            &verifier_params_verifier,
+            &vkey,
+            &instances,
+            &proof[..],
+            &mut aux,
+        );
+        assert_eq!(
+            aux,
+            load_proof(&path.join(format!("{}.{}.aux.data", "verify-circuit", 0)))
+        );
     }
 
     #[test]
@@ -301,4 +540,28 @@ mod tests {
     fn test_solidity_render_keccak() {
         test_solidity_render::<sha3::Keccak256>(TranscriptHash::Keccak)
     }
+
+    #[test]
+    #[should_panic(expected = "solidity verifier does not support Blake2s transcript")]
+    fn test_solidity_render_blake2s_unsupported() {
+        let (circuit, instances) = SimpleCircuit::<Fr>::default_with_instance();
+        let params = load_or_build_unsafe_params::<Bn256>(8, None);
+        let params_verifier: ParamsVerifier<Bn256> = params.verifier(1).unwrap();
+        let vkey = load_or_build_vkey::<Bn256, _>(&params, &circuit, None);
+
+        // Rejected before the templates are loaded, so the missing template folder is never read.
+        solidity_render::<_, blake2::Blake2s256>(
+            "./output/missing-templates/*",
+            "./output",
+            vec![],
+            "",
+            "",
+            |i| format!("{}", i),
+            TranscriptHash::Blake2s,
+            &params_verifier,
+            &vkey,
+            &instances[0],
+            vec![],
+        );
+    }
 }
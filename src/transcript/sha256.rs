@@ -69,7 +69,8 @@ impl<R: Read, C: CurveAffine, D: Digest + Clone> Transcript<C, Challenge255<C>>
     fn squeeze_challenge(&mut self) -> Challenge255<C> {
         self.state.update(&[SHA_PREFIX_CHALLENGE]);
         let hasher = self.state.clone();
-        let mut result: [u8; 32] = hasher.finalize().as_slice().try_into().unwrap();
+        // digests longer than 256 bits (e.g. sha512) are truncated to 32 bytes
+        let mut result: [u8; 32] = hasher.finalize().as_slice()[..32].try_into().unwrap();
 
         self.state = D::new();
         self.state.update(result);
@@ -171,7 +172,8 @@ impl<W: Write, C: CurveAffine, D: Digest + Clone> Transcript<C, Challenge255<C>>
     fn squeeze_challenge(&mut self) -> Challenge255<C> {
         self.state.update(&[SHA_PREFIX_CHALLENGE]);
         let hasher = self.state.clone();
-        let mut result: [u8; 32] = hasher.finalize().as_slice().try_into().unwrap();
+        // digests longer than 256 bits (e.g. sha512) are truncated to 32 bytes
+        let mut result: [u8; 32] = hasher.finalize().as_slice()[..32].try_into().unwrap();
 
         self.state = D::new();
         self.state.update(result);